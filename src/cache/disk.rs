@@ -0,0 +1,323 @@
+use crate::cache::{CacheStats, TileKey};
+use crate::models::responses::TileResponse;
+use crate::utils::hash::fnv1a;
+use lru::LruCache;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use walkdir::WalkDir;
+
+// Written next to the tiles of each layer/style, records the size and mtime
+// of the raster they were rendered from
+const SOURCE_FILE: &str = ".source";
+
+// Numbers temporary files so concurrent writes of one tile don't mix
+static TMP_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Rendered tiles persisted as {root}/{layer}/{style}/{z}/{x}/{y}.png so they
+/// survive restarts, with non-default render options in a hashed folder
+/// between style and z. Total size is capped, least recently used tiles go first.
+///
+/// The lock only covers the index, files are read and written outside it.
+/// Everything here blocks, so call it from `spawn_blocking` in async code.
+pub struct DiskTileCache {
+    root: PathBuf,
+    inner: Mutex<Inner>,
+    capacity_bytes: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Inner {
+    // Every tile file on disk with its size, in access order
+    files: LruCache<PathBuf, u64>,
+    size_bytes: u64,
+    // Source stamps of the layer/style folders already checked this run
+    checked: HashMap<PathBuf, (u64, u64)>,
+}
+
+/// (size, mtime secs) the same way LayerMetadata records them
+fn source_stamp(key: &TileKey) -> (u64, u64) {
+    let secs = key
+        .last_modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0))
+        .as_secs();
+    (key.size_bytes, secs)
+}
+
+fn read_stamp(path: &Path) -> Option<(u64, u64)> {
+    let content = fs::read_to_string(path).ok()?;
+    let (size, secs) = content.trim().split_once(',')?;
+    Some((size.parse().ok()?, secs.parse().ok()?))
+}
+
+impl DiskTileCache {
+    /// Open (or create) the cache directory and index what is already there
+    pub fn new(root: &Path, capacity_bytes: u64) -> anyhow::Result<Self> {
+        fs::create_dir_all(root)?;
+
+        let mut existing: Vec<(PathBuf, u64, SystemTime)> = WalkDir::new(root)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .filter(|e| e.path().extension().and_then(|s| s.to_str()) == Some("png"))
+            .filter_map(|e| {
+                let meta = e.metadata().ok()?;
+                let mtime = meta.modified().unwrap_or(UNIX_EPOCH);
+                Some((e.into_path(), meta.len(), mtime))
+            })
+            .collect();
+        // Oldest first so the most recently written tiles end up most recently used
+        existing.sort_by_key(|(_, _, mtime)| *mtime);
+
+        let mut inner = Inner {
+            files: LruCache::unbounded(),
+            size_bytes: 0,
+            checked: HashMap::new(),
+        };
+        for (path, size, _) in existing {
+            inner.files.push(path, size);
+            inner.size_bytes += size;
+        }
+        for path in inner.evict_to(capacity_bytes) {
+            let _ = fs::remove_file(path);
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            inner: Mutex::new(inner),
+            capacity_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    fn style_dir(&self, key: &TileKey) -> PathBuf {
+        self.root.join(&key.layer).join(&key.style)
+    }
+
+    fn tile_path(&self, key: &TileKey) -> PathBuf {
+        let mut dir = self.style_dir(key);
+        // XYZ tiles keep the layout they had before other grids existed
+        if key.tms != "WebMercatorQuad" {
+            dir.push(&key.tms);
+        }
+        if !key.params.is_empty() {
+            dir.push(format!("p{:016x}", fnv1a(&[key.params.as_bytes()])));
+        }
+        dir.join(key.z.to_string())
+            .join(key.x.to_string())
+            .join(format!("{}.png", key.y))
+    }
+
+    pub fn get(&self, key: &TileKey) -> Option<TileResponse> {
+        self.invalidate_if_modified(key);
+
+        let path = self.tile_path(key);
+        let indexed = self.inner.lock().unwrap().files.get(&path).is_some();
+        let bytes = indexed.then(|| fs::read(&path).ok()).flatten();
+        if indexed && bytes.is_none() {
+            // Removed since it was indexed, by an eviction or invalidation
+            // racing with its write
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(size) = inner.files.pop(&path) {
+                inner.size_bytes -= size;
+            }
+        }
+        match bytes {
+            Some(bytes) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(TileResponse {
+                    bytes,
+                    content_type: "image/png".into(),
+                })
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: &TileKey, tile: &TileResponse) {
+        // Only PNGs are persisted, the file extension is the content type
+        let size = tile.bytes.len() as u64;
+        if tile.content_type != "image/png" || size > self.capacity_bytes {
+            return;
+        }
+
+        self.invalidate_if_modified(key);
+
+        let path = self.tile_path(key);
+        if let Err(e) = write_atomic(&path, &tile.bytes) {
+            eprintln!("⚠️ Failed to write tile cache {}: {}", path.display(), e);
+            return;
+        }
+
+        let evicted = {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(old) = inner.files.put(path, size) {
+                inner.size_bytes -= old;
+            }
+            inner.size_bytes += size;
+            inner.evict_to(self.capacity_bytes)
+        };
+        for path in evicted {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: inner.files.len(),
+            size_bytes: inner.size_bytes,
+            capacity_bytes: self.capacity_bytes,
+        }
+    }
+
+    /// Drop the tiles of a layer/style folder if they were rendered from a
+    /// different version of the raster, then record the current one
+    fn invalidate_if_modified(&self, key: &TileKey) {
+        let dir = self.style_dir(key);
+        let stamp = source_stamp(key);
+        if self.inner.lock().unwrap().checked.get(&dir) == Some(&stamp) {
+            return;
+        }
+
+        let stamp_path = dir.join(SOURCE_FILE);
+        let stale = read_stamp(&stamp_path) != Some(stamp);
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if stale {
+                let paths: Vec<PathBuf> = inner
+                    .files
+                    .iter()
+                    .filter(|(p, _)| p.starts_with(&dir))
+                    .map(|(p, _)| p.clone())
+                    .collect();
+                for path in paths {
+                    if let Some(size) = inner.files.pop(&path) {
+                        inner.size_bytes -= size;
+                    }
+                }
+            }
+            inner.checked.insert(dir.clone(), stamp);
+        }
+        if stale {
+            let _ = fs::remove_dir_all(&dir);
+            let _ = write_atomic(&stamp_path, format!("{},{}", stamp.0, stamp.1).as_bytes());
+        }
+    }
+}
+
+impl Inner {
+    /// Drop least recently used tiles from the index until it fits, giving
+    /// back their files to delete once the lock is released
+    fn evict_to(&mut self, capacity_bytes: u64) -> Vec<PathBuf> {
+        let mut evicted = Vec::new();
+        while self.size_bytes > capacity_bytes {
+            match self.files.pop_lru() {
+                Some((path, size)) => {
+                    self.size_bytes -= size;
+                    evicted.push(path);
+                }
+                None => break,
+            }
+        }
+        evicted
+    }
+}
+
+/// Write to a temporary file and rename it into place so a crash never
+/// leaves half a tile behind
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension(format!("{}.tmp", TMP_COUNT.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn key(layer: &str, x: u32, mtime: u64) -> TileKey {
+        TileKey {
+            layer: layer.to_string(),
+            style: "viridis".to_string(),
+            tms: "WebMercatorQuad".to_string(),
+            z: 4,
+            x,
+            y: 7,
+            last_modified: UNIX_EPOCH + Duration::from_secs(mtime),
+            size_bytes: 1234,
+            params: String::new(),
+        }
+    }
+
+    fn tile(len: usize) -> TileResponse {
+        TileResponse {
+            bytes: vec![1; len],
+            content_type: "image/png".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_tiles_survive_restart() {
+        let tmp = TempDir::new().unwrap();
+        {
+            let cache = DiskTileCache::new(tmp.path(), 1024 * 1024).unwrap();
+            cache.insert(&key("dem", 0, 1), &tile(100));
+        }
+        assert!(tmp.path().join("dem/viridis/4/0/7.png").exists());
+
+        let cache = DiskTileCache::new(tmp.path(), 1024 * 1024).unwrap();
+        assert_eq!(cache.stats().entries, 1);
+        let hit = cache
+            .get(&key("dem", 0, 1))
+            .expect("tile should be on disk");
+        assert_eq!(hit.bytes.len(), 100);
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[test]
+    fn test_modified_source_invalidates_tiles() {
+        let tmp = TempDir::new().unwrap();
+        let cache = DiskTileCache::new(tmp.path(), 1024 * 1024).unwrap();
+        cache.insert(&key("dem", 0, 1), &tile(100));
+        cache.insert(&key("dem", 1, 1), &tile(100));
+
+        assert!(cache.get(&key("dem", 0, 2)).is_none());
+        assert_eq!(cache.stats().entries, 0);
+        assert!(!tmp.path().join("dem/viridis/4/1/7.png").exists());
+    }
+
+    #[test]
+    fn test_size_cap_evicts_least_recently_used() {
+        let tmp = TempDir::new().unwrap();
+        let cache = DiskTileCache::new(tmp.path(), 250).unwrap();
+        cache.insert(&key("dem", 0, 1), &tile(100));
+        cache.insert(&key("dem", 1, 1), &tile(100));
+        assert!(cache.get(&key("dem", 0, 1)).is_some());
+        cache.insert(&key("dem", 2, 1), &tile(100));
+
+        assert!(cache.stats().size_bytes <= 250);
+        assert!(cache.get(&key("dem", 0, 1)).is_some());
+        assert!(cache.get(&key("dem", 1, 1)).is_none());
+        assert!(!tmp.path().join("dem/viridis/4/1/7.png").exists());
+    }
+}
@@ -0,0 +1,204 @@
+use crate::cache::{CacheStats, TileKey};
+use crate::models::responses::TileResponse;
+use lru::LruCache;
+use std::{
+    collections::HashMap,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
+
+/// Bounded, byte-size-limited LRU of rendered tiles
+pub struct MemoryTileCache {
+    inner: Mutex<Inner>,
+    capacity_bytes: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Inner {
+    tiles: LruCache<TileKey, TileResponse>,
+    size_bytes: u64,
+    // Last (mtime, size) seen per layer, a change drops every tile of that layer
+    layer_sources: HashMap<String, (SystemTime, u64)>,
+}
+
+fn entry_size(key: &TileKey, tile: &TileResponse) -> u64 {
+    (tile.bytes.len()
+        + tile.content_type.len()
+        + key.layer.len()
+        + key.style.len()
+        + key.params.len()) as u64
+}
+
+impl MemoryTileCache {
+    pub fn new(capacity_bytes: u64) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                tiles: LruCache::unbounded(),
+                size_bytes: 0,
+                layer_sources: HashMap::new(),
+            }),
+            capacity_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &TileKey) -> Option<TileResponse> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.invalidate_if_modified(key);
+
+        match inner.tiles.get(key) {
+            Some(tile) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(tile.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: TileKey, tile: TileResponse) {
+        let size = entry_size(&key, &tile);
+        if size > self.capacity_bytes {
+            return; // Would evict everything and still not fit
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.invalidate_if_modified(&key);
+
+        if let Some((old_key, old_tile)) = inner.tiles.push(key, tile) {
+            inner.size_bytes -= entry_size(&old_key, &old_tile);
+        }
+        inner.size_bytes += size;
+
+        while inner.size_bytes > self.capacity_bytes {
+            match inner.tiles.pop_lru() {
+                Some((k, t)) => inner.size_bytes -= entry_size(&k, &t),
+                None => break,
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: inner.tiles.len(),
+            size_bytes: inner.size_bytes,
+            capacity_bytes: self.capacity_bytes,
+        }
+    }
+}
+
+impl Inner {
+    fn invalidate_if_modified(&mut self, key: &TileKey) {
+        let source = (key.last_modified, key.size_bytes);
+        match self.layer_sources.get(&key.layer) {
+            Some(seen) if *seen == source => return,
+            None => {
+                self.layer_sources.insert(key.layer.clone(), source);
+                return;
+            }
+            Some(_) => {}
+        }
+
+        let stale: Vec<TileKey> = self
+            .tiles
+            .iter()
+            .filter(|(k, _)| k.layer == key.layer)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for stale_key in stale {
+            if let Some(tile) = self.tiles.pop(&stale_key) {
+                self.size_bytes -= entry_size(&stale_key, &tile);
+            }
+        }
+        self.layer_sources.insert(key.layer.clone(), source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn key(layer: &str, x: u32, mtime: u64) -> TileKey {
+        TileKey {
+            layer: layer.to_string(),
+            style: "default".to_string(),
+            tms: "WebMercatorQuad".to_string(),
+            z: 3,
+            x,
+            y: 2,
+            last_modified: UNIX_EPOCH + Duration::from_secs(mtime),
+            size_bytes: 1234,
+            params: String::new(),
+        }
+    }
+
+    fn tile(len: usize) -> TileResponse {
+        TileResponse {
+            bytes: vec![0; len],
+            content_type: "image/png".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_hit_and_miss_counters() {
+        let cache = MemoryTileCache::new(1024 * 1024);
+        assert!(cache.get(&key("dem", 0, 1)).is_none());
+        cache.insert(key("dem", 0, 1), tile(100));
+        assert!(cache.get(&key("dem", 0, 1)).is_some());
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.entries, 1);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used_by_size() {
+        let one = entry_size(&key("dem", 0, 1), &tile(1000));
+        let cache = MemoryTileCache::new(one * 2);
+        cache.insert(key("dem", 0, 1), tile(1000));
+        cache.insert(key("dem", 1, 1), tile(1000));
+        // Touch tile 0 so tile 1 is the least recently used
+        assert!(cache.get(&key("dem", 0, 1)).is_some());
+        cache.insert(key("dem", 2, 1), tile(1000));
+
+        assert!(cache.get(&key("dem", 0, 1)).is_some());
+        assert!(cache.get(&key("dem", 1, 1)).is_none());
+        assert!(cache.get(&key("dem", 2, 1)).is_some());
+        assert!(cache.stats().size_bytes <= one * 2);
+    }
+
+    #[test]
+    fn test_oversized_tile_is_not_cached() {
+        let cache = MemoryTileCache::new(10);
+        cache.insert(key("dem", 0, 1), tile(100));
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().size_bytes, 0);
+    }
+
+    #[test]
+    fn test_modified_layer_drops_its_tiles() {
+        let cache = MemoryTileCache::new(1024 * 1024);
+        cache.insert(key("dem", 0, 1), tile(100));
+        cache.insert(key("dem", 1, 1), tile(100));
+        cache.insert(key("sst", 0, 1), tile(100));
+
+        // The dem raster was rewritten
+        assert!(cache.get(&key("dem", 0, 2)).is_none());
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 1);
+        assert!(cache.get(&key("sst", 0, 1)).is_some());
+    }
+}
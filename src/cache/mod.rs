@@ -0,0 +1,38 @@
+pub mod disk;
+pub mod memory;
+pub mod reader;
+
+use serde::Serialize;
+use std::time::SystemTime;
+
+/// Everything that changes the bytes of a rendered tile. The file mtime and
+/// size are part of the key so a rewritten raster never serves stale tiles.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TileKey {
+    pub layer: String,
+    pub style: String,
+    pub tms: String, // Tile matrix set id
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+    pub last_modified: SystemTime,
+    pub size_bytes: u64,
+    pub params: String, // RenderOptions::cache_key of the effective options
+}
+
+/// Hit/miss counters for one cache tier
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub size_bytes: u64,
+    pub capacity_bytes: u64,
+}
+
+/// Stats of every enabled tier, as served on /cache
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TileCacheStats {
+    pub memory: Option<CacheStats>,
+    pub disk: Option<CacheStats>,
+}
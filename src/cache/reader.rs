@@ -0,0 +1,110 @@
+use crate::cache::{TileCacheStats, TileKey, disk::DiskTileCache, memory::MemoryTileCache};
+use crate::models::{
+    layer::Layer, render::RenderOptions, responses::TileResponse, tms::TileMatrixSet,
+};
+use crate::traits::TileReader;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::task;
+
+/// Wraps any TileReader and answers repeated tile requests from the memory
+/// cache, then the disk cache, before rendering
+pub struct CachedTileReader {
+    inner: Arc<dyn TileReader>,
+    memory: Option<MemoryTileCache>,
+    disk: Option<Arc<DiskTileCache>>, // Shared with the blocking threads it runs on
+}
+
+impl CachedTileReader {
+    pub fn new(
+        inner: Arc<dyn TileReader>,
+        memory: Option<MemoryTileCache>,
+        disk: Option<DiskTileCache>,
+    ) -> Self {
+        Self {
+            inner,
+            memory,
+            disk: disk.map(Arc::new),
+        }
+    }
+
+    pub fn stats(&self) -> TileCacheStats {
+        TileCacheStats {
+            memory: self.memory.as_ref().map(|c| c.stats()),
+            disk: self.disk.as_ref().map(|c| c.stats()),
+        }
+    }
+}
+
+#[async_trait]
+impl TileReader for CachedTileReader {
+    async fn list_layers(&self) -> Vec<Layer> {
+        self.inner.list_layers().await
+    }
+
+    async fn get_layer(&self, layer: &str) -> Option<Layer> {
+        self.inner.get_layer(layer).await
+    }
+
+    async fn get_tile(
+        &self,
+        layer: &str,
+        tms: &TileMatrixSet,
+        z: u8,
+        x: u32,
+        y: u32,
+        style: Option<&str>,
+        options: &RenderOptions,
+    ) -> Result<TileResponse, String> {
+        // Unknown layers go straight through so the reader reports the error
+        let Some(layer_obj) = self.inner.get_layer(layer).await else {
+            return self
+                .inner
+                .get_tile(layer, tms, z, x, y, style, options)
+                .await;
+        };
+
+        let (last_modified, size_bytes) = layer_obj.current_source().await;
+        let key = TileKey {
+            layer: layer.to_string(),
+            style: style.unwrap_or(&layer_obj.style).to_string(),
+            tms: tms.id.clone(),
+            z,
+            x,
+            y,
+            last_modified,
+            size_bytes,
+            params: options.merged_over(&layer_obj.render)?.cache_key(),
+        };
+
+        if let Some(tile) = self.memory.as_ref().and_then(|c| c.get(&key)) {
+            return Ok(tile);
+        }
+        if let Some(disk) = &self.disk {
+            let (disk, disk_key) = (disk.clone(), key.clone());
+            let cached = task::spawn_blocking(move || disk.get(&disk_key))
+                .await
+                .ok()
+                .flatten();
+            if let Some(tile) = cached {
+                if let Some(memory) = &self.memory {
+                    memory.insert(key, tile.clone());
+                }
+                return Ok(tile);
+            }
+        }
+
+        let tile = self
+            .inner
+            .get_tile(layer, tms, z, x, y, style, options)
+            .await?;
+        if let Some(disk) = &self.disk {
+            let (disk, disk_key, disk_tile) = (disk.clone(), key.clone(), tile.clone());
+            let _ = task::spawn_blocking(move || disk.insert(&disk_key, &disk_tile)).await;
+        }
+        if let Some(memory) = &self.memory {
+            memory.insert(key, tile.clone());
+        }
+        Ok(tile)
+    }
+}
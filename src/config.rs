@@ -6,6 +6,33 @@ pub enum Source {
     S3 { bucket: String, prefix: String },
 }
 
+/// Connection settings for the S3 source. Anything left as `None` falls back
+/// to GDAL's own lookup (AWS_* environment variables, ~/.aws/credentials).
+#[derive(Debug, Clone)]
+pub struct S3Options {
+    pub endpoint: Option<String>, // e.g. "localhost:9000" for MinIO, None for AWS
+    pub region: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub use_https: bool,
+    pub virtual_hosting: bool, // bucket.endpoint/key vs endpoint/bucket/key
+    pub no_sign_request: bool, // anonymous access to public buckets
+}
+
+impl Default for S3Options {
+    fn default() -> Self {
+        S3Options {
+            endpoint: None,
+            region: None,
+            access_key_id: None,
+            secret_access_key: None,
+            use_https: true,
+            virtual_hosting: true,
+            no_sign_request: false,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub source: Option<Source>,
@@ -15,6 +42,13 @@ pub struct Config {
     pub tile_size_y: u32,
     pub port: u16,
     pub default_raster_band: usize,
+    pub s3: S3Options,
+    pub tile_cache_bytes: u64, // In-memory tile cache size, 0 disables it
+    pub tile_cache_dir: Option<PathBuf>, // Persist rendered tiles here if set
+    pub tile_cache_dir_bytes: u64,
+    pub cache_control_max_age: u32, // Seconds clients/CDNs may reuse a tile, 0 = revalidate
+    pub attribution: Option<String>, // Credit line handed to web clients in TileJSON
+    pub tile_matrix_set_dir: Option<PathBuf>, // Extra OGC TMS JSON definitions
 }
 
 impl Default for Config {
@@ -28,6 +62,13 @@ impl Default for Config {
             tile_size_y: 256,
             port: 8000,
             default_raster_band: 1,
+            s3: S3Options::default(),
+            tile_cache_bytes: 256 * 1024 * 1024,
+            tile_cache_dir: None,
+            tile_cache_dir_bytes: 1024 * 1024 * 1024,
+            cache_control_max_age: 3600,
+            attribution: None,
+            tile_matrix_set_dir: None,
         }
     }
 }
@@ -59,4 +100,16 @@ impl Config {
         // Return the default port
         Self::default().port
     }
+
+    pub fn default_tile_cache_mb() -> u64 {
+        Self::default().tile_cache_bytes / 1024 / 1024
+    }
+
+    pub fn default_tile_cache_dir_mb() -> u64 {
+        Self::default().tile_cache_dir_bytes / 1024 / 1024
+    }
+
+    pub fn default_cache_control_max_age() -> u32 {
+        Self::default().cache_control_max_age
+    }
 }
@@ -0,0 +1,174 @@
+use crate::utils::hash::fnv1a;
+use axum::http::{HeaderMap, HeaderValue, header};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Strong ETag for a rendered tile, from the raster it was rendered from
+/// (path + mtime + size) and everything else that changes the output
+pub fn tile_etag(path: &str, last_modified: SystemTime, size_bytes: u64, params: &str) -> String {
+    let mtime = last_modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0));
+    let hash = fnv1a(&[
+        path.as_bytes(),
+        &mtime.as_secs().to_le_bytes(),
+        &mtime.subsec_nanos().to_le_bytes(),
+        &size_bytes.to_le_bytes(),
+        params.as_bytes(),
+    ]);
+    format!("\"{:016x}\"", hash)
+}
+
+/// True if the client's cached copy is still good (RFC 9110 §13.2.2):
+/// If-None-Match wins over If-Modified-Since when both are sent
+pub fn is_not_modified(request: &HeaderMap, etag: &str, last_modified: SystemTime) -> bool {
+    if let Some(if_none_match) = request.get(header::IF_NONE_MATCH) {
+        let Ok(value) = if_none_match.to_str() else {
+            return false;
+        };
+        return value.split(',').map(str::trim).any(|candidate| {
+            // Weak comparison is what GET conditionals use
+            candidate == "*" || candidate.trim_start_matches("W/") == etag
+        });
+    }
+
+    // The epoch stands for an unknown time (S3 objects without a
+    // Last-Modified), which can't tell whether anything changed
+    if let Some(since) = request
+        .get(header::IF_MODIFIED_SINCE)
+        .filter(|_| last_modified != UNIX_EPOCH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+    {
+        // HTTP dates have one second resolution
+        let modified = httpdate::HttpDate::from(last_modified);
+        return modified <= httpdate::HttpDate::from(since);
+    }
+
+    false
+}
+
+/// The validator and freshness headers sent with both 200 and 304 responses
+pub fn cache_headers(etag: &str, last_modified: SystemTime, max_age: u32) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(v) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, v);
+    }
+    if let Some(v) = (last_modified != UNIX_EPOCH)
+        .then(|| HeaderValue::from_str(&httpdate::fmt_http_date(last_modified)).ok())
+        .flatten()
+    {
+        headers.insert(header::LAST_MODIFIED, v);
+    }
+    let cache_control = if max_age == 0 {
+        "no-cache".to_string()
+    } else {
+        format!("public, max-age={}", max_age)
+    };
+    if let Ok(v) = HeaderValue::from_str(&cache_control) {
+        headers.insert(header::CACHE_CONTROL, v);
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mtime() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    fn request(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_etag_changes_with_inputs() {
+        let a = tile_etag("/data/dem.tif", mtime(), 10, "default/1/0/0");
+        assert_eq!(a, tile_etag("/data/dem.tif", mtime(), 10, "default/1/0/0"));
+        assert!(a.starts_with('"') && a.ends_with('"'));
+        assert_ne!(a, tile_etag("/data/dem.tif", mtime(), 11, "default/1/0/0"));
+        assert_ne!(a, tile_etag("/data/dem.tif", mtime(), 10, "viridis/1/0/0"));
+        assert_ne!(
+            a,
+            tile_etag(
+                "/data/dem.tif",
+                mtime() + Duration::from_secs(1),
+                10,
+                "default/1/0/0"
+            )
+        );
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let etag = tile_etag("/data/dem.tif", mtime(), 10, "");
+        let list = format!("\"nope\", W/{}", etag);
+        assert!(is_not_modified(
+            &request(header::IF_NONE_MATCH, &etag),
+            &etag,
+            mtime()
+        ));
+        assert!(is_not_modified(
+            &request(header::IF_NONE_MATCH, &list),
+            &etag,
+            mtime()
+        ));
+        assert!(!is_not_modified(
+            &request(header::IF_NONE_MATCH, "\"nope\""),
+            &etag,
+            mtime()
+        ));
+    }
+
+    #[test]
+    fn test_if_modified_since() {
+        let etag = tile_etag("/data/dem.tif", mtime(), 10, "");
+        let same = httpdate::fmt_http_date(mtime());
+        let before = httpdate::fmt_http_date(mtime() - Duration::from_secs(60));
+        assert!(is_not_modified(
+            &request(header::IF_MODIFIED_SINCE, &same),
+            &etag,
+            mtime() + Duration::from_millis(500)
+        ));
+        assert!(!is_not_modified(
+            &request(header::IF_MODIFIED_SINCE, &before),
+            &etag,
+            mtime()
+        ));
+
+        // An unknown time is never sent, and never taken as unmodified
+        assert!(!is_not_modified(
+            &request(header::IF_MODIFIED_SINCE, &same),
+            &etag,
+            UNIX_EPOCH
+        ));
+        assert!(!cache_headers(&etag, UNIX_EPOCH, 600).contains_key(header::LAST_MODIFIED));
+    }
+
+    #[test]
+    fn test_if_none_match_takes_precedence() {
+        let etag = tile_etag("/data/dem.tif", mtime(), 10, "");
+        let mut headers = request(header::IF_NONE_MATCH, "\"other\"");
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_str(&httpdate::fmt_http_date(mtime())).unwrap(),
+        );
+        assert!(!is_not_modified(&headers, &etag, mtime()));
+    }
+
+    #[test]
+    fn test_cache_control_max_age() {
+        let headers = cache_headers("\"abc\"", mtime(), 600);
+        assert_eq!(headers[header::CACHE_CONTROL], "public, max-age=600");
+        assert_eq!(headers[header::ETAG], "\"abc\"");
+        assert_eq!(
+            headers[header::LAST_MODIFIED],
+            "Tue, 14 Nov 2023 22:13:20 GMT"
+        );
+        let headers = cache_headers("\"abc\"", mtime(), 0);
+        assert_eq!(headers[header::CACHE_CONTROL], "no-cache");
+    }
+}
@@ -0,0 +1,164 @@
+use crate::Config;
+use crate::endpoints::caching::{cache_headers, is_not_modified, tile_etag};
+use crate::models::tms::TileMatrixSets;
+use crate::reader::contours::{ContourOptions, default_interval};
+use crate::traits::TileReader;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// `?interval=10&base=0&band=1&tms=WebMercatorQuad`, all optional. The
+/// interval defaults to a round number giving about ten lines over the
+/// layer's range, which is that of band 1, so other bands need one.
+#[derive(Debug, Default, Deserialize)]
+pub struct ContourQuery {
+    interval: Option<f64>,
+    base: Option<f64>,
+    band: Option<usize>,
+    tms: Option<String>,
+}
+
+impl ContourQuery {
+    fn options(&self, min_value: f32, max_value: f32) -> Result<ContourOptions, String> {
+        if let Some(interval) = self.interval.filter(|i| !(i.is_finite() && *i > 0.0)) {
+            return Err(format!("interval must be positive, got {}", interval));
+        }
+        if let Some(base) = self.base.filter(|b| !b.is_finite()) {
+            return Err(format!("base must be a number, got {}", base));
+        }
+        let band = self.band.unwrap_or(1);
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None if band == 1 => default_interval(min_value as f64, max_value as f64),
+            None => return Err(format!("Contours of band {} need an interval", band)),
+        };
+        Ok(ContourOptions {
+            band,
+            interval,
+            base: self.base.unwrap_or(0.0),
+        })
+    }
+}
+
+/// Contour lines of a DEM as vector tiles:
+/// /contours/{layer}/{z}/{x}/{y}.mvt?interval=
+pub async fn contours_handler(
+    Path((layer, z, x, file)): Path<(String, u8, u32, String)>,
+    State(reader): State<Arc<dyn TileReader>>,
+    State(config): State<Arc<Config>>,
+    State(tile_matrix_sets): State<Arc<TileMatrixSets>>,
+    Query(query): Query<ContourQuery>,
+    request_headers: HeaderMap,
+) -> Response {
+    let Some(y) = file
+        .strip_suffix(".mvt")
+        .and_then(|y| y.parse::<u32>().ok())
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            "Contours are served as {z}/{x}/{y}.mvt",
+        )
+            .into_response();
+    };
+    let tms_id = query.tms.as_deref().unwrap_or("WebMercatorQuad");
+    let Some(tms) = tile_matrix_sets.get(tms_id) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Unknown tile matrix set '{}'", tms_id),
+        )
+            .into_response();
+    };
+    let Some(layer_obj) = reader.get_layer(&layer).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Layer not found: '{}'", layer),
+        )
+            .into_response();
+    };
+    if tms.tile_bounds(z, x, y).is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Tile {}/{}/{} is outside {}", z, x, y, tms.id),
+        )
+            .into_response();
+    }
+    let options = match query.options(layer_obj.min_value, layer_obj.max_value) {
+        Ok(options) => options,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let (last_modified, size_bytes) = layer_obj.current_source().await;
+    let params = format!(
+        "contours/{}/{}/{}/{}?band={}&interval={}&base={}",
+        tms.id, z, x, y, options.band, options.interval, options.base
+    );
+    let etag = tile_etag(
+        &layer_obj.path.to_string_lossy(),
+        last_modified,
+        size_bytes,
+        &params,
+    );
+    let headers = cache_headers(&etag, last_modified, config.cache_control_max_age);
+    if is_not_modified(&request_headers, &etag, last_modified) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    match reader.get_contours(&layer, tms, z, x, y, options).await {
+        Ok(tile) => (
+            headers,
+            [(header::CONTENT_TYPE, tile.content_type)],
+            tile.bytes,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contour_options() {
+        let query = ContourQuery::default();
+        assert_eq!(
+            query.options(0.0, 1000.0),
+            Ok(ContourOptions {
+                band: 1,
+                interval: 100.0,
+                base: 0.0
+            })
+        );
+
+        let query = ContourQuery {
+            interval: Some(25.0),
+            base: Some(5.0),
+            band: Some(2),
+            tms: None,
+        };
+        let options = query.options(0.0, 1000.0).unwrap();
+        assert_eq!(
+            (options.band, options.interval, options.base),
+            (2, 25.0, 5.0)
+        );
+
+        for interval in [0.0, -10.0, f64::NAN] {
+            let query = ContourQuery {
+                interval: Some(interval),
+                ..Default::default()
+            };
+            assert!(query.options(0.0, 1000.0).is_err());
+        }
+
+        // The layer's range is band 1's
+        let query = ContourQuery {
+            band: Some(2),
+            ..Default::default()
+        };
+        assert!(query.options(0.0, 1000.0).is_err());
+    }
+}
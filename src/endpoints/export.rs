@@ -0,0 +1,147 @@
+use crate::endpoints::point::parse_crs;
+use crate::models::{geometry::GeometryExtent, render::Resampling};
+use crate::reader::export::{ExportFormat, ExportOptions};
+use crate::traits::TileReader;
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio_util::io::ReaderStream;
+
+/// `?bbox=minx,miny,maxx,maxy&crs=EPSG:4326&format=cog`, optionally with
+/// `target_crs`, `resolution` (target CRS units) and `resampling`
+#[derive(Debug, Default, Deserialize)]
+pub struct ExportQuery {
+    bbox: Option<String>,
+    crs: Option<String>,
+    target_crs: Option<String>,
+    resolution: Option<f64>,
+    resampling: Option<String>,
+    format: Option<String>,
+}
+
+impl ExportQuery {
+    fn options(&self) -> Result<ExportOptions, String> {
+        let bbox: GeometryExtent = self.bbox.as_deref().ok_or("Missing bbox")?.parse()?;
+        if let Some(resolution) = self.resolution.filter(|r| !(r.is_finite() && *r > 0.0)) {
+            return Err(format!("resolution must be positive, got {}", resolution));
+        }
+        Ok(ExportOptions {
+            bbox,
+            crs: parse_crs(self.crs.as_deref())?,
+            target_crs: self
+                .target_crs
+                .as_deref()
+                .map(|crs| parse_crs(Some(crs)))
+                .transpose()?,
+            resolution: self.resolution,
+            resampling: self
+                .resampling
+                .as_deref()
+                .map(str::parse::<Resampling>)
+                .transpose()?,
+            format: match self.format.as_deref() {
+                Some(format) => format.parse()?,
+                None => ExportFormat::GTiff,
+            },
+        })
+    }
+}
+
+/// The source raster cut to a bbox: /export/{layer}?bbox=&crs=&format=
+pub async fn export_handler(
+    Path(layer): Path<String>,
+    State(reader): State<Arc<dyn TileReader>>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let options = match query.options() {
+        Ok(options) => options,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    if reader.get_layer(&layer).await.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Layer not found: '{}'", layer),
+        )
+            .into_response();
+    }
+
+    let export = match reader.get_export(&layer, options).await {
+        Ok(export) => export,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let length = match export.file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    (
+        [
+            (header::CONTENT_TYPE, export.content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.tif\"", layer),
+            ),
+            (header::CONTENT_LENGTH, length.to_string()),
+        ],
+        // Streamed, exports can be far bigger than a tile
+        Body::from_stream(ReaderStream::new(export.file)),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pairs: &[(&str, &str)]) -> ExportQuery {
+        let query = pairs
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+        Query::<ExportQuery>::try_from_uri(&format!("/export/a?{}", query).parse().unwrap())
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_export_query() {
+        let options = query(&[("bbox", "147,-43,148,-42")]).options().unwrap();
+        assert_eq!(
+            options.bbox,
+            GeometryExtent::from((147.0, -43.0, 148.0, -42.0))
+        );
+        assert_eq!(options.crs, 4326);
+        assert_eq!(options.target_crs, None);
+        assert_eq!(options.format, ExportFormat::GTiff);
+
+        let options = query(&[
+            ("bbox", "0,0,1000,1000"),
+            ("crs", "EPSG:3857"),
+            ("target_crs", "EPSG:3031"),
+            ("resolution", "30"),
+            ("resampling", "cubic"),
+            ("format", "COG"),
+        ])
+        .options()
+        .unwrap();
+        assert_eq!((options.crs, options.target_crs), (3857, Some(3031)));
+        assert_eq!(options.resolution, Some(30.0));
+        assert_eq!(options.resampling, Some(Resampling::Cubic));
+        assert_eq!(options.format, ExportFormat::Cog);
+
+        for bad in [
+            vec![],
+            vec![("bbox", "1,2,3")],
+            vec![("bbox", "0,0,1,1"), ("format", "png")],
+            vec![("bbox", "0,0,1,1"), ("resolution", "-5")],
+            vec![("bbox", "0,0,1,1"), ("resampling", "blurry")],
+        ] {
+            assert!(query(&bad).options().is_err());
+        }
+    }
+}
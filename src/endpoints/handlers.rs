@@ -1,11 +1,21 @@
-use crate::endpoints::map::INDEX_HTML;
-use crate::models::layer::{Layer, LayerGeometry};
+use crate::Config;
+use crate::cache::reader::CachedTileReader;
+use crate::endpoints::{
+    caching::{cache_headers, is_not_modified, tile_etag},
+    map::INDEX_HTML,
+};
+use crate::models::{
+    layer::{Layer, LayerGeometry},
+    render::{RenderOptions, RenderQuery},
+    style::{ColourStop, Interpolation},
+    tms::{TileMatrixSet, TileMatrixSets},
+};
 use crate::traits::TileReader;
 use axum::{
     Json,
-    extract::{Path, State},
-    http::StatusCode,
-    response::{Html, IntoResponse},
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
 };
 use serde::Serialize;
 use std::collections::HashMap;
@@ -16,6 +26,15 @@ struct LayerResponse {
     layer: String,
     style: String,
     geometry: HashMap<i32, LayerGeometry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    legend: Option<Legend>,
+}
+
+/// The style.txt colour stops and their labels, for drawing a legend
+#[derive(Serialize)]
+struct Legend {
+    interpolation: Interpolation,
+    stops: Vec<ColourStop>,
 }
 
 pub async fn webmap_handler() -> impl IntoResponse {
@@ -25,12 +44,90 @@ pub async fn webmap_handler() -> impl IntoResponse {
 pub async fn tile_handler(
     Path((layer, z, x, y)): Path<(String, u8, u32, u32)>,
     State(reader): State<Arc<dyn TileReader>>,
-) -> impl IntoResponse {
-    match reader.get_tile(&layer, z, x, y, None).await {
-        Ok(tile) => axum::http::Response::builder()
-            .header("Content-Type", tile.content_type)
-            .body(axum::body::Body::from(tile.bytes))
-            .unwrap()
+    State(config): State<Arc<Config>>,
+    State(tile_matrix_sets): State<Arc<TileMatrixSets>>,
+    Query(query): Query<RenderQuery>,
+    request_headers: HeaderMap,
+) -> Response {
+    let options = match RenderOptions::from_query(&query) {
+        Ok(options) => options,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let tms_id = query.tms.as_deref().unwrap_or("WebMercatorQuad");
+    let Some(tms) = tile_matrix_sets.get(tms_id) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Unknown tile matrix set '{}'", tms_id),
+        )
+            .into_response();
+    };
+    let Some(layer_obj) = reader.get_layer(&layer).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Layer not found: '{}'", layer),
+        )
+            .into_response();
+    };
+    render_tile(
+        &reader,
+        &config,
+        &layer_obj,
+        tms,
+        (z, x, y),
+        &options,
+        &request_headers,
+    )
+    .await
+}
+
+/// Render (or revalidate) one tile of a layer that is known to exist.
+/// Shared by every protocol that serves tiles.
+pub(crate) async fn render_tile(
+    reader: &Arc<dyn TileReader>,
+    config: &Config,
+    layer_obj: &Layer,
+    tms: &TileMatrixSet,
+    (z, x, y): (u8, u32, u32),
+    options: &RenderOptions,
+    request_headers: &HeaderMap,
+) -> Response {
+    // Validators come from the raster itself, so they can be checked before
+    // doing any rendering
+    let merged = match options.merged_over(&layer_obj.render) {
+        Ok(merged) => merged,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let (last_modified, size_bytes) = layer_obj.current_source().await;
+    let params = format!(
+        "{}/{}/{}/{}/{}?{}",
+        layer_obj.style,
+        tms.id,
+        z,
+        x,
+        y,
+        merged.cache_key()
+    );
+    let etag = tile_etag(
+        &layer_obj.path.to_string_lossy(),
+        last_modified,
+        size_bytes,
+        &params,
+    );
+    let headers = cache_headers(&etag, last_modified, config.cache_control_max_age);
+
+    if is_not_modified(request_headers, &etag, last_modified) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    match reader
+        .get_tile(&layer_obj.layer, tms, z, x, y, None, options)
+        .await
+    {
+        Ok(tile) => (
+            headers,
+            [(axum::http::header::CONTENT_TYPE, tile.content_type)],
+            tile.bytes,
+        )
             .into_response(),
         Err(e) => (StatusCode::NOT_FOUND, e).into_response(),
     }
@@ -46,6 +143,10 @@ pub async fn get_all_layers(State(reader): State<Arc<dyn TileReader>>) -> impl I
                 layer: layer.layer.clone(),
                 style: layer.style.clone(),
                 geometry: layer.cached_geometry, // Assumes this has already been populated at load
+                legend: (!layer.colour_stops.is_empty()).then_some(Legend {
+                    interpolation: layer.interpolation,
+                    stops: layer.colour_stops,
+                }),
             });
         }
     }
@@ -60,3 +161,12 @@ pub async fn get_all_layers(State(reader): State<Arc<dyn TileReader>>) -> impl I
 
     (StatusCode::OK, Json(all_layers))
 }
+
+pub async fn cache_stats_handler(
+    State(tile_cache): State<Option<Arc<CachedTileReader>>>,
+) -> impl IntoResponse {
+    match tile_cache {
+        Some(cache) => (StatusCode::OK, Json(cache.stats())).into_response(),
+        None => (StatusCode::NOT_FOUND, "Tile cache is disabled").into_response(),
+    }
+}
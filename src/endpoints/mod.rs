@@ -1,3 +1,15 @@
+pub mod caching;
+pub mod contours;
+pub mod export;
 pub mod handlers;
 pub mod map;
+pub mod ogc;
+pub mod point;
+pub mod preview;
+pub mod profile;
 pub mod server;
+pub mod tilejson;
+pub mod urls;
+pub mod wms;
+pub mod wmts;
+pub mod zonal;
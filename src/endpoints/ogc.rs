@@ -0,0 +1,544 @@
+//! OGC API - Tiles (https://docs.ogc.org/is/20-057/20-057.html). Every
+//! resource is JSON by default and HTML for browsers (`Accept: text/html` or
+//! `?f=html`).
+
+use crate::Config;
+use crate::endpoints::{handlers::render_tile, urls::base_url};
+use crate::models::{
+    layer::Layer,
+    render::{RenderOptions, RenderQuery},
+    tms::{TileMatrixSet, TileMatrixSets},
+};
+use crate::traits::TileReader;
+use crate::utils::xml::escape;
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::fmt::Write;
+use std::sync::Arc;
+
+const CRS84: &str = "http://www.opengis.net/def/crs/OGC/1.3/CRS84";
+
+const CONFORMANCE: &[&str] = &[
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/core",
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/landing-page",
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/json",
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/html",
+    "http://www.opengis.net/spec/ogcapi-common-2/1.0/conf/collections",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/core",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/tileset",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/tilesets-list",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/geodata-tilesets",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/png",
+    "http://www.opengis.net/spec/tms/2.0/conf/tilematrixset",
+    "http://www.opengis.net/spec/tms/2.0/conf/json-tilematrixset",
+];
+
+#[derive(Deserialize, Default)]
+pub struct FormatQuery {
+    f: Option<String>,
+}
+
+/// JSON or HTML, from `?f=` first and then the Accept header
+fn wants_html(query: &FormatQuery, headers: &HeaderMap) -> bool {
+    match query.f.as_deref() {
+        Some(f) => f.eq_ignore_ascii_case("html"),
+        None => headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html")),
+    }
+}
+
+/// OGC API errors are a small JSON document
+fn error(status: StatusCode, description: String) -> Response {
+    let code = status.canonical_reason().unwrap_or_default();
+    (
+        status,
+        Json(json!({ "code": code, "description": description })),
+    )
+        .into_response()
+}
+
+fn link(href: String, rel: &str, media_type: &str, title: &str) -> Value {
+    json!({ "href": href, "rel": rel, "type": media_type, "title": title })
+}
+
+/// The self (JSON) and alternate (HTML) links every resource carries
+fn self_links(url: &str) -> Vec<Value> {
+    vec![
+        link(url.to_string(), "self", "application/json", "This document"),
+        link(
+            format!("{}?f=html", url),
+            "alternate",
+            "text/html",
+            "This document as HTML",
+        ),
+    ]
+}
+
+/// Send `doc` as JSON, or as a simple HTML page of its links and lists
+fn respond(doc: Value, title: &str, html: bool) -> Response {
+    if !html {
+        return Json(doc).into_response();
+    }
+
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n",
+        escape(title)
+    );
+    let anchor = |href: &str, text: &str| {
+        format!(
+            "<a href=\"{}\">{}</a>",
+            escape(&html_href(href)),
+            escape(text)
+        )
+    };
+
+    if let Some(links) = doc["links"].as_array() {
+        page.push_str("<h2>Links</h2>\n<ul>\n");
+        for l in links.iter().filter(|l| l["rel"] != "self") {
+            let href = l["href"].as_str().unwrap_or_default();
+            let text = l["title"].as_str().unwrap_or(href);
+            let _ = writeln!(page, "<li>{} ({})</li>", anchor(href, text), l["rel"]);
+        }
+        page.push_str("</ul>\n");
+    }
+    for key in ["collections", "tilesets", "tileMatrixSets"] {
+        let Some(items) = doc[key].as_array() else {
+            continue;
+        };
+        let _ = writeln!(page, "<h2>{}</h2>\n<ul>", key);
+        for item in items {
+            let name = item["title"].as_str().or(item["id"].as_str());
+            let href = item["links"]
+                .as_array()
+                .and_then(|links| links.iter().find(|l| l["rel"] == "self"))
+                .and_then(|l| l["href"].as_str());
+            if let (Some(name), Some(href)) = (name, href) {
+                let _ = writeln!(page, "<li>{}</li>", anchor(href, name));
+            }
+        }
+        page.push_str("</ul>\n");
+    }
+    let _ = write!(
+        page,
+        "<h2>JSON</h2>\n<pre>{}</pre>\n</body>\n</html>\n",
+        escape(&serde_json::to_string_pretty(&doc).unwrap_or_default())
+    );
+    Html(page).into_response()
+}
+
+/// Links between HTML pages stay HTML, templated and image links are left alone
+fn html_href(href: &str) -> String {
+    if href.contains('{') || href.contains("?f=") || href.ends_with(".png") {
+        href.to_string()
+    } else {
+        format!("{}?f=html", href)
+    }
+}
+
+/// Landing page: /
+pub async fn landing_handler(Query(query): Query<FormatQuery>, headers: HeaderMap) -> Response {
+    let base = base_url(&headers);
+    let mut links = self_links(&format!("{}/", base));
+    links.extend([
+        link(
+            format!("{}/conformance", base),
+            "http://www.opengis.net/def/rel/ogc/1.0/conformance",
+            "application/json",
+            "Conformance classes",
+        ),
+        link(
+            format!("{}/collections", base),
+            "http://www.opengis.net/def/rel/ogc/1.0/data",
+            "application/json",
+            "Layers",
+        ),
+        link(
+            format!("{}/tileMatrixSets", base),
+            "http://www.opengis.net/def/rel/ogc/1.0/tiling-schemes",
+            "application/json",
+            "Tile matrix sets",
+        ),
+    ]);
+    let doc = json!({
+        "title": "TileYolo",
+        "description": "GeoTIFF layers as OGC API - Tiles",
+        "links": links,
+    });
+    respond(doc, "TileYolo", wants_html(&query, &headers))
+}
+
+/// Conformance declaration: /conformance
+pub async fn conformance_handler(Query(query): Query<FormatQuery>, headers: HeaderMap) -> Response {
+    let url = format!("{}/conformance", base_url(&headers));
+    let doc = json!({ "conformsTo": CONFORMANCE, "links": self_links(&url) });
+    respond(doc, "Conformance", wants_html(&query, &headers))
+}
+
+/// All layers: /collections
+pub async fn collections_handler(
+    State(reader): State<Arc<dyn TileReader>>,
+    Query(query): Query<FormatQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let base = base_url(&headers);
+    let mut layers = reader.list_layers().await;
+    layers.sort_by_key(|l| l.layer.to_lowercase());
+
+    let doc = json!({
+        "links": self_links(&format!("{}/collections", base)),
+        "collections": layers.iter().map(|l| collection(l, &base)).collect::<Vec<_>>(),
+    });
+    respond(doc, "Layers", wants_html(&query, &headers))
+}
+
+/// One layer: /collections/{layer}
+pub async fn collection_handler(
+    Path(layer): Path<String>,
+    State(reader): State<Arc<dyn TileReader>>,
+    Query(query): Query<FormatQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(layer_obj) = reader.get_layer(&layer).await else {
+        return not_found(&layer);
+    };
+    let doc = collection(&layer_obj, &base_url(&headers));
+    respond(doc, &layer_obj.layer, wants_html(&query, &headers))
+}
+
+/// The tilesets of a layer, one per tile matrix set: /collections/{layer}/map/tiles
+pub async fn tilesets_handler(
+    Path(layer): Path<String>,
+    State(reader): State<Arc<dyn TileReader>>,
+    State(tile_matrix_sets): State<Arc<TileMatrixSets>>,
+    Query(query): Query<FormatQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(layer_obj) = reader.get_layer(&layer).await else {
+        return not_found(&layer);
+    };
+    let base = base_url(&headers);
+    let url = format!("{}/collections/{}/map/tiles", base, layer_obj.layer);
+    let tilesets: Vec<Value> = tile_matrix_sets
+        .iter()
+        .map(|tms| {
+            let mut summary = tileset_summary(&layer_obj, tms, &base);
+            summary["links"] = json!([
+                link(
+                    format!("{}/{}", url, tms.id),
+                    "self",
+                    "application/json",
+                    &tms.id
+                ),
+                tiling_scheme_link(tms, &base),
+            ]);
+            summary
+        })
+        .collect();
+    let doc = json!({ "links": self_links(&url), "tilesets": tilesets });
+    respond(doc, &layer_obj.layer, wants_html(&query, &headers))
+}
+
+/// A tileset: /collections/{layer}/map/tiles/{tileMatrixSetId}
+pub async fn tileset_handler(
+    Path((layer, tms)): Path<(String, String)>,
+    State(reader): State<Arc<dyn TileReader>>,
+    State(tile_matrix_sets): State<Arc<TileMatrixSets>>,
+    Query(query): Query<FormatQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(layer_obj) = reader.get_layer(&layer).await else {
+        return not_found(&layer);
+    };
+    let Some(tms) = tile_matrix_sets.get(&tms) else {
+        return unknown_tms(&tms);
+    };
+    let doc = tileset(&layer_obj, tms, &base_url(&headers));
+    respond(doc, &layer_obj.layer, wants_html(&query, &headers))
+}
+
+/// A map tile: /collections/{layer}/map/tiles/{tileMatrixSetId}/{tileMatrix}/{tileRow}/{tileCol}
+pub async fn tile_handler(
+    Path((layer, tms, matrix, row, col)): Path<(String, String, String, u32, u32)>,
+    State(reader): State<Arc<dyn TileReader>>,
+    State(config): State<Arc<Config>>,
+    State(tile_matrix_sets): State<Arc<TileMatrixSets>>,
+    Query(query): Query<RenderQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(layer_obj) = reader.get_layer(&layer).await else {
+        return not_found(&layer);
+    };
+    let Some(tms) = tile_matrix_sets.get(&tms) else {
+        return unknown_tms(&tms);
+    };
+    let Some(z) = tms
+        .matrix_index(&matrix)
+        .filter(|z| tms.tile_bounds(*z, col, row).is_some())
+    else {
+        return error(
+            StatusCode::NOT_FOUND,
+            format!("Tile {}/{}/{} is outside {}", matrix, row, col, tms.id),
+        );
+    };
+    let options = match RenderOptions::from_query(&query) {
+        Ok(options) => options,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
+    render_tile(
+        &reader,
+        &config,
+        &layer_obj,
+        tms,
+        (z, col, row),
+        &options,
+        &headers,
+    )
+    .await
+}
+
+/// The tile matrix sets on offer: /tileMatrixSets
+pub async fn tile_matrix_sets_handler(
+    State(tile_matrix_sets): State<Arc<TileMatrixSets>>,
+    Query(query): Query<FormatQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let url = format!("{}/tileMatrixSets", base_url(&headers));
+    let sets: Vec<Value> = tile_matrix_sets
+        .iter()
+        .map(|tms| {
+            json!({
+                "id": tms.id,
+                "title": tms.title,
+                "uri": tms.uri,
+                "crs": tms.crs.uri(),
+                "links": [link(format!("{}/{}", url, tms.id), "self", "application/json", &tms.id)],
+            })
+        })
+        .collect();
+    let doc = json!({ "links": self_links(&url), "tileMatrixSets": sets });
+    respond(doc, "Tile matrix sets", wants_html(&query, &headers))
+}
+
+/// A tile matrix set definition in OGC TMS 2.0 JSON: /tileMatrixSets/{id}
+pub async fn tile_matrix_set_handler(
+    Path(id): Path<String>,
+    State(tile_matrix_sets): State<Arc<TileMatrixSets>>,
+    Query(query): Query<FormatQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(tms) = tile_matrix_sets.get(&id) else {
+        return unknown_tms(&id);
+    };
+    let mut doc = serde_json::to_value(tms).unwrap_or_default();
+    doc["links"] = json!(self_links(&format!(
+        "{}/tileMatrixSets/{}",
+        base_url(&headers),
+        tms.id
+    )));
+    respond(doc, &tms.id, wants_html(&query, &headers))
+}
+
+fn not_found(layer: &str) -> Response {
+    error(
+        StatusCode::NOT_FOUND,
+        format!("Layer not found: '{}'", layer),
+    )
+}
+
+fn unknown_tms(tms: &str) -> Response {
+    error(
+        StatusCode::NOT_FOUND,
+        format!("Unknown tile matrix set '{}'", tms),
+    )
+}
+
+fn tiling_scheme_link(tms: &TileMatrixSet, base: &str) -> Value {
+    link(
+        format!("{}/tileMatrixSets/{}", base, tms.id),
+        "http://www.opengis.net/def/rel/ogc/1.0/tiling-scheme",
+        "application/json",
+        &tms.id,
+    )
+}
+
+/// The fields a tileset shares with its entry in the tilesets list
+fn tileset_summary(layer: &Layer, tms: &TileMatrixSet, base: &str) -> Value {
+    let mut summary = json!({
+        "title": format!("{} ({})", layer.layer, tms.id),
+        "dataType": "map",
+        "crs": tms.crs.uri(),
+    });
+    match &tms.uri {
+        Some(uri) => summary["tileMatrixSetURI"] = json!(uri),
+        None => summary["tileMatrixSet"] = json!(format!("{}/tileMatrixSets/{}", base, tms.id)),
+    }
+    summary
+}
+
+fn wgs84_bbox(layer: &Layer) -> [f64; 4] {
+    layer
+        .cached_geometry
+        .get(&4326)
+        .map(|g| [g.extent.minx, g.extent.miny, g.extent.maxx, g.extent.maxy])
+        .unwrap_or([-180.0, -90.0, 180.0, 90.0])
+}
+
+pub(crate) fn collection(layer: &Layer, base: &str) -> Value {
+    let url = format!("{}/collections/{}", base, layer.layer);
+    let mut links = self_links(&url);
+    links.push(link(
+        format!("{}/map/tiles", url),
+        "http://www.opengis.net/def/rel/ogc/1.0/tilesets-map",
+        "application/json",
+        "Map tilesets",
+    ));
+    json!({
+        "id": layer.layer,
+        "title": layer.layer,
+        "description": format!("{} styled with '{}'", layer.layer, layer.style),
+        "extent": { "spatial": { "bbox": [wgs84_bbox(layer)], "crs": CRS84 } },
+        "links": links,
+    })
+}
+
+pub(crate) fn tileset(layer: &Layer, tms: &TileMatrixSet, base: &str) -> Value {
+    let url = format!("{}/collections/{}/map/tiles/{}", base, layer.layer, tms.id);
+    let mut links = self_links(&url);
+    links.extend([
+        tiling_scheme_link(tms, base),
+        link(
+            format!("{}/collections/{}", base, layer.layer),
+            "http://www.opengis.net/def/rel/ogc/1.0/geodata",
+            "application/json",
+            &layer.layer,
+        ),
+    ]);
+    let mut tiles = link(
+        format!("{}/{{tileMatrix}}/{{tileRow}}/{{tileCol}}", url),
+        "item",
+        "image/png",
+        "Map tiles",
+    );
+    tiles["templated"] = json!(true);
+    links.push(tiles);
+
+    // Only the tiles that cover the layer, down to the first level at least
+    // as fine as the raster
+    let limits: Vec<Value> = match layer.source_geometry.edge_bounds(tms.crs_code) {
+        Ok(extent) => {
+            let resolution = (extent.maxx - extent.minx) / layer.raster_size.0.max(1) as f64;
+            (0..=tms.native_zoom(resolution))
+                .filter_map(|z| {
+                    let (min_row, max_row, min_col, max_col) = tms.tile_limits(z, &extent)?;
+                    Some(json!({
+                        "tileMatrix": tms.tile_matrices[z as usize].id,
+                        "minTileRow": min_row,
+                        "maxTileRow": max_row,
+                        "minTileCol": min_col,
+                        "maxTileCol": max_col,
+                    }))
+                })
+                .collect()
+        }
+        Err(_) => Vec::new(),
+    };
+
+    let bbox = wgs84_bbox(layer);
+    let mut doc = tileset_summary(layer, tms, base);
+    doc["tileMatrixSetLimits"] = json!(limits);
+    doc["boundingBox"] = json!({
+        "lowerLeft": [bbox[0], bbox[1]],
+        "upperRight": [bbox[2], bbox[3]],
+        "crs": CRS84,
+    });
+    doc["links"] = json!(links);
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::wmts::tests::make_layer;
+
+    #[test]
+    fn test_tileset() {
+        let doc = tileset(
+            &make_layer("rain"),
+            &TileMatrixSet::web_mercator_quad(),
+            "https://maps.example.com",
+        );
+
+        let tiles = doc["links"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|l| l["rel"] == "item")
+            .unwrap();
+        assert_eq!(
+            tiles["href"],
+            "https://maps.example.com/collections/rain/map/tiles/WebMercatorQuad/{tileMatrix}/{tileRow}/{tileCol}"
+        );
+        assert_eq!(
+            doc["tileMatrixSetURI"],
+            "http://www.opengis.net/def/tilematrixset/OGC/1.0/WebMercatorQuad"
+        );
+
+        // z0 to z8 over Australia, at z3 110..155°E is columns 6-7 and
+        // 10..45°S rows 4-5
+        let limits = doc["tileMatrixSetLimits"].as_array().unwrap();
+        assert_eq!(limits.len(), 9);
+        assert_eq!(
+            limits[3],
+            json!({
+                "tileMatrix": "3",
+                "minTileRow": 4,
+                "maxTileRow": 5,
+                "minTileCol": 6,
+                "maxTileCol": 7,
+            })
+        );
+    }
+
+    #[test]
+    fn test_tileset_crs84() {
+        let doc = tileset(
+            &make_layer("rain"),
+            &TileMatrixSet::world_crs84_quad(),
+            "http://localhost",
+        );
+        assert_eq!(doc["crs"], CRS84);
+        // 0.01° pixels, z7 has 180/256/128 = 0.0055° cells
+        let limits = doc["tileMatrixSetLimits"].as_array().unwrap();
+        assert_eq!(limits.len(), 8);
+        assert_eq!(limits[3]["minTileCol"], 12);
+        assert_eq!(limits[3]["maxTileRow"], 5);
+    }
+
+    #[test]
+    fn test_tile_matrix_set_json() {
+        let tms = serde_json::to_value(TileMatrixSet::web_mercator_quad()).unwrap();
+        let matrices = tms["tileMatrices"].as_array().unwrap();
+        assert_eq!(matrices[1]["matrixWidth"], json!(2));
+        assert_eq!(matrices[0]["cornerOfOrigin"], "topLeft");
+        assert_eq!(tms["crs"], "http://www.opengis.net/def/crs/EPSG/0/3857");
+        assert!(tms.get("crsCode").is_none());
+    }
+
+    #[test]
+    fn test_html() {
+        let doc = collection(&make_layer("rain<1>"), "http://localhost");
+        let response = respond(doc, "rain<1>", true);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+    }
+}
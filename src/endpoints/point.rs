@@ -0,0 +1,223 @@
+use crate::models::{responses::PointSample, tms::CrsRef};
+use crate::traits::TileReader;
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const MAX_POINTS: usize = 10_000;
+
+/// `?lon=&lat=`, or `?x=&y=&crs=EPSG:3857` for any other CRS, and
+/// `&terrain=true` for the slope and aspect there
+#[derive(Debug, Default, Deserialize)]
+pub struct PointQuery {
+    lon: Option<f64>,
+    lat: Option<f64>,
+    x: Option<f64>,
+    y: Option<f64>,
+    crs: Option<String>,
+    #[serde(default)]
+    terrain: bool,
+}
+
+/// Many points in one CRS, `{"crs": "EPSG:4326", "points": [[x, y], ...]}`
+#[derive(Debug, Deserialize)]
+pub struct PointsRequest {
+    crs: Option<String>,
+    points: Vec<(f64, f64)>,
+    #[serde(default)]
+    terrain: bool,
+}
+
+#[derive(Serialize)]
+struct PointResult {
+    x: f64,
+    y: f64,
+    #[serde(flatten)]
+    sample: Option<PointSample>, // Left out when the point misses the raster
+}
+
+#[derive(Serialize)]
+struct PointsResponse {
+    layer: String,
+    crs: String,
+    results: Vec<PointResult>,
+}
+
+/// An EPSG code from `EPSG:3857`, `3857`, `CRS:84` or an OGC CRS URI,
+/// EPSG:4326 if not given
+pub(crate) fn parse_crs(crs: Option<&str>) -> Result<i32, String> {
+    let Some(crs) = crs else {
+        return Ok(4326);
+    };
+    crs.parse::<i32>()
+        .ok()
+        .or_else(|| CrsRef::Uri(crs.to_string()).epsg())
+        .ok_or_else(|| format!("Unsupported CRS '{}'", crs))
+}
+
+/// Raw pixel values of every band under one point:
+/// /point/{layer}?lon=&lat= or /point/{layer}?x=&y=&crs=
+pub async fn point_handler(
+    Path(layer): Path<String>,
+    State(reader): State<Arc<dyn TileReader>>,
+    Query(query): Query<PointQuery>,
+) -> Response {
+    let terrain = query.terrain;
+    let (x, y, crs) = match query {
+        PointQuery {
+            lon: Some(lon),
+            lat: Some(lat),
+            x: None,
+            y: None,
+            crs: None,
+            ..
+        } => (lon, lat, 4326),
+        PointQuery {
+            lon: None,
+            lat: None,
+            x: Some(x),
+            y: Some(y),
+            crs,
+            ..
+        } => match parse_crs(crs.as_deref()) {
+            Ok(crs) => (x, y, crs),
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        },
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Give either lon and lat, or x, y and optionally crs",
+            )
+                .into_response();
+        }
+    };
+
+    points_response(&reader, &layer, vec![(x, y)], crs, terrain)
+        .await
+        .map(|mut response| {
+            // A single point outside the raster is a 404 rather than an empty result
+            match response.results.pop() {
+                Some(result) if result.sample.is_some() => Json(result).into_response(),
+                _ => (
+                    StatusCode::NOT_FOUND,
+                    format!("Point is outside layer '{}'", layer),
+                )
+                    .into_response(),
+            }
+        })
+        .unwrap_or_else(IntoResponse::into_response)
+}
+
+/// Batch form: POST /point/{layer} with a PointsRequest
+pub async fn points_handler(
+    Path(layer): Path<String>,
+    State(reader): State<Arc<dyn TileReader>>,
+    Json(request): Json<PointsRequest>,
+) -> Response {
+    if request.points.len() > MAX_POINTS {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("At most {} points per request", MAX_POINTS),
+        )
+            .into_response();
+    }
+    let crs = match parse_crs(request.crs.as_deref()) {
+        Ok(crs) => crs,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    points_response(&reader, &layer, request.points, crs, request.terrain)
+        .await
+        .map(|response| Json(response).into_response())
+        .unwrap_or_else(IntoResponse::into_response)
+}
+
+async fn points_response(
+    reader: &Arc<dyn TileReader>,
+    layer: &str,
+    points: Vec<(f64, f64)>,
+    crs: i32,
+    terrain: bool,
+) -> Result<PointsResponse, (StatusCode, String)> {
+    if reader.get_layer(layer).await.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Layer not found: '{}'", layer),
+        ));
+    }
+    let samples = reader
+        .get_points(layer, points.clone(), crs, terrain)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    Ok(PointsResponse {
+        layer: layer.to_string(),
+        crs: format!("EPSG:{}", crs),
+        results: points
+            .into_iter()
+            .zip(samples)
+            .map(|((x, y), sample)| PointResult { x, y, sample })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::responses::BandValue;
+
+    #[test]
+    fn test_parse_crs() {
+        assert_eq!(parse_crs(None), Ok(4326));
+        assert_eq!(parse_crs(Some("EPSG:3857")), Ok(3857));
+        assert_eq!(parse_crs(Some("3031")), Ok(3031));
+        assert_eq!(parse_crs(Some("CRS:84")), Ok(4326));
+        assert_eq!(
+            parse_crs(Some("http://www.opengis.net/def/crs/EPSG/0/3413")),
+            Ok(3413)
+        );
+        assert!(parse_crs(Some("web mercator")).is_err());
+    }
+
+    #[test]
+    fn test_point_result_json() {
+        let inside = PointResult {
+            x: 1.0,
+            y: 2.0,
+            sample: Some(PointSample {
+                pixel: 3,
+                line: 4,
+                values: vec![BandValue {
+                    band: 1,
+                    value: Some(-9999.0),
+                    nodata: true,
+                }],
+                terrain: None,
+            }),
+        };
+        assert_eq!(
+            serde_json::to_value(&inside).unwrap(),
+            serde_json::json!({
+                "x": 1.0,
+                "y": 2.0,
+                "pixel": 3,
+                "line": 4,
+                "values": [{ "band": 1, "value": -9999.0, "nodata": true }],
+            })
+        );
+
+        let outside = PointResult {
+            x: 1.0,
+            y: 2.0,
+            sample: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&outside).unwrap(),
+            serde_json::json!({ "x": 1.0, "y": 2.0 })
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use crate::Config;
+use crate::endpoints::caching::{cache_headers, is_not_modified, tile_etag};
+use crate::endpoints::point::parse_crs;
+use crate::models::{
+    geometry::GeometryExtent,
+    layer::LayerGeometry,
+    render::{RenderOptions, RenderQuery},
+};
+use crate::traits::TileReader;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+const DEFAULT_SIZE: usize = 512;
+const MAX_SIZE: usize = 4096;
+
+/// `?width=&height=`, either one keeps the aspect ratio, and optionally
+/// `?bbox=minx,miny,maxx,maxy&crs=` for part of the layer
+#[derive(Debug, Default, Deserialize)]
+pub struct PreviewQuery {
+    width: Option<usize>,
+    height: Option<usize>,
+    bbox: Option<String>,
+    crs: Option<String>,
+}
+
+/// Image size for an extent: as given, the missing side from the aspect
+/// ratio, or DEFAULT_SIZE along the longer side
+fn preview_size(
+    extent: &GeometryExtent,
+    width: Option<usize>,
+    height: Option<usize>,
+) -> Result<(usize, usize), String> {
+    let aspect = (extent.maxx - extent.minx) / (extent.maxy - extent.miny);
+    let from_width = |w: usize| (w, ((w as f64 / aspect).round() as usize).max(1));
+    let from_height = |h: usize| (((h as f64 * aspect).round() as usize).max(1), h);
+    let (width, height) = match (width, height) {
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => from_width(w),
+        (None, Some(h)) => from_height(h),
+        (None, None) if aspect >= 1.0 => from_width(DEFAULT_SIZE),
+        (None, None) => from_height(DEFAULT_SIZE),
+    };
+    if !(1..=MAX_SIZE).contains(&width) || !(1..=MAX_SIZE).contains(&height) {
+        return Err(format!(
+            "Preview size {}×{} must be within 1 to {} pixels a side",
+            width, height, MAX_SIZE
+        ));
+    }
+    Ok((width, height))
+}
+
+/// A PNG of the whole layer, or of a bbox, in the layer's own CRS:
+/// /preview/{layer}.png?width=&height= plus any tile render parameters
+pub async fn preview_handler(
+    Path(file): Path<String>,
+    State(reader): State<Arc<dyn TileReader>>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<PreviewQuery>,
+    Query(render_query): Query<RenderQuery>,
+    request_headers: HeaderMap,
+) -> Response {
+    let Some(layer) = file.strip_suffix(".png") else {
+        return (StatusCode::NOT_FOUND, "Previews are served as {layer}.png").into_response();
+    };
+    let options = match RenderOptions::from_query(&render_query) {
+        Ok(options) if options.format.is_some_and(|f| f.is_raw()) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Previews are PNGs, use /export for values",
+            )
+                .into_response();
+        }
+        Ok(options) => options,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let Some(layer_obj) = reader.get_layer(layer).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Layer not found: '{}'", layer),
+        )
+            .into_response();
+    };
+
+    // The bbox in source coordinates, so it can be read without warping
+    let extent = match query.bbox.as_deref().map(|bbox| {
+        let bbox: GeometryExtent = bbox.parse()?;
+        let crs = parse_crs(query.crs.as_deref())?;
+        LayerGeometry {
+            crs_code: crs,
+            extent: bbox,
+        }
+        .edge_bounds(layer_obj.source_geometry.crs_code)
+        .map_err(|e| e.to_string())
+    }) {
+        Some(Ok(extent)) => Some(extent),
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        None => None,
+    };
+    let size = match preview_size(
+        extent.as_ref().unwrap_or(&layer_obj.source_geometry.extent),
+        query.width,
+        query.height,
+    ) {
+        Ok(size) => size,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let merged = match options.merged_over(&layer_obj.render) {
+        Ok(merged) => merged,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let (last_modified, size_bytes) = layer_obj.current_source().await;
+    let params = format!(
+        "preview/{}/{:?}/{}x{}?{}",
+        layer_obj.style,
+        extent,
+        size.0,
+        size.1,
+        merged.cache_key()
+    );
+    let etag = tile_etag(
+        &layer_obj.path.to_string_lossy(),
+        last_modified,
+        size_bytes,
+        &params,
+    );
+    let headers = cache_headers(&etag, last_modified, config.cache_control_max_age);
+    if is_not_modified(&request_headers, &etag, last_modified) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    match reader.get_preview(layer, extent, size, &options).await {
+        Ok(png) => (
+            headers,
+            [(header::CONTENT_TYPE, png.content_type)],
+            png.bytes,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_size() {
+        // Twice as wide as tall
+        let extent = GeometryExtent::from((110.0, -45.0, 150.0, -25.0));
+        assert_eq!(preview_size(&extent, None, None), Ok((512, 256)));
+        assert_eq!(preview_size(&extent, Some(300), None), Ok((300, 150)));
+        assert_eq!(preview_size(&extent, None, Some(100)), Ok((200, 100)));
+        assert_eq!(preview_size(&extent, Some(64), Some(64)), Ok((64, 64)));
+
+        let tall = GeometryExtent::from((0.0, 0.0, 1.0, 4.0));
+        assert_eq!(preview_size(&tall, None, None), Ok((128, 512)));
+        assert_eq!(preview_size(&tall, Some(2), None), Ok((2, 8)));
+
+        assert!(preview_size(&extent, Some(0), None).is_err());
+        assert!(preview_size(&extent, Some(5000), None).is_err());
+        assert!(preview_size(&extent, None, Some(4000)).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use crate::endpoints::point::parse_crs;
+use crate::models::{geojson::GeoJson, responses::LineProfile};
+use crate::traits::TileReader;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// `{"geometry": <LineString or Feature>, "crs": "EPSG:4326", "band": 1,
+/// "spacing": 30}`, spacing in meters, all but the geometry optional
+#[derive(Debug, Deserialize)]
+pub struct ProfileRequest {
+    geometry: GeoJson,
+    crs: Option<String>,
+    band: Option<usize>,
+    spacing: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct ProfileResponse {
+    layer: String,
+    crs: String,
+    #[serde(flatten)]
+    profile: LineProfile,
+}
+
+/// Values sampled along a line, e.g. an elevation profile:
+/// POST /profile/{layer} with a ProfileRequest
+pub async fn profile_handler(
+    Path(layer): Path<String>,
+    State(reader): State<Arc<dyn TileReader>>,
+    Json(request): Json<ProfileRequest>,
+) -> Response {
+    if let Some(spacing) = request.spacing.filter(|s| !(s.is_finite() && *s > 0.0)) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Spacing must be a positive number of meters, got {}",
+                spacing
+            ),
+        )
+            .into_response();
+    }
+    let parsed = request
+        .geometry
+        .line()
+        .and_then(|line| Ok((line, parse_crs(request.crs.as_deref())?)));
+    let (line, crs) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    if reader.get_layer(&layer).await.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Layer not found: '{}'", layer),
+        )
+            .into_response();
+    }
+    match reader
+        .get_profile(
+            &layer,
+            line,
+            crs,
+            request.band.unwrap_or(1),
+            request.spacing,
+        )
+        .await
+    {
+        Ok(profile) => Json(ProfileResponse {
+            layer,
+            crs: format!("EPSG:{}", crs),
+            profile,
+        })
+        .into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
@@ -1,35 +1,131 @@
+use crate::cache::{disk::DiskTileCache, memory::MemoryTileCache, reader::CachedTileReader};
 use crate::config::{Config, Source};
-use crate::endpoints::handlers::{get_all_layers, tile_handler, webmap_handler};
-use crate::reader::local::LocalTileReader;
+use crate::endpoints::contours::contours_handler;
+use crate::endpoints::export::export_handler;
+use crate::endpoints::handlers::{
+    cache_stats_handler, get_all_layers, tile_handler, webmap_handler,
+};
+use crate::endpoints::ogc;
+use crate::endpoints::point::{point_handler, points_handler};
+use crate::endpoints::preview::preview_handler;
+use crate::endpoints::profile::profile_handler;
+use crate::endpoints::tilejson::tilejson_handler;
+use crate::endpoints::wms::wms_handler;
+use crate::endpoints::wmts::{wmts_capabilities_handler, wmts_kvp_handler, wmts_tile_handler};
+use crate::endpoints::zonal::zonal_stats_handler;
+use crate::models::tms::TileMatrixSets;
+use crate::reader::{local::LocalTileReader, s3::S3TileReader};
 use crate::traits::TileReader;
-use axum::{Router, routing::get};
+use axum::{
+    Router,
+    extract::FromRef,
+    routing::{get, post},
+};
 use std::net::SocketAddr;
 use std::sync::Arc;
 
 pub struct TileServer {
     config: Config,
     reader: Arc<dyn TileReader>,
+    tile_cache: Option<Arc<CachedTileReader>>,
+    tile_matrix_sets: Arc<TileMatrixSets>,
+}
+
+/// Shared state for the router. Handlers that only need the reader can keep
+/// extracting `State<Arc<dyn TileReader>>`.
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pub reader: Arc<dyn TileReader>,
+    pub tile_cache: Option<Arc<CachedTileReader>>,
+    pub config: Arc<Config>,
+    pub tile_matrix_sets: Arc<TileMatrixSets>,
 }
 
 impl TileServer {
     pub async fn new(config: Config) -> anyhow::Result<Self> {
         let reader: Arc<dyn TileReader> = match &config.source {
             Some(Source::Local(path)) => Arc::new(LocalTileReader::new(path).await),
-            Some(Source::S3 { .. }) => unimplemented!("S3 backend is not yet implemented"),
+            Some(Source::S3 { bucket, prefix }) => {
+                Arc::new(S3TileReader::new(bucket, prefix, &config.s3).await?)
+            }
             None => anyhow::bail!("No source provided in the configuration"),
         };
 
-        // if reader
-        Ok(Self { config, reader })
+        // Put the tile caches in front of whichever reader we ended up with
+        let memory =
+            (config.tile_cache_bytes > 0).then(|| MemoryTileCache::new(config.tile_cache_bytes));
+        let disk = match &config.tile_cache_dir {
+            Some(dir) if config.tile_cache_dir_bytes > 0 => {
+                Some(DiskTileCache::new(dir, config.tile_cache_dir_bytes)?)
+            }
+            _ => None,
+        };
+        let tile_cache = (memory.is_some() || disk.is_some())
+            .then(|| Arc::new(CachedTileReader::new(reader.clone(), memory, disk)));
+        let reader: Arc<dyn TileReader> = match &tile_cache {
+            Some(cache) => cache.clone(),
+            None => reader,
+        };
+
+        let mut tile_matrix_sets = TileMatrixSets::default();
+        if let Some(dir) = &config.tile_matrix_set_dir {
+            tile_matrix_sets.load_dir(dir)?;
+        }
+
+        Ok(Self {
+            config,
+            reader,
+            tile_cache,
+            tile_matrix_sets: Arc::new(tile_matrix_sets),
+        })
     }
 
     pub async fn start(self) -> anyhow::Result<()> {
         // Tile-serving router with state
         let app = Router::new()
             .route("/tiles/{layer}/{z}/{x}/{y}", get(tile_handler))
+            .route("/tiles/{layer}/tilejson.json", get(tilejson_handler))
+            .route("/contours/{layer}/{z}/{x}/{file}", get(contours_handler))
             .route("/layers", get(get_all_layers))
             .route("/map", get(webmap_handler))
-            .with_state(self.reader.clone());
+            .route("/cache", get(cache_stats_handler))
+            .route("/point/{layer}", get(point_handler).post(points_handler))
+            .route("/stats/{layer}", post(zonal_stats_handler))
+            .route("/profile/{layer}", post(profile_handler))
+            .route("/export/{layer}", get(export_handler))
+            .route("/preview/{file}", get(preview_handler))
+            .route("/wmts", get(wmts_kvp_handler))
+            .route(
+                "/wmts/1.0.0/WMTSCapabilities.xml",
+                get(wmts_capabilities_handler),
+            )
+            .route(
+                "/wmts/1.0.0/{layer}/{style}/{tms}/{matrix}/{row}/{col}",
+                get(wmts_tile_handler),
+            )
+            .route("/wms", get(wms_handler))
+            // OGC API - Tiles
+            .route("/", get(ogc::landing_handler))
+            .route("/conformance", get(ogc::conformance_handler))
+            .route("/collections", get(ogc::collections_handler))
+            .route("/collections/{layer}", get(ogc::collection_handler))
+            .route("/collections/{layer}/map/tiles", get(ogc::tilesets_handler))
+            .route(
+                "/collections/{layer}/map/tiles/{tms}",
+                get(ogc::tileset_handler),
+            )
+            .route(
+                "/collections/{layer}/map/tiles/{tms}/{z}/{y}/{x}",
+                get(ogc::tile_handler),
+            )
+            .route("/tileMatrixSets", get(ogc::tile_matrix_sets_handler))
+            .route("/tileMatrixSets/{id}", get(ogc::tile_matrix_set_handler))
+            .with_state(AppState {
+                reader: self.reader.clone(),
+                tile_cache: self.tile_cache.clone(),
+                config: Arc::new(self.config.clone()),
+                tile_matrix_sets: self.tile_matrix_sets.clone(),
+            });
 
         let addr = SocketAddr::from(([0, 0, 0, 0], self.config.port));
         let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
@@ -61,8 +157,17 @@ impl TileServer {
 
     📚 Query for all layers (JSON)
        → http://{}/layers
+
+    🧭 WMTS capabilities (ArcGIS, QGIS)
+       → http://{}/wmts/1.0.0/WMTSCapabilities.xml
+
+    🧭 WMS capabilities
+       → http://{}/wms?SERVICE=WMS&REQUEST=GetCapabilities
+
+    🧭 OGC API - Tiles landing page
+       → http://{}/
             "#,
-            addr, random_layer, addr, random_layer, addr, addr
+            addr, random_layer, addr, random_layer, addr, addr, addr, addr, addr
         );
 
         axum::serve(listener, app.into_make_service())
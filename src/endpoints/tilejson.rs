@@ -0,0 +1,185 @@
+use crate::Config;
+use crate::endpoints::urls::base_url;
+use crate::models::{
+    layer::Layer,
+    render::{RenderMode, RenderOptions, RenderQuery},
+};
+use crate::traits::TileReader;
+use axum::{
+    Json,
+    extract::{Path, Query, RawQuery, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+/// https://github.com/mapbox/tilejson-spec/tree/master/3.0.0
+#[derive(Serialize)]
+pub(crate) struct TileJson {
+    tilejson: &'static str,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attribution: Option<String>,
+    scheme: &'static str,
+    tiles: Vec<String>,
+    minzoom: u8,
+    maxzoom: u8,
+    bounds: [f64; 4],
+    center: [f64; 3],
+    data_range: [f32; 2], // Not in the spec, the layer's min/max for legends
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>, // MapLibre's raster-dem encoding
+}
+
+/// The TileJSON for a layer. Render parameters given here (e.g.
+/// `?expression=`) are carried over to the tile URL.
+pub async fn tilejson_handler(
+    Path(layer): Path<String>,
+    State(reader): State<Arc<dyn TileReader>>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<RenderQuery>,
+    RawQuery(raw_query): RawQuery,
+    headers: HeaderMap,
+) -> Response {
+    let options = match RenderOptions::from_query(&query) {
+        Ok(options) => options,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let Some(layer_obj) = reader.get_layer(&layer).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Layer not found: '{}'", layer),
+        )
+            .into_response();
+    };
+
+    let merged = match options.merged_over(&layer_obj.render) {
+        Ok(merged) => merged,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let mut doc = tilejson(
+        &layer_obj,
+        &base_url(&headers),
+        raw_query.as_deref(),
+        config.attribution.clone(),
+    );
+    doc.encoding = dem_encoding(&merged);
+    Json(doc).into_response()
+}
+
+/// How MapLibre should decode elevation tiles. A custom base or interval
+/// has no name, so is left for the client to configure.
+fn dem_encoding(options: &RenderOptions) -> Option<&'static str> {
+    match options.render? {
+        RenderMode::TerrainRgb if options.base.is_none() && options.interval.is_none() => {
+            Some("mapbox")
+        }
+        RenderMode::Terrarium => Some("terrarium"),
+        _ => None,
+    }
+}
+
+pub(crate) fn tilejson(
+    layer: &Layer,
+    base_url: &str,
+    query: Option<&str>,
+    attribution: Option<String>,
+) -> TileJson {
+    let query = query
+        .filter(|q| !q.is_empty())
+        .map(|q| format!("?{}", q))
+        .unwrap_or_default();
+    let (minzoom, maxzoom) = layer.zoom_range();
+
+    let bounds = match layer.cached_geometry.get(&4326) {
+        Some(geo) => [
+            geo.extent.minx.max(-180.0),
+            geo.extent.miny.max(-90.0),
+            geo.extent.maxx.min(180.0),
+            geo.extent.maxy.min(90.0),
+        ],
+        None => [-180.0, -90.0, 180.0, 90.0],
+    };
+
+    TileJson {
+        tilejson: "3.0.0",
+        name: layer.layer.clone(),
+        attribution,
+        scheme: "xyz",
+        tiles: vec![format!(
+            "{}/tiles/{}/{{z}}/{{x}}/{{y}}{}",
+            base_url, layer.layer, query
+        )],
+        minzoom,
+        maxzoom,
+        bounds,
+        center: [
+            (bounds[0] + bounds[2]) / 2.0,
+            (bounds[1] + bounds[3]) / 2.0,
+            minzoom as f64,
+        ],
+        data_range: [layer.min_value, layer.max_value],
+        encoding: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::wmts::tests::make_layer;
+
+    #[test]
+    fn test_tilejson() {
+        let doc = tilejson(
+            &make_layer("rain"),
+            "https://maps.example.com/tileyolo",
+            Some("expression=b1*2"),
+            Some("© BoM".to_string()),
+        );
+        let json = serde_json::to_value(&doc).unwrap();
+
+        assert_eq!(json["tilejson"], "3.0.0");
+        assert_eq!(
+            json["tiles"][0],
+            "https://maps.example.com/tileyolo/tiles/rain/{z}/{x}/{y}?expression=b1*2"
+        );
+        assert_eq!(
+            json["bounds"],
+            serde_json::json!([110.0, -45.0, 155.0, -10.0])
+        );
+        assert_eq!(json["center"], serde_json::json!([132.5, -27.5, 3.0]));
+        assert_eq!(json["attribution"], "© BoM");
+        assert_eq!(json["data_range"], serde_json::json!([0.0, 1.0]));
+
+        // 45° fits in one z3 tile, 0.01° (~1.1 km) pixels need z8 (0.6 km, z7 is 1.2 km)
+        assert_eq!(
+            (json["minzoom"].as_u64(), json["maxzoom"].as_u64()),
+            (Some(3), Some(8))
+        );
+
+        let doc = tilejson(&make_layer("rain"), "http://localhost", Some(""), None);
+        let json = serde_json::to_value(&doc).unwrap();
+        assert_eq!(json["tiles"][0], "http://localhost/tiles/rain/{z}/{x}/{y}");
+        assert!(json.get("attribution").is_none());
+        assert!(json.get("encoding").is_none());
+    }
+
+    #[test]
+    fn test_dem_encoding() {
+        let options = |json: &str| RenderOptions::from_json(json).unwrap();
+        assert_eq!(dem_encoding(&options("{}")), None);
+        assert_eq!(
+            dem_encoding(&options(r#"{"render": "terrain_rgb"}"#)),
+            Some("mapbox")
+        );
+        assert_eq!(
+            dem_encoding(&options(r#"{"render": "terrarium"}"#)),
+            Some("terrarium")
+        );
+        assert_eq!(
+            dem_encoding(&options(r#"{"render": "terrain_rgb", "interval": 0.01}"#)),
+            None
+        );
+    }
+}
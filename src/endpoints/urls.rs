@@ -0,0 +1,78 @@
+use axum::http::{HeaderMap, header};
+use std::fmt::Write;
+
+/// The externally visible root of the server, e.g. `https://maps.example.com/tiles-api`,
+/// for links in capabilities documents. Behind a reverse proxy the
+/// X-Forwarded-{Proto,Host,Prefix} headers win over Host.
+pub(crate) fn base_url(headers: &HeaderMap) -> String {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            // Proxies chain these as a comma separated list, the first is the client's
+            .and_then(|v| v.split(',').next())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+
+    let proto = header("x-forwarded-proto").unwrap_or_else(|| "http".to_string());
+    let host = header("x-forwarded-host")
+        .or_else(|| header(header::HOST.as_str()))
+        .unwrap_or_else(|| "localhost".to_string());
+    let prefix = header("x-forwarded-prefix").unwrap_or_default();
+
+    format!("{}://{}{}", proto, host, prefix.trim_end_matches('/'))
+}
+
+/// Percent-encode one path segment, such as a layer name, for a link.
+/// Only the RFC 3986 unreserved characters are left as they are.
+pub(crate) fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_base_url() {
+        assert_eq!(base_url(&HeaderMap::new()), "http://localhost");
+        assert_eq!(
+            base_url(&headers(&[("host", "localhost:8000")])),
+            "http://localhost:8000"
+        );
+        assert_eq!(
+            base_url(&headers(&[
+                ("host", "10.0.0.5:8000"),
+                ("x-forwarded-proto", "https"),
+                ("x-forwarded-host", "maps.example.com, 10.0.0.1"),
+                ("x-forwarded-prefix", "/tileyolo/"),
+            ])),
+            "https://maps.example.com/tileyolo"
+        );
+    }
+
+    #[test]
+    fn test_encode_segment() {
+        assert_eq!(encode_segment("rain_2024-01.v2~x"), "rain_2024-01.v2~x");
+        assert_eq!(encode_segment("rain&temp"), "rain%26temp");
+        assert_eq!(encode_segment("a b/c?d#"), "a%20b%2Fc%3Fd%23");
+        assert_eq!(encode_segment("pluie_é"), "pluie_%C3%A9");
+    }
+}
@@ -0,0 +1,559 @@
+use crate::endpoints::urls::base_url;
+use crate::models::{
+    geometry::GeometryExtent, layer::Layer, render::RenderOptions, responses::BandValue,
+};
+use crate::traits::TileReader;
+use crate::utils::xml::escape;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use image::{ImageFormat, Rgba, RgbaImage, imageops};
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::Cursor;
+use std::sync::Arc;
+
+const MAX_SIZE: usize = 4096;
+/// Each layer is a full render of the image, so a request can only stack so many
+const MAX_LAYERS: usize = 8;
+
+/// A WMS 1.3.0 ServiceExceptionReport, `code` is left out for plain errors
+#[derive(Debug)]
+struct ServiceException {
+    code: Option<&'static str>,
+    text: String,
+}
+
+fn exception(code: Option<&'static str>, text: &str) -> ServiceException {
+    ServiceException {
+        code,
+        text: text.to_string(),
+    }
+}
+
+impl IntoResponse for ServiceException {
+    fn into_response(self) -> Response {
+        let code = self
+            .code
+            .map(|c| format!(" code=\"{}\"", c))
+            .unwrap_or_default();
+        let body = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<ServiceExceptionReport version="1.3.0" xmlns="http://www.opengis.net/ogc">
+  <ServiceException{}>{}</ServiceException>
+</ServiceExceptionReport>
+"#,
+            code,
+            escape(&self.text)
+        );
+        (
+            StatusCode::BAD_REQUEST,
+            [(header::CONTENT_TYPE, "text/xml")],
+            body,
+        )
+            .into_response()
+    }
+}
+
+/// Parsed KVP parameters, names are case insensitive
+struct Params(HashMap<String, String>);
+
+impl Params {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    fn require(&self, name: &str) -> Result<&str, ServiceException> {
+        self.get(name)
+            .ok_or_else(|| exception(None, &format!("Missing parameter {}", name)))
+    }
+
+    fn size(&self, name: &str) -> Result<usize, ServiceException> {
+        self.require(name)?
+            .parse::<usize>()
+            .ok()
+            .filter(|v| (1..=MAX_SIZE).contains(v))
+            .ok_or_else(|| {
+                exception(
+                    None,
+                    &format!("{} must be between 1 and {}", name, MAX_SIZE),
+                )
+            })
+    }
+
+    /// The CRS as an EPSG code and the BBOX in x/y (lon/lat) order. WMS 1.3.0
+    /// puts EPSG:4326 in lat/lon order, 1.1.1 (SRS=) and CRS:84 in lon/lat.
+    fn crs_and_bbox(&self) -> Result<(i32, GeometryExtent), ServiceException> {
+        let (crs, version_130) = match (self.get("CRS"), self.get("SRS")) {
+            (Some(crs), _) => (crs, true),
+            (None, Some(srs)) => (srs, false),
+            (None, None) => return Err(exception(None, "Missing parameter CRS")),
+        };
+        let (code, lat_lon) = match crs.to_ascii_uppercase().as_str() {
+            "EPSG:3857" | "EPSG:900913" => (3857, false),
+            "EPSG:4326" => (4326, version_130),
+            "CRS:84" => (4326, false),
+            _ => {
+                return Err(exception(
+                    Some("InvalidCRS"),
+                    &format!("Unsupported CRS '{}'", crs),
+                ));
+            }
+        };
+
+        let values: Vec<f64> = self
+            .require("BBOX")?
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| exception(None, "BBOX must be four numbers"))?;
+        let [a, b, c, d] = values[..] else {
+            return Err(exception(None, "BBOX must be four numbers"));
+        };
+        let extent = if lat_lon {
+            GeometryExtent::from((b, a, d, c))
+        } else {
+            GeometryExtent::from((a, b, c, d))
+        };
+        if extent.minx >= extent.maxx || extent.miny >= extent.maxy {
+            return Err(exception(None, "BBOX min must be less than max"));
+        }
+        Ok((code, extent))
+    }
+
+    fn layers<'a>(
+        &self,
+        name: &str,
+        available: &'a [Layer],
+    ) -> Result<Vec<&'a Layer>, ServiceException> {
+        let names = self.require(name)?;
+        if names.split(',').count() > MAX_LAYERS {
+            return Err(exception(
+                None,
+                &format!("At most {} layers can be requested at once", MAX_LAYERS),
+            ));
+        }
+        names
+            .split(',')
+            .map(|name| {
+                available.iter().find(|l| l.layer == name).ok_or_else(|| {
+                    exception(
+                        Some("LayerNotDefined"),
+                        &format!("Unknown layer '{}'", name),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// KVP binding: /wms?SERVICE=WMS&REQUEST=GetCapabilities|GetMap|GetFeatureInfo&...
+pub async fn wms_handler(
+    State(reader): State<Arc<dyn TileReader>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    let params = Params(
+        params
+            .into_iter()
+            .map(|(k, v)| (k.to_ascii_uppercase(), v))
+            .collect(),
+    );
+
+    // SERVICE is optional on GetMap/GetFeatureInfo, but must be WMS if given
+    if let Some(service) = params
+        .get("SERVICE")
+        .filter(|s| !s.eq_ignore_ascii_case("WMS"))
+    {
+        return exception(None, &format!("Unknown service '{}'", service)).into_response();
+    }
+
+    let result = match params.get("REQUEST") {
+        Some(r) if r.eq_ignore_ascii_case("GetCapabilities") => {
+            let mut layers = reader.list_layers().await;
+            layers.sort_by_key(|l| l.layer.to_lowercase());
+            Ok((
+                [(header::CONTENT_TYPE, "text/xml")],
+                capabilities_xml(&layers, &base_url(&headers)),
+            )
+                .into_response())
+        }
+        Some(r) if r.eq_ignore_ascii_case("GetMap") => get_map(&reader, &params).await,
+        Some(r) if r.eq_ignore_ascii_case("GetFeatureInfo") => {
+            get_feature_info(&reader, &params).await
+        }
+        Some(r) => Err(exception(
+            Some("OperationNotSupported"),
+            &format!("Unsupported request '{}'", r),
+        )),
+        None => Err(exception(None, "Missing parameter REQUEST")),
+    };
+    result.unwrap_or_else(|e| e.into_response())
+}
+
+async fn get_map(
+    reader: &Arc<dyn TileReader>,
+    params: &Params,
+) -> Result<Response, ServiceException> {
+    if let Some(format) = params.get("FORMAT").filter(|f| *f != "image/png") {
+        return Err(exception(
+            Some("InvalidFormat"),
+            &format!("Unsupported format '{}'", format),
+        ));
+    }
+    let available = reader.list_layers().await;
+    let layers = params.layers("LAYERS", &available)?;
+    check_styles(params, &layers)?;
+    let (crs, extent) = params.crs_and_bbox()?;
+    let size = (params.size("WIDTH")?, params.size("HEIGHT")?);
+
+    // Layers are drawn bottom to top in the order given
+    let mut img = RgbaImage::new(size.0 as u32, size.1 as u32);
+    let transparent = params
+        .get("TRANSPARENT")
+        .is_some_and(|t| t.eq_ignore_ascii_case("TRUE"));
+    if !transparent {
+        let colour = params
+            .get("BGCOLOR")
+            .and_then(|c| u32::from_str_radix(c.trim_start_matches("0x"), 16).ok())
+            .unwrap_or(0xFFFFFF);
+        let [_, r, g, b] = colour.to_be_bytes();
+        img.pixels_mut().for_each(|p| *p = Rgba([r, g, b, 255]));
+    }
+    for layer in layers {
+        let png = reader
+            .get_map(
+                &layer.layer,
+                extent.clone(),
+                crs,
+                size,
+                &RenderOptions::default(),
+            )
+            .await
+            .map_err(|e| exception(None, &e))?;
+        let rendered = image::load_from_memory(&png.bytes)
+            .map_err(|e| exception(None, &e.to_string()))?
+            .to_rgba8();
+        imageops::overlay(&mut img, &rendered, 0, 0);
+    }
+
+    let mut bytes = Vec::new();
+    img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .map_err(|e| exception(None, &e.to_string()))?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], bytes).into_response())
+}
+
+/// Each layer has one style, named after its folder. Empty means default.
+fn check_styles(params: &Params, layers: &[&Layer]) -> Result<(), ServiceException> {
+    let styles = params.get("STYLES").unwrap_or_default();
+    if styles.is_empty() {
+        return Ok(());
+    }
+    for (style, layer) in styles.split(',').zip(layers) {
+        if !style.is_empty() && style != layer.style && style != "default" {
+            return Err(exception(
+                Some("StyleNotDefined"),
+                &format!("Layer '{}' has no style '{}'", layer.layer, style),
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn get_feature_info(
+    reader: &Arc<dyn TileReader>,
+    params: &Params,
+) -> Result<Response, ServiceException> {
+    let available = reader.list_layers().await;
+    let layers = params.layers("QUERY_LAYERS", &available)?;
+    let (crs, extent) = params.crs_and_bbox()?;
+    let (width, height) = (params.size("WIDTH")?, params.size("HEIGHT")?);
+
+    // 1.3.0 calls the pixel I/J, 1.1.1 X/Y
+    let pixel = |names: [&str; 2]| {
+        names
+            .iter()
+            .find_map(|n| params.get(n))
+            .and_then(|v| v.parse::<usize>().ok())
+    };
+    let (Some(i), Some(j)) = (pixel(["I", "X"]), pixel(["J", "Y"])) else {
+        return Err(exception(None, "Missing or invalid parameter I/J"));
+    };
+    if i >= width || j >= height {
+        return Err(exception(Some("InvalidPoint"), "I/J is outside the image"));
+    }
+
+    // Centre of the pixel clicked
+    let x = extent.minx + (i as f64 + 0.5) * (extent.maxx - extent.minx) / width as f64;
+    let y = extent.maxy - (j as f64 + 0.5) * (extent.maxy - extent.miny) / height as f64;
+
+    let mut features = Vec::new();
+    for layer in layers {
+        let Some(sample) = reader
+            .get_point(&layer.layer, x, y, crs)
+            .await
+            .map_err(|e| exception(None, &e))?
+        else {
+            continue;
+        };
+        let values: Vec<Option<f64>> = sample.values.iter().map(BandValue::data).collect();
+        features.push((layer.layer.clone(), values));
+    }
+
+    let info_format = params.get("INFO_FORMAT").unwrap_or("application/json");
+    match info_format {
+        "application/json" | "application/geo+json" => {
+            let features: Vec<Value> = features
+                .into_iter()
+                .map(|(layer, values)| {
+                    let mut properties = Map::new();
+                    properties.insert("layer".into(), json!(layer));
+                    for (b, value) in values.iter().enumerate() {
+                        properties.insert(format!("band_{}", b + 1), json!(value));
+                    }
+                    json!({
+                        "type": "Feature",
+                        "id": layer,
+                        "geometry": null,
+                        "properties": properties,
+                    })
+                })
+                .collect();
+            let body = json!({ "type": "FeatureCollection", "features": features });
+            Ok((
+                [(header::CONTENT_TYPE, info_format.to_string())],
+                body.to_string(),
+            )
+                .into_response())
+        }
+        "text/plain" => {
+            let mut body = String::new();
+            for (layer, values) in features {
+                let _ = writeln!(body, "Layer '{}'", layer);
+                for (b, value) in values.iter().enumerate() {
+                    let value = value.map(|v| v.to_string());
+                    let _ = writeln!(
+                        body,
+                        "  band_{} = {}",
+                        b + 1,
+                        value.as_deref().unwrap_or("nodata")
+                    );
+                }
+            }
+            Ok(([(header::CONTENT_TYPE, "text/plain")], body).into_response())
+        }
+        other => Err(exception(
+            Some("InvalidFormat"),
+            &format!("Unsupported INFO_FORMAT '{}'", other),
+        )),
+    }
+}
+
+/// The GetCapabilities document for the given layers
+pub(crate) fn capabilities_xml(layers: &[Layer], base_url: &str) -> String {
+    let href = escape(&format!("{}/wms?", base_url));
+    let dcp = format!(
+        r#"<DCPType><HTTP><Get><OnlineResource xlink:type="simple" xlink:href="{}"/></Get></HTTP></DCPType>"#,
+        href
+    );
+    let mut xml = String::new();
+
+    let _ = write!(
+        xml,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<WMS_Capabilities version="1.3.0" xmlns="http://www.opengis.net/wms" xmlns:xlink="http://www.w3.org/1999/xlink" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.opengis.net/wms http://schemas.opengis.net/wms/1.3.0/capabilities_1_3_0.xsd">
+  <Service>
+    <Name>WMS</Name>
+    <Title>TileYolo</Title>
+    <OnlineResource xlink:type="simple" xlink:href="{href}"/>
+    <LayerLimit>{MAX_LAYERS}</LayerLimit>
+    <MaxWidth>{MAX_SIZE}</MaxWidth>
+    <MaxHeight>{MAX_SIZE}</MaxHeight>
+  </Service>
+  <Capability>
+    <Request>
+      <GetCapabilities>
+        <Format>text/xml</Format>
+        {dcp}
+      </GetCapabilities>
+      <GetMap>
+        <Format>image/png</Format>
+        {dcp}
+      </GetMap>
+      <GetFeatureInfo>
+        <Format>application/json</Format>
+        <Format>text/plain</Format>
+        {dcp}
+      </GetFeatureInfo>
+    </Request>
+    <Exception>
+      <Format>XML</Format>
+    </Exception>
+    <Layer>
+      <Title>TileYolo</Title>
+      <CRS>EPSG:3857</CRS>
+      <CRS>EPSG:4326</CRS>
+      <CRS>CRS:84</CRS>
+"#
+    );
+
+    for layer in layers {
+        let name = escape(&layer.layer);
+        let style = escape(&layer.style);
+        let _ = write!(
+            xml,
+            r#"      <Layer queryable="1" opaque="0">
+        <Name>{name}</Name>
+        <Title>{name}</Title>
+"#
+        );
+        if let Some(geo) = layer.cached_geometry.get(&4326) {
+            let e = &geo.extent;
+            // EPSG:4326 bounding boxes are lat/lon in 1.3.0
+            let _ = write!(
+                xml,
+                r#"        <EX_GeographicBoundingBox>
+          <westBoundLongitude>{}</westBoundLongitude>
+          <eastBoundLongitude>{}</eastBoundLongitude>
+          <southBoundLatitude>{}</southBoundLatitude>
+          <northBoundLatitude>{}</northBoundLatitude>
+        </EX_GeographicBoundingBox>
+        <BoundingBox CRS="CRS:84" minx="{}" miny="{}" maxx="{}" maxy="{}"/>
+        <BoundingBox CRS="EPSG:4326" minx="{}" miny="{}" maxx="{}" maxy="{}"/>
+"#,
+                e.minx,
+                e.maxx,
+                e.miny,
+                e.maxy,
+                e.minx,
+                e.miny,
+                e.maxx,
+                e.maxy,
+                e.miny,
+                e.minx,
+                e.maxy,
+                e.maxx
+            );
+        }
+        if let Some(merc) = layer.cached_geometry.get(&3857) {
+            let e = &merc.extent;
+            let _ = writeln!(
+                xml,
+                r#"        <BoundingBox CRS="EPSG:3857" minx="{}" miny="{}" maxx="{}" maxy="{}"/>"#,
+                e.minx, e.miny, e.maxx, e.maxy
+            );
+        }
+        let _ = write!(
+            xml,
+            r#"        <Style>
+          <Name>{style}</Name>
+          <Title>{style}</Title>
+        </Style>
+      </Layer>
+"#
+        );
+    }
+
+    xml.push_str("    </Layer>\n  </Capability>\n</WMS_Capabilities>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::wmts::tests::make_layer;
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        Params(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_axis_order() {
+        // 1.3.0 EPSG:4326 is lat/lon
+        let (crs, extent) = params(&[("CRS", "EPSG:4326"), ("BBOX", "-45,110,-10,155")])
+            .crs_and_bbox()
+            .unwrap();
+        assert_eq!(crs, 4326);
+        assert_eq!(
+            (extent.minx, extent.miny, extent.maxx, extent.maxy),
+            (110.0, -45.0, 155.0, -10.0)
+        );
+
+        // CRS:84 and 1.1.1 SRS are lon/lat
+        for p in [
+            params(&[("CRS", "CRS:84"), ("BBOX", "110,-45,155,-10")]),
+            params(&[("SRS", "EPSG:4326"), ("BBOX", "110,-45,155,-10")]),
+        ] {
+            let (_, extent) = p.crs_and_bbox().unwrap();
+            assert_eq!((extent.minx, extent.miny), (110.0, -45.0));
+        }
+
+        let (crs, extent) = params(&[("CRS", "EPSG:3857"), ("BBOX", "0,1,2,3")])
+            .crs_and_bbox()
+            .unwrap();
+        assert_eq!((crs, extent.maxx), (3857, 2.0));
+    }
+
+    #[test]
+    fn test_bad_requests() {
+        assert!(
+            params(&[("CRS", "EPSG:28355"), ("BBOX", "0,0,1,1")])
+                .crs_and_bbox()
+                .is_err()
+        );
+        assert!(
+            params(&[("CRS", "EPSG:3857"), ("BBOX", "0,0,1")])
+                .crs_and_bbox()
+                .is_err()
+        );
+        assert!(
+            params(&[("CRS", "EPSG:3857"), ("BBOX", "1,0,0,1")])
+                .crs_and_bbox()
+                .is_err()
+        );
+        assert!(params(&[("WIDTH", "0")]).size("WIDTH").is_err());
+        assert!(params(&[("WIDTH", "5000")]).size("WIDTH").is_err());
+        assert_eq!(params(&[("WIDTH", "800")]).size("WIDTH").ok(), Some(800));
+
+        let available = [make_layer("rain")];
+        let rain = ["rain"; MAX_LAYERS].join(",");
+        assert!(
+            params(&[("LAYERS", &rain)])
+                .layers("LAYERS", &available)
+                .is_ok()
+        );
+        let too_many = format!("{},rain", rain);
+        assert!(
+            params(&[("LAYERS", &too_many)])
+                .layers("LAYERS", &available)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_capabilities_xml() {
+        let xml = capabilities_xml(&[make_layer("rain&temp")], "https://maps.example.com");
+
+        assert!(xml.contains("<Name>rain&amp;temp</Name>"));
+        assert!(xml.contains("xlink:href=\"https://maps.example.com/wms?\""));
+        assert!(xml.contains("<LayerLimit>8</LayerLimit>"));
+        assert!(xml.contains("<westBoundLongitude>110</westBoundLongitude>"));
+        // 1.3.0 EPSG:4326 bounding boxes are lat/lon, CRS:84 lon/lat
+        assert!(xml.contains(
+            "<BoundingBox CRS=\"EPSG:4326\" minx=\"-45\" miny=\"110\" maxx=\"-10\" maxy=\"155\"/>"
+        ));
+        assert!(xml.contains(
+            "<BoundingBox CRS=\"CRS:84\" minx=\"110\" miny=\"-45\" maxx=\"155\" maxy=\"-10\"/>"
+        ));
+        assert!(xml.contains("<BoundingBox CRS=\"EPSG:3857\""));
+        assert!(xml.contains("<Name>viridis</Name>"));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::Config;
+use crate::endpoints::{
+    handlers::render_tile,
+    urls::{base_url, encode_segment},
+};
+use crate::geometry::projection::MAX_ZOOM;
+use crate::models::{layer::Layer, render::RenderOptions, tms::TileMatrixSet};
+use crate::traits::TileReader;
+use crate::utils::xml::escape;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+const TILE_MATRIX_SET: &str = "GoogleMapsCompatible";
+const TILE_SIZE: u32 = 256;
+// OGC scale denominator of zoom 0 in GoogleMapsCompatible (0.28mm pixels)
+const SCALE_DENOMINATOR_Z0: f64 = 559_082_264.028_717_8;
+const ORIGIN: f64 = 20_037_508.342_789_244;
+
+/// An OWS ExceptionReport, how WMTS clients expect errors
+fn exception(status: StatusCode, code: &str, locator: &str, text: &str) -> Response {
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<ExceptionReport xmlns="http://www.opengis.net/ows/1.1" version="1.1.0" xml:lang="en">
+  <Exception exceptionCode="{}" locator="{}">
+    <ExceptionText>{}</ExceptionText>
+  </Exception>
+</ExceptionReport>
+"#,
+        code,
+        escape(locator),
+        escape(text)
+    );
+    (status, [(header::CONTENT_TYPE, "application/xml")], body).into_response()
+}
+
+fn missing(param: &str) -> Response {
+    exception(
+        StatusCode::BAD_REQUEST,
+        "MissingParameterValue",
+        param,
+        &format!("Missing parameter {}", param),
+    )
+}
+
+fn invalid(param: &str, text: String) -> Response {
+    exception(
+        StatusCode::BAD_REQUEST,
+        "InvalidParameterValue",
+        param,
+        &text,
+    )
+}
+
+/// KVP binding: /wmts?SERVICE=WMTS&REQUEST=GetCapabilities|GetTile&...
+pub async fn wmts_kvp_handler(
+    State(reader): State<Arc<dyn TileReader>>,
+    State(config): State<Arc<Config>>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    // KVP parameter names are case insensitive
+    let params: HashMap<String, String> = params
+        .into_iter()
+        .map(|(k, v)| (k.to_ascii_uppercase(), v))
+        .collect();
+    let param = |name: &str| params.get(name).map(String::as_str);
+
+    match param("SERVICE") {
+        Some(s) if s.eq_ignore_ascii_case("WMTS") => {}
+        Some(s) => return invalid("SERVICE", format!("Unknown service '{}'", s)),
+        None => return missing("SERVICE"),
+    }
+
+    match param("REQUEST") {
+        Some(r) if r.eq_ignore_ascii_case("GetCapabilities") => {
+            capabilities_response(&reader, &headers).await
+        }
+        Some(r) if r.eq_ignore_ascii_case("GetTile") => {
+            let mut values = Vec::new();
+            for name in [
+                "LAYER",
+                "STYLE",
+                "TILEMATRIXSET",
+                "TILEMATRIX",
+                "TILEROW",
+                "TILECOL",
+            ] {
+                match param(name) {
+                    Some(v) => values.push(v),
+                    None => return missing(name),
+                }
+            }
+            if let Some(format) = param("FORMAT").filter(|f| *f != "image/png") {
+                return invalid("FORMAT", format!("Unsupported format '{}'", format));
+            }
+            get_tile(
+                &reader, &config, values[0], values[1], values[2], values[3], values[4], values[5],
+                &headers,
+            )
+            .await
+        }
+        Some(r) => exception(
+            StatusCode::NOT_IMPLEMENTED,
+            "OperationNotSupported",
+            "REQUEST",
+            &format!("Unsupported request '{}'", r),
+        ),
+        None => missing("REQUEST"),
+    }
+}
+
+/// RESTful binding: /wmts/1.0.0/WMTSCapabilities.xml
+pub async fn wmts_capabilities_handler(
+    State(reader): State<Arc<dyn TileReader>>,
+    headers: HeaderMap,
+) -> Response {
+    capabilities_response(&reader, &headers).await
+}
+
+/// RESTful binding: /wmts/1.0.0/{layer}/{style}/{TileMatrixSet}/{TileMatrix}/{TileRow}/{TileCol}.png
+pub async fn wmts_tile_handler(
+    Path((layer, style, tms, matrix, row, col)): Path<(
+        String,
+        String,
+        String,
+        String,
+        String,
+        String,
+    )>,
+    State(reader): State<Arc<dyn TileReader>>,
+    State(config): State<Arc<Config>>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(col) = col.strip_suffix(".png") else {
+        return invalid("Format", "Only .png tiles are served".to_string());
+    };
+    get_tile(
+        &reader, &config, &layer, &style, &tms, &matrix, &row, col, &headers,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn get_tile(
+    reader: &Arc<dyn TileReader>,
+    config: &Config,
+    layer: &str,
+    style: &str,
+    tms: &str,
+    matrix: &str,
+    row: &str,
+    col: &str,
+    headers: &HeaderMap,
+) -> Response {
+    let Some(layer_obj) = reader.get_layer(layer).await else {
+        return invalid("LAYER", format!("Unknown layer '{}'", layer));
+    };
+    // Each layer has exactly one style, "default" is accepted for clients
+    // that don't read it from the capabilities
+    if style != layer_obj.style && style != "default" {
+        return invalid("STYLE", format!("Unknown style '{}'", style));
+    }
+    if tms != TILE_MATRIX_SET {
+        return invalid(
+            "TILEMATRIXSET",
+            format!("Unknown tile matrix set '{}'", tms),
+        );
+    }
+    let Some(z) = matrix.parse::<u8>().ok().filter(|z| *z <= MAX_ZOOM) else {
+        return invalid("TILEMATRIX", format!("Unknown tile matrix '{}'", matrix));
+    };
+
+    let size = 1u32 << z;
+    let (Ok(y), Ok(x)) = (row.parse::<u32>(), col.parse::<u32>()) else {
+        return invalid(
+            "TILEROW",
+            "TILEROW and TILECOL must be integers".to_string(),
+        );
+    };
+    if y >= size || x >= size {
+        return exception(
+            StatusCode::BAD_REQUEST,
+            "TileOutOfRange",
+            if y >= size { "TILEROW" } else { "TILECOL" },
+            &format!("Tile matrix {} is {}×{} tiles", z, size, size),
+        );
+    }
+
+    // GoogleMapsCompatible is the WebMercatorQuad grid
+    render_tile(
+        reader,
+        config,
+        &layer_obj,
+        &TileMatrixSet::web_mercator_quad(),
+        (z, x, y),
+        &RenderOptions::default(),
+        headers,
+    )
+    .await
+}
+
+async fn capabilities_response(reader: &Arc<dyn TileReader>, headers: &HeaderMap) -> Response {
+    let mut layers = reader.list_layers().await;
+    layers.sort_by_key(|l| l.layer.to_lowercase());
+    let body = capabilities_xml(&layers, &base_url(headers));
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/xml")],
+        body,
+    )
+        .into_response()
+}
+
+/// The GetCapabilities document for the given layers
+pub(crate) fn capabilities_xml(layers: &[Layer], base_url: &str) -> String {
+    let kvp_url = escape(&format!("{}/wmts?", base_url));
+    let mut xml = String::new();
+
+    let _ = write!(
+        xml,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<Capabilities xmlns="http://www.opengis.net/wmts/1.0" xmlns:ows="http://www.opengis.net/ows/1.1" xmlns:xlink="http://www.w3.org/1999/xlink" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.opengis.net/wmts/1.0 http://schemas.opengis.net/wmts/1.0/wmtsGetCapabilities_response.xsd" version="1.0.0">
+  <ows:ServiceIdentification>
+    <ows:Title>TileYolo</ows:Title>
+    <ows:ServiceType>OGC WMTS</ows:ServiceType>
+    <ows:ServiceTypeVersion>1.0.0</ows:ServiceTypeVersion>
+  </ows:ServiceIdentification>
+  <ows:OperationsMetadata>
+"#
+    );
+    for operation in ["GetCapabilities", "GetTile"] {
+        let _ = write!(
+            xml,
+            r#"    <ows:Operation name="{}">
+      <ows:DCP>
+        <ows:HTTP>
+          <ows:Get xlink:href="{}">
+            <ows:Constraint name="GetEncoding">
+              <ows:AllowedValues>
+                <ows:Value>KVP</ows:Value>
+              </ows:AllowedValues>
+            </ows:Constraint>
+          </ows:Get>
+        </ows:HTTP>
+      </ows:DCP>
+    </ows:Operation>
+"#,
+            operation, kvp_url
+        );
+    }
+    xml.push_str("  </ows:OperationsMetadata>\n  <Contents>\n");
+
+    for layer in layers {
+        let name = escape(&layer.layer);
+        let style = escape(&layer.style);
+        let _ = write!(
+            xml,
+            r#"    <Layer>
+      <ows:Title>{name}</ows:Title>
+"#
+        );
+        if let Some(bbox) = layer.cached_geometry.get(&4326) {
+            let e = &bbox.extent;
+            let _ = write!(
+                xml,
+                r#"      <ows:WGS84BoundingBox>
+        <ows:LowerCorner>{} {}</ows:LowerCorner>
+        <ows:UpperCorner>{} {}</ows:UpperCorner>
+      </ows:WGS84BoundingBox>
+"#,
+                e.minx, e.miny, e.maxx, e.maxy
+            );
+        }
+        let template = escape(&format!(
+            "{}/wmts/1.0.0/{}/{{Style}}/{{TileMatrixSet}}/{{TileMatrix}}/{{TileRow}}/{{TileCol}}.png",
+            base_url,
+            encode_segment(&layer.layer)
+        ));
+        let _ = write!(
+            xml,
+            r#"      <ows:Identifier>{name}</ows:Identifier>
+      <Style isDefault="true">
+        <ows:Identifier>{style}</ows:Identifier>
+      </Style>
+      <Format>image/png</Format>
+      <TileMatrixSetLink>
+        <TileMatrixSet>{TILE_MATRIX_SET}</TileMatrixSet>
+      </TileMatrixSetLink>
+      <ResourceURL format="image/png" resourceType="tile" template="{template}"/>
+    </Layer>
+"#
+        );
+    }
+
+    let _ = write!(
+        xml,
+        r#"    <TileMatrixSet>
+      <ows:Identifier>{TILE_MATRIX_SET}</ows:Identifier>
+      <ows:SupportedCRS>urn:ogc:def:crs:EPSG::3857</ows:SupportedCRS>
+      <WellKnownScaleSet>urn:ogc:def:wkss:OGC:1.0:GoogleMapsCompatible</WellKnownScaleSet>
+"#
+    );
+    for z in 0..=MAX_ZOOM {
+        let size = 1u64 << z;
+        let _ = write!(
+            xml,
+            r#"      <TileMatrix>
+        <ows:Identifier>{z}</ows:Identifier>
+        <ScaleDenominator>{}</ScaleDenominator>
+        <TopLeftCorner>{} {}</TopLeftCorner>
+        <TileWidth>{TILE_SIZE}</TileWidth>
+        <TileHeight>{TILE_SIZE}</TileHeight>
+        <MatrixWidth>{size}</MatrixWidth>
+        <MatrixHeight>{size}</MatrixHeight>
+      </TileMatrix>
+"#,
+            SCALE_DENOMINATOR_Z0 / size as f64,
+            -ORIGIN,
+            ORIGIN
+        );
+    }
+    let _ = write!(
+        xml,
+        r#"    </TileMatrixSet>
+  </Contents>
+  <ServiceMetadataURL xlink:href="{}"/>
+</Capabilities>
+"#,
+        escape(&format!("{}/wmts/1.0.0/WMTSCapabilities.xml", base_url))
+    );
+
+    xml
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::models::{
+        geometry::GeometryExtent, layer::LayerGeometry, render::RenderOptions, style::Interpolation,
+    };
+    use std::path::PathBuf;
+
+    /// A 4326 layer over Australia, shared with the other endpoint tests
+    pub(crate) fn make_layer(name: &str) -> Layer {
+        let source_geometry = LayerGeometry {
+            crs_code: 4326,
+            extent: GeometryExtent {
+                minx: 110.0,
+                miny: -45.0,
+                maxx: 155.0,
+                maxy: -10.0,
+            },
+        };
+        Layer {
+            layer: name.to_string(),
+            style: "viridis".to_string(),
+            path: PathBuf::from(format!("/data/viridis/{}.tif", name)),
+            size_bytes: 0,
+            cached_geometry: source_geometry.generate_cached_geometry_sync().unwrap(),
+            source_geometry,
+            raster_size: (4500, 3500), // 0.01°
+            colour_stops: Vec::new(),
+            interpolation: Interpolation::Interpolated,
+            min_value: 0.0,
+            max_value: 1.0,
+            is_cog: true,
+            last_modified: std::time::SystemTime::UNIX_EPOCH,
+            render: RenderOptions::default(),
+            cache: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_capabilities_xml() {
+        let xml = capabilities_xml(&[make_layer("rain&temp")], "https://maps.example.com");
+
+        assert!(xml.contains("<ows:Identifier>rain&amp;temp</ows:Identifier>"));
+        assert!(xml.contains("<ows:LowerCorner>110 -45</ows:LowerCorner>"));
+        assert!(xml.contains("<ows:Identifier>viridis</ows:Identifier>"));
+        assert!(xml.contains(
+            "template=\"https://maps.example.com/wmts/1.0.0/rain%26temp/{Style}/{TileMatrixSet}/{TileMatrix}/{TileRow}/{TileCol}.png\""
+        ));
+        assert!(xml.contains("<ows:Get xlink:href=\"https://maps.example.com/wmts?\">"));
+
+        // One matrix per zoom level, halving the scale each time
+        assert_eq!(xml.matches("<TileMatrix>").count(), MAX_ZOOM as usize + 1);
+        assert!(xml.contains("<ScaleDenominator>559082264.0287178</ScaleDenominator>"));
+        assert!(xml.contains("<ScaleDenominator>279541132.0143589</ScaleDenominator>"));
+        assert!(xml.contains("<MatrixWidth>4</MatrixWidth>"));
+    }
+}
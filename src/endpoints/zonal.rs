@@ -0,0 +1,146 @@
+use crate::endpoints::point::parse_crs;
+use crate::models::{geojson::GeoJson, render::Terrain, responses::ZonalStats};
+use crate::reader::zonal::ZonalOptions;
+use crate::traits::TileReader;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const DEFAULT_PERCENTILES: [f64; 5] = [5.0, 25.0, 50.0, 75.0, 95.0];
+const DEFAULT_BINS: usize = 10;
+const MAX_BINS: usize = 1000;
+
+/// `{"geometry": <Polygon, MultiPolygon or Feature>, "crs": "EPSG:4326",
+/// "band": 1, "percentiles": [5, 50, 95], "bins": 10, "terrain": "slope"}`,
+/// all but the geometry optional
+#[derive(Debug, Deserialize)]
+pub struct ZonalRequest {
+    geometry: GeoJson,
+    crs: Option<String>,
+    band: Option<usize>,
+    percentiles: Option<Vec<f64>>,
+    bins: Option<usize>,
+    terrain: Option<Terrain>,
+}
+
+#[derive(Serialize)]
+struct ZonalResponse {
+    layer: String,
+    crs: String,
+    #[serde(flatten)]
+    stats: ZonalStats,
+}
+
+impl ZonalRequest {
+    fn options(&self) -> Result<ZonalOptions, String> {
+        let percentiles = self
+            .percentiles
+            .clone()
+            .unwrap_or_else(|| DEFAULT_PERCENTILES.to_vec());
+        if let Some(p) = percentiles.iter().find(|p| !(0.0..=100.0).contains(*p)) {
+            return Err(format!("Percentile {} is not between 0 and 100", p));
+        }
+        let bins = self.bins.unwrap_or(DEFAULT_BINS);
+        if bins > MAX_BINS {
+            return Err(format!("At most {} histogram bins", MAX_BINS));
+        }
+        Ok(ZonalOptions {
+            band: self.band.unwrap_or(1),
+            percentiles,
+            bins,
+            terrain: self.terrain,
+        })
+    }
+}
+
+/// Statistics of the source pixels inside a polygon: POST /stats/{layer}
+/// with a ZonalRequest
+pub async fn zonal_stats_handler(
+    Path(layer): Path<String>,
+    State(reader): State<Arc<dyn TileReader>>,
+    Json(request): Json<ZonalRequest>,
+) -> Response {
+    let parsed = request.options().and_then(|options| {
+        Ok((
+            request.geometry.polygons()?,
+            parse_crs(request.crs.as_deref())?,
+            options,
+        ))
+    });
+    let (polygons, crs, options) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    if reader.get_layer(&layer).await.is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Layer not found: '{}'", layer),
+        )
+            .into_response();
+    }
+    match reader.get_zonal_stats(&layer, polygons, crs, options).await {
+        Ok(stats) => Json(ZonalResponse {
+            layer,
+            crs: format!("EPSG:{}", crs),
+            stats,
+        })
+        .into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: serde_json::Value) -> ZonalRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_zonal_request() {
+        let square = serde_json::json!({
+            "type": "Polygon",
+            "coordinates": [[[147, -43], [148, -43], [148, -42], [147, -43]]],
+        });
+        let options = request(serde_json::json!({ "geometry": square }))
+            .options()
+            .unwrap();
+        assert_eq!(options.band, 1);
+        assert_eq!(options.percentiles, DEFAULT_PERCENTILES.to_vec());
+        assert_eq!(options.bins, DEFAULT_BINS);
+
+        let options = request(serde_json::json!({
+            "geometry": square, "band": 2, "percentiles": [], "bins": 0,
+        }))
+        .options()
+        .unwrap();
+        assert_eq!((options.band, options.bins), (2, 0));
+        assert!(options.percentiles.is_empty());
+        assert_eq!(options.terrain, None);
+
+        let options = request(serde_json::json!({ "geometry": square, "terrain": "aspect" }))
+            .options()
+            .unwrap();
+        assert_eq!(options.terrain, Some(Terrain::Aspect));
+        assert!(
+            serde_json::from_value::<ZonalRequest>(
+                serde_json::json!({ "geometry": square, "terrain": "curvature" })
+            )
+            .is_err()
+        );
+
+        for bad in [
+            serde_json::json!({ "geometry": square, "percentiles": [101] }),
+            serde_json::json!({ "geometry": square, "bins": 5000 }),
+        ] {
+            assert!(request(bad).options().is_err());
+        }
+    }
+}
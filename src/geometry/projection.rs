@@ -3,6 +3,10 @@ use std::f64::consts::PI;
 /// WebMercator constants
 const R_MAJOR: f64 = 6378137.0;
 const MAX_LAT: f64 = 85.05112877980659; // Max bounds for Web Mercator
+pub const WORLD_WIDTH: f64 = 2.0 * PI * R_MAJOR; // Web Mercator x span in meters
+
+/// Deepest zoom level the tile services advertise
+pub const MAX_ZOOM: u8 = 22;
 
 /// from longitude, latitude (degrees) → Web Mercator (x, y in meters)
 pub fn lon_lat_to_mercator(lon: f64, lat: f64) -> (f64, f64) {
@@ -23,6 +27,51 @@ pub fn mercator_to_lon_lat(x: f64, y: f64) -> (f64, f64) {
     (lon, lat)
 }
 
+/// Mean earth radius for great circle distances, in meters
+const R_MEAN: f64 = 6371008.8;
+
+/// Great circle distance in meters between two lon/lat points
+pub fn haversine_distance((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = phi2 - phi1;
+    let d_lambda = (lon2 - lon1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * R_MEAN * a.sqrt().asin()
+}
+
+/// Converts points between two EPSG codes (x/y in lon/lat order): the
+/// closed forms above between 4326 and 3857, PROJ for anything else
+pub enum Projector {
+    Identity,
+    LonLatToMercator,
+    MercatorToLonLat,
+    Proj(proj::Proj),
+}
+
+impl Projector {
+    pub fn new(from_crs: i32, to_crs: i32) -> anyhow::Result<Self> {
+        Ok(match (from_crs, to_crs) {
+            (from, to) if from == to => Projector::Identity,
+            (4326, 3857) => Projector::LonLatToMercator,
+            (3857, 4326) => Projector::MercatorToLonLat,
+            _ => Projector::Proj(proj::Proj::new_known_crs(
+                &format!("EPSG:{}", from_crs),
+                &format!("EPSG:{}", to_crs),
+                None,
+            )?),
+        })
+    }
+
+    pub fn convert(&self, (x, y): (f64, f64)) -> anyhow::Result<(f64, f64)> {
+        Ok(match self {
+            Projector::Identity => (x, y),
+            Projector::LonLatToMercator => lon_lat_to_mercator(x, y),
+            Projector::MercatorToLonLat => mercator_to_lon_lat(x, y),
+            Projector::Proj(proj) => proj.convert((x, y))?,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,4 +132,53 @@ mod tests {
             assert!(approx_eq(lat1, lat2));
         }
     }
+
+    #[test]
+    fn test_haversine_distance() {
+        assert!(approx_eq(
+            haversine_distance((147.0, -42.0), (147.0, -42.0)),
+            0.0
+        ));
+        // One degree along the equator and along a meridian
+        let degree = R_MEAN * PI / 180.0;
+        assert!(approx_eq(
+            haversine_distance((0.0, 0.0), (1.0, 0.0)),
+            degree
+        ));
+        assert!(approx_eq(
+            haversine_distance((10.0, 45.0), (10.0, 46.0)),
+            degree
+        ));
+        // Half the world
+        assert!(approx_eq(
+            haversine_distance((0.0, 0.0), (180.0, 0.0)),
+            PI * R_MEAN
+        ));
+    }
+
+    #[test]
+    fn test_projector() {
+        let (lon, lat) = (147.3, -42.9);
+        let merc = Projector::new(4326, 3857)
+            .unwrap()
+            .convert((lon, lat))
+            .unwrap();
+        assert_eq!(merc, lon_lat_to_mercator(lon, lat));
+        let (lon2, lat2) = Projector::new(3857, 4326).unwrap().convert(merc).unwrap();
+        assert!(approx_eq(lon, lon2) && approx_eq(lat, lat2));
+        assert_eq!(
+            Projector::new(3031, 3031)
+                .unwrap()
+                .convert((1.0, 2.0))
+                .unwrap(),
+            (1.0, 2.0)
+        );
+
+        // South pole in Antarctic Polar Stereographic is the origin
+        let (x, y) = Projector::new(4326, 3031)
+            .unwrap()
+            .convert((0.0, -90.0))
+            .unwrap();
+        assert!(x.abs() < 1e-3 && y.abs() < 1e-3);
+    }
 }
@@ -1,3 +1,4 @@
+mod cache;
 mod config;
 mod geometry;
 mod models;
@@ -7,5 +8,5 @@ mod utils;
 pub mod endpoints;
 pub mod reader;
 
-pub use config::{Config, Source};
+pub use config::{Config, S3Options, Source};
 pub use endpoints::server::TileServer;
@@ -1,6 +1,6 @@
 use clap::Parser;
 use std::path::PathBuf;
-use tileyolo::{Config, Source, TileServer};
+use tileyolo::{Config, S3Options, Source, TileServer};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -20,6 +20,69 @@ struct Cli {
         help = "Port to run the server on"
     )]
     port: u16,
+    #[arg(
+        long,
+        default_value_t = Config::default_tile_cache_mb(),
+        value_name = "MiB",
+        help = "Size of the in-memory tile cache, 0 to disable"
+    )]
+    tile_cache_mb: u64,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Persist rendered tiles in this folder across restarts"
+    )]
+    tile_cache_dir: Option<String>,
+    #[arg(
+        long,
+        default_value_t = Config::default_tile_cache_dir_mb(),
+        value_name = "MiB",
+        help = "Size cap of the on-disk tile cache"
+    )]
+    tile_cache_dir_mb: u64,
+    #[arg(
+        long,
+        default_value_t = Config::default_cache_control_max_age(),
+        value_name = "SECONDS",
+        help = "Cache-Control max-age sent with tiles, 0 to always revalidate"
+    )]
+    cache_max_age: u32,
+    #[arg(
+        long,
+        value_name = "TEXT",
+        help = "Attribution (HTML allowed) that web clients show with the layers"
+    )]
+    attribution: Option<String>,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Folder of OGC TileMatrixSet JSON files to offer next to the built-in grids"
+    )]
+    tile_matrix_sets: Option<String>,
+    /// Serve from an S3 bucket instead of the data folder
+    #[arg(long, value_name = "BUCKET", help = "S3 bucket to serve rasters from")]
+    s3_bucket: Option<String>,
+    #[arg(
+        long,
+        default_value = "",
+        value_name = "PREFIX",
+        help = "Key prefix inside the S3 bucket (the 'data folder')"
+    )]
+    s3_prefix: String,
+    #[arg(
+        long,
+        value_name = "HOST:PORT",
+        help = "S3-compatible endpoint, e.g. localhost:9000 for MinIO"
+    )]
+    s3_endpoint: Option<String>,
+    #[arg(long, value_name = "REGION", help = "S3 region")]
+    s3_region: Option<String>,
+    #[arg(long, help = "Use plain HTTP to talk to the S3 endpoint")]
+    s3_no_https: bool,
+    #[arg(long, help = "Use path-style (endpoint/bucket/key) S3 addressing")]
+    s3_path_style: bool,
+    #[arg(long, help = "Access the S3 bucket anonymously")]
+    s3_no_sign_request: bool,
 }
 
 #[tokio::main]
@@ -27,11 +90,41 @@ async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     // Use the value from the command line argument (default from config is provided to it already)
-    let config = Config {
-        source: Some(Source::Local(Config::parse_path_to_absolute(
-            &PathBuf::from(cli.data_folder),
+    let source = match cli.s3_bucket {
+        Some(bucket) => Source::S3 {
+            bucket,
+            prefix: cli.s3_prefix,
+        },
+        None => Source::Local(Config::parse_path_to_absolute(&PathBuf::from(
+            cli.data_folder,
         ))),
+    };
+
+    // Credentials are left to GDAL (AWS_ACCESS_KEY_ID etc. or ~/.aws) so they
+    // never show up in the process list
+    let s3 = S3Options {
+        endpoint: cli.s3_endpoint,
+        region: cli.s3_region,
+        use_https: !cli.s3_no_https,
+        virtual_hosting: !cli.s3_path_style,
+        no_sign_request: cli.s3_no_sign_request,
+        ..S3Options::default()
+    };
+
+    let config = Config {
+        source: Some(source),
         port: cli.port,
+        s3,
+        tile_cache_bytes: cli.tile_cache_mb.saturating_mul(1024 * 1024),
+        tile_cache_dir: cli
+            .tile_cache_dir
+            .map(|dir| Config::parse_path_to_absolute(&PathBuf::from(dir))),
+        tile_cache_dir_bytes: cli.tile_cache_dir_mb.saturating_mul(1024 * 1024),
+        cache_control_max_age: cli.cache_max_age,
+        attribution: cli.attribution,
+        tile_matrix_set_dir: cli
+            .tile_matrix_sets
+            .map(|dir| Config::parse_path_to_absolute(&PathBuf::from(dir))),
         ..Config::default() // Then fill with the rest of the default config
     };
 
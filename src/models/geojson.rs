@@ -0,0 +1,135 @@
+use serde::Deserialize;
+
+/// A ring of x/y (lon/lat) positions, first and last the same
+pub type Ring = Vec<(f64, f64)>;
+/// An outer ring followed by its holes
+pub type Polygon = Vec<Ring>;
+
+/// The parts of GeoJSON (RFC 7946) the data API takes: a bare geometry or
+/// a Feature wrapping one. Positions may carry a z, it is dropped.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+pub enum GeoJson {
+    Feature {
+        geometry: Box<GeoJson>,
+    },
+    LineString {
+        coordinates: Vec<Vec<f64>>,
+    },
+    Polygon {
+        coordinates: Vec<Vec<Vec<f64>>>,
+    },
+    MultiPolygon {
+        coordinates: Vec<Vec<Vec<Vec<f64>>>>,
+    },
+}
+
+impl GeoJson {
+    /// The polygons of a Polygon or MultiPolygon
+    pub fn polygons(&self) -> Result<Vec<Polygon>, String> {
+        match self {
+            GeoJson::Feature { geometry } => geometry.polygons(),
+            GeoJson::Polygon { coordinates } => Ok(vec![polygon(coordinates)?]),
+            GeoJson::MultiPolygon { coordinates } if coordinates.is_empty() => {
+                Err("Empty MultiPolygon".into())
+            }
+            GeoJson::MultiPolygon { coordinates } => {
+                coordinates.iter().map(|p| polygon(p)).collect()
+            }
+            GeoJson::LineString { .. } => Err("Expected a Polygon or MultiPolygon".into()),
+        }
+    }
+
+    /// The positions of a LineString
+    pub fn line(&self) -> Result<Vec<(f64, f64)>, String> {
+        match self {
+            GeoJson::Feature { geometry } => geometry.line(),
+            GeoJson::LineString { coordinates } if coordinates.len() >= 2 => {
+                coordinates.iter().map(|p| position(p)).collect()
+            }
+            GeoJson::LineString { .. } => Err("A LineString needs 2 or more positions".into()),
+            _ => Err("Expected a LineString".into()),
+        }
+    }
+}
+
+fn polygon(rings: &[Vec<Vec<f64>>]) -> Result<Polygon, String> {
+    if rings.is_empty() {
+        return Err("A polygon needs at least an outer ring".into());
+    }
+    rings
+        .iter()
+        .map(|ring| {
+            if ring.len() < 4 || ring.first() != ring.last() {
+                return Err("Polygon rings need 4 or more positions and must be closed".into());
+            }
+            ring.iter().map(|p| position(p)).collect()
+        })
+        .collect()
+}
+
+fn position(p: &[f64]) -> Result<(f64, f64), String> {
+    match p {
+        [x, y, ..] if x.is_finite() && y.is_finite() => Ok((*x, *y)),
+        _ => Err(format!("Invalid position {:?}", p)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polygons() {
+        let square = r#"{"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 0]]]}"#;
+        let geometry: GeoJson = serde_json::from_str(square).unwrap();
+        assert_eq!(
+            geometry.polygons().unwrap(),
+            vec![vec![vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 0.0)]]]
+        );
+
+        let feature: GeoJson = serde_json::from_str(&format!(
+            r#"{{"type": "Feature", "properties": {{"name": "a"}}, "geometry": {}}}"#,
+            square
+        ))
+        .unwrap();
+        assert_eq!(feature.polygons().unwrap().len(), 1);
+
+        let multi: GeoJson = serde_json::from_str(
+            r#"{"type": "MultiPolygon", "coordinates": [
+                [[[0, 0, 5], [1, 0, 5], [1, 1, 5], [0, 0, 5]]],
+                [[[2, 2], [3, 2], [3, 3], [2, 2]], [[2.1, 2.1], [2.2, 2.1], [2.2, 2.2], [2.1, 2.1]]]
+            ]}"#,
+        )
+        .unwrap();
+        let polygons = multi.polygons().unwrap();
+        assert_eq!(polygons.len(), 2);
+        assert_eq!(polygons[0][0][1], (1.0, 0.0));
+        assert_eq!(polygons[1].len(), 2);
+
+        let open: GeoJson = serde_json::from_str(
+            r#"{"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1], [0, 1]]]}"#,
+        )
+        .unwrap();
+        assert!(open.polygons().is_err());
+        assert!(open.line().is_err());
+        assert!(
+            serde_json::from_str::<GeoJson>(r#"{"type": "Point", "coordinates": [0, 0]}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_line() {
+        let line: GeoJson = serde_json::from_str(
+            r#"{"type": "Feature", "properties": null, "geometry":
+                {"type": "LineString", "coordinates": [[147, -42, 10], [148, -42.5]]}}"#,
+        )
+        .unwrap();
+        assert_eq!(line.line().unwrap(), vec![(147.0, -42.0), (148.0, -42.5)]);
+        assert!(line.polygons().is_err());
+
+        let short: GeoJson =
+            serde_json::from_str(r#"{"type": "LineString", "coordinates": [[147, -42]]}"#).unwrap();
+        assert!(short.line().is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use serde::Serialize;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct GeometryExtent {
     pub minx: f64,
     pub miny: f64,
@@ -18,3 +19,65 @@ impl From<(f64, f64, f64, f64)> for GeometryExtent {
         }
     }
 }
+
+impl GeometryExtent {
+    /// The overlap of two extents, None if they don't overlap
+    pub fn intersection(&self, other: &GeometryExtent) -> Option<GeometryExtent> {
+        let extent = GeometryExtent {
+            minx: self.minx.max(other.minx),
+            miny: self.miny.max(other.miny),
+            maxx: self.maxx.min(other.maxx),
+            maxy: self.maxy.min(other.maxy),
+        };
+        (extent.minx < extent.maxx && extent.miny < extent.maxy).then_some(extent)
+    }
+}
+
+/// `minx,miny,maxx,maxy` as in a `?bbox=` parameter
+impl FromStr for GeometryExtent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f64> = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("bbox must be four numbers, got '{}'", s))?;
+        let [minx, miny, maxx, maxy] = values[..] else {
+            return Err(format!("bbox must be four numbers, got '{}'", s));
+        };
+        if !(minx < maxx && miny < maxy) {
+            return Err("bbox min must be less than max".into());
+        }
+        Ok(GeometryExtent::from((minx, miny, maxx, maxy)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bbox() {
+        assert_eq!(
+            "110, -45,155,-10".parse::<GeometryExtent>(),
+            Ok(GeometryExtent::from((110.0, -45.0, 155.0, -10.0)))
+        );
+        assert!("1,2,3".parse::<GeometryExtent>().is_err());
+        assert!("1,2,3,x".parse::<GeometryExtent>().is_err());
+        assert!("3,2,1,4".parse::<GeometryExtent>().is_err());
+    }
+
+    #[test]
+    fn test_intersection() {
+        let a = GeometryExtent::from((0.0, 0.0, 10.0, 10.0));
+        assert_eq!(
+            a.intersection(&GeometryExtent::from((5.0, -5.0, 15.0, 5.0))),
+            Some(GeometryExtent::from((5.0, 0.0, 10.0, 5.0)))
+        );
+        assert_eq!(
+            a.intersection(&GeometryExtent::from((10.0, 0.0, 20.0, 10.0))),
+            None
+        );
+    }
+}
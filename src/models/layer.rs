@@ -1,7 +1,24 @@
-use crate::geometry::projection::{lon_lat_to_mercator, mercator_to_lon_lat};
-use crate::models::{geometry::GeometryExtent, style::ColourStop};
+use crate::geometry::projection::{MAX_ZOOM, Projector, WORLD_WIDTH};
+use crate::models::{
+    geometry::GeometryExtent,
+    render::RenderOptions,
+    style::{ColourStop, Interpolation},
+};
+use lru::LruCache;
 use serde::Serialize;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How long a stat of a local raster is trusted, so the handler and the
+/// tile cache serving one request share it
+const SOURCE_TTL: Duration = Duration::from_secs(1);
+/// Value ranges kept per layer, one per band, expression and nodata list
+const MAX_RANGES: usize = 64;
 
 #[derive(Debug, Clone)]
 pub struct Layer {
@@ -10,12 +27,104 @@ pub struct Layer {
     pub path: PathBuf,
     pub size_bytes: u64,
     pub source_geometry: LayerGeometry,
+    pub raster_size: (usize, usize), // Width and height in pixels
     pub cached_geometry: HashMap<i32, LayerGeometry>, // Used to cache the projected extents for supplying endpoint
     pub colour_stops: Vec<ColourStop>,
+    pub interpolation: Interpolation, // How style.txt colours fall between stops
     pub min_value: f32,
     pub max_value: f32,
     pub is_cog: bool,
-    pub last_modified: std::time::SystemTime,
+    pub last_modified: SystemTime,
+    pub render: RenderOptions,  // Defaults from the layer's sidecar .json
+    pub cache: Arc<LayerCache>, // Shared by every clone of the layer
+}
+
+/// What has been worked out about a layer while serving it
+#[derive(Debug)]
+pub struct LayerCache {
+    source: Mutex<Option<(Instant, (SystemTime, u64))>>, // When it was stat'ed, and the result
+    ranges: Mutex<LruCache<String, (f32, f32)>>,
+}
+
+impl Default for LayerCache {
+    fn default() -> Self {
+        Self {
+            source: Mutex::new(None),
+            ranges: Mutex::new(LruCache::new(NonZeroUsize::new(MAX_RANGES).unwrap())),
+        }
+    }
+}
+
+impl Layer {
+    /// The mtime and size of the raster right now. Local files are re-stat'ed,
+    /// at most once every SOURCE_TTL, so a file replaced while the server
+    /// runs is picked up. Remote ones (/vsis3/) keep the values read at
+    /// startup.
+    pub async fn current_source(&self) -> (SystemTime, u64) {
+        let startup = (self.last_modified, self.size_bytes);
+        if self.is_remote() {
+            return startup;
+        }
+        let checked = *self.cache.source.lock().unwrap();
+        if let Some((_, source)) = checked.filter(|(at, _)| at.elapsed() < SOURCE_TTL) {
+            return source;
+        }
+
+        let source = tokio::fs::metadata(&self.path)
+            .await
+            .ok()
+            .and_then(|m| Some((m.modified().ok()?, m.len())))
+            .unwrap_or(startup);
+        *self.cache.source.lock().unwrap() = Some((Instant::now(), source));
+        source
+    }
+
+    /// A value range that is the same for every tile, such as a band's
+    /// min/max, worked out by `compute` the first time `key` is asked for
+    pub fn cached_range<E>(
+        &self,
+        key: &str,
+        compute: impl FnOnce() -> Result<(f32, f32), E>,
+    ) -> Result<(f32, f32), E> {
+        if let Some(range) = self.cache.ranges.lock().unwrap().get(key) {
+            return Ok(*range);
+        }
+        let range = compute()?;
+        self.cache
+            .ranges
+            .lock()
+            .unwrap()
+            .put(key.to_string(), range);
+        Ok(range)
+    }
+
+    /// Paths in GDAL's virtual file systems, such as /vsis3/, aren't local files
+    pub fn is_remote(&self) -> bool {
+        self.path.to_string_lossy().starts_with("/vsi")
+    }
+
+    /// Zoom levels worth requesting as 256px Web Mercator tiles: from where
+    /// the whole layer fits in about one tile, to the first level with pixels
+    /// at least as fine as the raster's own.
+    pub fn zoom_range(&self) -> (u8, u8) {
+        let Some(merc) = self.cached_geometry.get(&3857) else {
+            return (0, MAX_ZOOM);
+        };
+        let (width, height) = (
+            merc.extent.maxx - merc.extent.minx,
+            merc.extent.maxy - merc.extent.miny,
+        );
+        let resolution = width / self.raster_size.0 as f64;
+        if !(resolution.is_finite() && resolution > 0.0 && height > 0.0) {
+            return (0, MAX_ZOOM);
+        }
+
+        // Nudged so exact powers of two don't tip over on rounding error
+        let zoom = |ratio: f64| ratio.log2().clamp(0.0, MAX_ZOOM as f64);
+        let maxzoom = (zoom(WORLD_WIDTH / 256.0 / resolution) - 1e-6).ceil() as u8;
+        let minzoom = (zoom(WORLD_WIDTH / width.max(height)) + 1e-6).floor() as u8;
+        (minzoom.min(maxzoom), maxzoom)
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -32,36 +141,10 @@ impl LayerGeometry {
             return Ok(self.clone());
         }
 
-        let new_extent = match (self.crs_code, target_crs) {
-            (4326, 3857) => {
-                // lon/lat → WebMercator
-                let (minx, miny) = lon_lat_to_mercator(self.extent.minx, self.extent.miny);
-                let (maxx, maxy) = lon_lat_to_mercator(self.extent.maxx, self.extent.maxy);
-                GeometryExtent::from((minx, miny, maxx, maxy))
-            }
-            (3857, 4326) => {
-                // WebMercator → lon/lat
-                let (minx, miny) = mercator_to_lon_lat(self.extent.minx, self.extent.miny);
-                let (maxx, maxy) = mercator_to_lon_lat(self.extent.maxx, self.extent.maxy);
-                GeometryExtent::from((minx, miny, maxx, maxy))
-            }
-            // any other CRS: fall back to PROJ
-            _ => {
-                let proj = proj::Proj::new_known_crs(
-                    format!("EPSG:{}", self.crs_code).as_str(),
-                    format!("EPSG:{}", target_crs).as_str(),
-                    None,
-                )
-                .unwrap();
-                let (minx, miny) = proj
-                    .convert((self.extent.minx, self.extent.miny))
-                    .map_err(anyhow::Error::from)?;
-                let (maxx, maxy) = proj
-                    .convert((self.extent.maxx, self.extent.maxy))
-                    .map_err(anyhow::Error::from)?;
-                GeometryExtent::from((minx, miny, maxx, maxy))
-            }
-        };
+        let projector = Projector::new(self.crs_code, target_crs)?;
+        let (minx, miny) = projector.convert((self.extent.minx, self.extent.miny))?;
+        let (maxx, maxy) = projector.convert((self.extent.maxx, self.extent.maxy))?;
+        let new_extent = GeometryExtent::from((minx, miny, maxx, maxy));
 
         Ok(LayerGeometry {
             crs_code: target_crs,
@@ -69,6 +152,46 @@ impl LayerGeometry {
         })
     }
 
+    /// Bounding box in another CRS through points along every edge, not just
+    /// the corners, so it holds up for polar and other curved projections
+    pub fn edge_bounds(&self, target_crs: i32) -> anyhow::Result<GeometryExtent> {
+        if self.crs_code == target_crs {
+            return Ok(self.extent.clone());
+        }
+        let projector = Projector::new(self.crs_code, target_crs)?;
+
+        const STEPS: usize = 32;
+        let e = &self.extent;
+        let mut bounds = GeometryExtent::from((
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ));
+        for i in 0..=STEPS {
+            let t = i as f64 / STEPS as f64;
+            let x = e.minx + t * (e.maxx - e.minx);
+            let y = e.miny + t * (e.maxy - e.miny);
+            for point in [(x, e.miny), (x, e.maxy), (e.minx, y), (e.maxx, y)] {
+                // Points with no image in the target CRS are skipped
+                if let Some((px, py)) = projector
+                    .convert(point)
+                    .ok()
+                    .filter(|(px, py): &(f64, f64)| px.is_finite() && py.is_finite())
+                {
+                    bounds.minx = bounds.minx.min(px);
+                    bounds.miny = bounds.miny.min(py);
+                    bounds.maxx = bounds.maxx.max(px);
+                    bounds.maxy = bounds.maxy.max(py);
+                }
+            }
+        }
+        if !bounds.minx.is_finite() {
+            anyhow::bail!("extent has no image in EPSG:{}", target_crs);
+        }
+        Ok(bounds)
+    }
+
     /// Generate both 4326 & 3857 variants for caching
     pub fn generate_cached_geometry_sync(&self) -> anyhow::Result<HashMap<i32, LayerGeometry>> {
         let mut cache = HashMap::new();
@@ -1,4 +1,7 @@
+pub mod geojson;
 pub mod geometry;
 pub mod layer;
+pub mod render;
 pub mod responses;
 pub mod style;
+pub mod tms;
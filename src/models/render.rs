@@ -0,0 +1,882 @@
+use crate::utils::expression::Expression;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// How source pixels are resampled when a tile is warped. The names match
+/// gdalwarp's `-r`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resampling {
+    Nearest,
+    Bilinear,
+    Cubic,
+    CubicSpline,
+    Lanczos,
+    Average,
+    Mode,
+    Min,
+    Max,
+    Median,
+}
+
+impl Resampling {
+    pub const ALL: [Resampling; 10] = [
+        Resampling::Nearest,
+        Resampling::Bilinear,
+        Resampling::Cubic,
+        Resampling::CubicSpline,
+        Resampling::Lanczos,
+        Resampling::Average,
+        Resampling::Mode,
+        Resampling::Min,
+        Resampling::Max,
+        Resampling::Median,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resampling::Nearest => "nearest",
+            Resampling::Bilinear => "bilinear",
+            Resampling::Cubic => "cubic",
+            Resampling::CubicSpline => "cubicspline",
+            Resampling::Lanczos => "lanczos",
+            Resampling::Average => "average",
+            Resampling::Mode => "mode",
+            Resampling::Min => "min",
+            Resampling::Max => "max",
+            Resampling::Median => "median",
+        }
+    }
+}
+
+impl fmt::Display for Resampling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Resampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        Resampling::ALL
+            .into_iter()
+            .find(|r| r.as_str() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Resampling::ALL.iter().map(|r| r.as_str()).collect();
+                format!(
+                    "Unknown resampling '{}', expected one of {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// A value, or an inclusive `[min, max]` range, to treat as nodata on top of
+/// whatever the raster itself declares
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum NodataValue {
+    Value(f32),
+    Range(f32, f32),
+}
+
+impl NodataValue {
+    pub fn contains(&self, value: f32) -> bool {
+        match *self {
+            NodataValue::Value(v) => value == v,
+            NodataValue::Range(min, max) => value >= min && value <= max,
+        }
+    }
+}
+
+impl fmt::Display for NodataValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodataValue::Value(v) => write!(f, "{}", v),
+            NodataValue::Range(min, max) => write!(f, "{}..{}", min, max),
+        }
+    }
+}
+
+/// What the values in a style.txt mean
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StopValues {
+    /// Stop values are data values, as in QGIS
+    #[default]
+    Absolute,
+    /// The stops are spread over the layer's min/max (or `rescale`)
+    Stretched,
+}
+
+impl StopValues {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StopValues::Absolute => "absolute",
+            StopValues::Stretched => "stretched",
+        }
+    }
+}
+
+impl FromStr for StopValues {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "absolute" => Ok(StopValues::Absolute),
+            "stretched" => Ok(StopValues::Stretched),
+            other => Err(format!(
+                "Unknown stops '{}', expected absolute or stretched",
+                other
+            )),
+        }
+    }
+}
+
+/// Values derived from elevation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Terrain {
+    /// Degrees from flat
+    Slope,
+    /// Rise over run, 100 at 45°
+    SlopePercent,
+    /// Degrees clockwise from north the slope faces, none on the flat
+    Aspect,
+}
+
+impl Terrain {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Terrain::Slope => "slope",
+            Terrain::SlopePercent => "slope_percent",
+            Terrain::Aspect => "aspect",
+        }
+    }
+
+    /// What the style is stretched over without a rescale
+    pub fn default_range(&self) -> (f32, f32) {
+        match self {
+            Terrain::Slope => (0.0, 90.0),
+            Terrain::SlopePercent => (0.0, 100.0),
+            Terrain::Aspect => (0.0, 360.0),
+        }
+    }
+}
+
+impl FromStr for Terrain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "slope" => Ok(Terrain::Slope),
+            "slope_percent" => Ok(Terrain::SlopePercent),
+            "aspect" => Ok(Terrain::Aspect),
+            other => Err(format!(
+                "Unknown terrain '{}', expected slope, slope_percent or aspect",
+                other
+            )),
+        }
+    }
+}
+
+/// What a single band (or expression) is drawn as
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderMode {
+    /// Through the layer's style
+    #[default]
+    Colour,
+    /// Grey shaded relief, for elevation
+    Hillshade,
+    /// The style's colours darkened by the shaded relief
+    Hypsometric,
+    /// Slope or aspect of the values, through the layer's style
+    Slope,
+    SlopePercent,
+    Aspect,
+    /// Elevation packed into RGB for 3D terrain, Mapbox's scheme
+    TerrainRgb,
+    /// Elevation packed into RGB for 3D terrain, Mapzen's scheme
+    Terrarium,
+}
+
+impl RenderMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RenderMode::Colour => "colour",
+            RenderMode::Hillshade => "hillshade",
+            RenderMode::Hypsometric => "hypsometric",
+            RenderMode::Slope => "slope",
+            RenderMode::SlopePercent => "slope_percent",
+            RenderMode::Aspect => "aspect",
+            RenderMode::TerrainRgb => "terrain_rgb",
+            RenderMode::Terrarium => "terrarium",
+        }
+    }
+
+    /// Whether the relief is shaded
+    pub fn is_shaded(&self) -> bool {
+        matches!(self, RenderMode::Hillshade | RenderMode::Hypsometric)
+    }
+
+    /// The terrain value drawn in place of the values, if any
+    pub fn terrain(&self) -> Option<Terrain> {
+        match self {
+            RenderMode::Slope => Some(Terrain::Slope),
+            RenderMode::SlopePercent => Some(Terrain::SlopePercent),
+            RenderMode::Aspect => Some(Terrain::Aspect),
+            _ => None,
+        }
+    }
+
+    /// Whether pixels depend on their neighbours, as relief and terrain do
+    pub fn uses_neighbours(&self) -> bool {
+        self.is_shaded() || self.terrain().is_some()
+    }
+
+    /// Whether the values are encoded as elevation instead of coloured
+    pub fn is_encoded(&self) -> bool {
+        matches!(self, RenderMode::TerrainRgb | RenderMode::Terrarium)
+    }
+}
+
+impl FromStr for RenderMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "colour" | "color" => Ok(RenderMode::Colour),
+            "hillshade" => Ok(RenderMode::Hillshade),
+            "hypsometric" => Ok(RenderMode::Hypsometric),
+            "slope" => Ok(RenderMode::Slope),
+            "slope_percent" => Ok(RenderMode::SlopePercent),
+            "aspect" => Ok(RenderMode::Aspect),
+            "terrain_rgb" | "terrainrgb" | "mapbox" => Ok(RenderMode::TerrainRgb),
+            "terrarium" => Ok(RenderMode::Terrarium),
+            other => Err(format!(
+                "Unknown render '{}', expected colour, hillshade, hypsometric, slope, \
+                 slope_percent, aspect, terrain_rgb or terrarium",
+                other
+            )),
+        }
+    }
+}
+
+/// What a tile is returned as: a coloured PNG, or the values themselves
+/// for clients that colour their own
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TileFormat {
+    #[default]
+    Png,
+    /// NumPy float32 array of (bands + 1, height, width), the last being a
+    /// mask of 255 where there's data, like rio-tiler's
+    Npy,
+    /// Float32 GeoTIFF with NaN as nodata
+    GTiff,
+}
+
+impl TileFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TileFormat::Png => "png",
+            TileFormat::Npy => "npy",
+            TileFormat::GTiff => "tif",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TileFormat::Png => "image/png",
+            TileFormat::Npy => "application/x-npy",
+            TileFormat::GTiff => "image/tiff; application=geotiff",
+        }
+    }
+
+    /// Whether values are returned as they are instead of coloured
+    pub fn is_raw(&self) -> bool {
+        *self != TileFormat::Png
+    }
+}
+
+impl FromStr for TileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "png" => Ok(TileFormat::Png),
+            "npy" => Ok(TileFormat::Npy),
+            "tif" | "tiff" | "gtiff" | "geotiff" => Ok(TileFormat::GTiff),
+            other => Err(format!(
+                "Unknown format '{}', expected png, npy or tif",
+                other
+            )),
+        }
+    }
+}
+
+/// Render parameters as they arrive on a tile request, e.g.
+/// `?bands=4,3,2&rescale=0,3000` or `?expression=(b4-b3)/(b4+b3)&rescale=-1,1`
+#[derive(Debug, Default, Deserialize)]
+pub struct RenderQuery {
+    pub bands: Option<String>,
+    pub expression: Option<String>,
+    pub rescale: Option<String>,
+    pub resampling: Option<String>,
+    pub stops: Option<String>,
+    pub render: Option<String>,
+    pub azimuth: Option<String>,
+    pub altitude: Option<String>,
+    pub z_factor: Option<String>,
+    pub multidirectional: Option<String>,
+    pub base: Option<String>,
+    pub interval: Option<String>,
+    pub format: Option<String>,
+    pub tms: Option<String>, // Tile matrix set of /tiles, WebMercatorQuad if unset
+}
+
+/// How a layer is rendered beyond its style. Loaded per layer from a sidecar
+/// `{layer}.json` next to the raster, and overridable per request through the
+/// query string.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderOptions {
+    /// 1-based bands rendered straight to R,G,B(,A) instead of a colour ramp
+    pub bands: Option<Vec<usize>>,
+    /// Band math such as `(b4-b3)/(b4+b3)`, coloured with the layer's style
+    pub expression: Option<String>,
+    /// (min, max) stretch per composite band, one pair applies to all bands.
+    /// Defaults to each band's own min/max. For a single band or an
+    /// expression it is the range the style is stretched over.
+    pub rescale: Option<Vec<(f32, f32)>>,
+    /// Resampling used when warping. Defaults to nearest for categorical data
+    /// (a colour table or 8-bit) and bilinear for everything else.
+    pub resampling: Option<Resampling>,
+    /// Extra nodata values or ranges. Only set from the sidecar, the band's
+    /// own nodata value and mask are always honoured.
+    pub nodata: Option<Vec<NodataValue>>,
+    /// Whether style.txt stop values are data values (the default) or are
+    /// stretched over the value range
+    pub stops: Option<StopValues>,
+    /// Colour (the default), hillshade, hypsometric tint with hillshade,
+    /// or the slope or aspect of the values
+    pub render: Option<RenderMode>,
+    /// Direction the light comes from, degrees clockwise from north (315)
+    pub azimuth: Option<f64>,
+    /// Height of the light above the horizon in degrees (45)
+    pub altitude: Option<f64>,
+    /// Multiplies the values before shading, taking the slope or encoding
+    /// them, e.g. to turn feet into meters (1)
+    pub z_factor: Option<f64>,
+    /// Light from four directions around the azimuth, as gdaldem's
+    /// -multidirectional
+    pub multidirectional: Option<bool>,
+    /// Elevation of RGB 0,0,0 in terrain_rgb (-10000)
+    pub base: Option<f64>,
+    /// Elevation step of each terrain_rgb value (0.1)
+    pub interval: Option<f64>,
+    /// PNG unless the request asks for raw values. Not for sidecars.
+    #[serde(skip)]
+    pub format: Option<TileFormat>,
+}
+
+impl RenderOptions {
+    /// Options from a tile request query string. Values left out fall back to
+    /// the layer's sidecar through `merged_over`.
+    pub fn from_query(query: &RenderQuery) -> Result<Self, String> {
+        let bands = query
+            .bands
+            .as_deref()
+            .map(|s| {
+                s.split(',')
+                    .map(|b| {
+                        b.trim()
+                            .parse::<usize>()
+                            .map_err(|e| format!("Invalid band '{}': {}", b, e))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+
+        let rescale = query
+            .rescale
+            .as_deref()
+            .map(|s| {
+                let values = s
+                    .split(',')
+                    .map(|v| {
+                        v.trim()
+                            .parse::<f32>()
+                            .map_err(|e| format!("Invalid rescale value '{}': {}", v, e))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if values.is_empty() || values.len() % 2 != 0 {
+                    return Err("rescale must be min,max pairs".to_string());
+                }
+                Ok(values.chunks(2).map(|p| (p[0], p[1])).collect::<Vec<_>>())
+            })
+            .transpose()?;
+
+        let number = |name: &str, value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|v| {
+                    v.trim()
+                        .parse::<f64>()
+                        .map_err(|e| format!("Invalid {} '{}': {}", name, v, e))
+                })
+                .transpose()
+        };
+        let multidirectional = query
+            .multidirectional
+            .as_deref()
+            .map(|v| match v.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" => Ok(true),
+                "false" | "0" => Ok(false),
+                _ => Err(format!("Invalid multidirectional '{}'", v)),
+            })
+            .transpose()?;
+
+        let options = RenderOptions {
+            bands,
+            expression: query.expression.clone(),
+            rescale,
+            resampling: query.resampling.as_deref().map(str::parse).transpose()?,
+            nodata: None,
+            stops: query.stops.as_deref().map(str::parse).transpose()?,
+            render: query.render.as_deref().map(str::parse).transpose()?,
+            azimuth: number("azimuth", &query.azimuth)?,
+            altitude: number("altitude", &query.altitude)?,
+            z_factor: number("z_factor", &query.z_factor)?,
+            multidirectional,
+            base: number("base", &query.base)?,
+            interval: number("interval", &query.interval)?,
+            format: query.format.as_deref().map(str::parse).transpose()?,
+        };
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// Options from a layer sidecar file
+    pub fn from_json(content: &str) -> Result<Self, String> {
+        let options: RenderOptions =
+            serde_json::from_str(content).map_err(|e| format!("Invalid sidecar: {}", e))?;
+        options.validate()?;
+        Ok(options)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bands.is_some() && self.expression.is_some() {
+            return Err("bands and expression can't be used together".to_string());
+        }
+        if let Some(NodataValue::Range(min, max)) = self
+            .nodata
+            .iter()
+            .flatten()
+            .find(|v| matches!(v, NodataValue::Range(min, max) if min > max))
+        {
+            return Err(format!("nodata range {}..{} is empty", min, max));
+        }
+        if let Some(expression) = &self.expression {
+            Expression::parse(expression).map_err(|e| format!("Invalid expression: {}", e))?;
+        }
+        if let Some(rescale) = self
+            .rescale
+            .as_ref()
+            .filter(|r| self.bands.is_none() && r.len() != 1)
+        {
+            return Err(format!(
+                "rescale needs 1 min,max pair without bands, got {}",
+                rescale.len()
+            ));
+        }
+        if let Some(render) = self
+            .render
+            .filter(|r| *r != RenderMode::Colour && self.bands.is_some())
+        {
+            return Err(format!(
+                "render={} works on a single band or an expression, not bands",
+                render.as_str()
+            ));
+        }
+        if let Some((format, render)) = self
+            .format
+            .filter(TileFormat::is_raw)
+            .zip(self.render.filter(|r| r.is_shaded() || r.is_encoded()))
+        {
+            return Err(format!(
+                "format={} returns values, render={} only makes images",
+                format.as_str(),
+                render.as_str()
+            ));
+        }
+        if let Some(azimuth) = self.azimuth.filter(|a| !a.is_finite()) {
+            return Err(format!(
+                "azimuth must be a number of degrees, got {}",
+                azimuth
+            ));
+        }
+        if let Some(altitude) = self.altitude.filter(|a| !(0.0..=90.0).contains(a)) {
+            return Err(format!(
+                "altitude must be 0 to 90 degrees, got {}",
+                altitude
+            ));
+        }
+        if let Some(z_factor) = self.z_factor.filter(|z| !(z.is_finite() && *z > 0.0)) {
+            return Err(format!("z_factor must be positive, got {}", z_factor));
+        }
+        if let Some(base) = self.base.filter(|b| !b.is_finite()) {
+            return Err(format!("base must be a number, got {}", base));
+        }
+        if let Some(interval) = self.interval.filter(|i| !(i.is_finite() && *i > 0.0)) {
+            return Err(format!("interval must be positive, got {}", interval));
+        }
+        if let Some(bands) = &self.bands {
+            if bands.len() != 3 && bands.len() != 4 {
+                return Err(format!(
+                    "bands needs 3 (RGB) or 4 (RGBA) bands, got {}",
+                    bands.len()
+                ));
+            }
+            if bands.contains(&0) {
+                return Err("bands are numbered from 1".to_string());
+            }
+            if let Some(rescale) = self
+                .rescale
+                .as_ref()
+                .filter(|r| r.len() != 1 && r.len() != bands.len())
+            {
+                return Err(format!(
+                    "rescale needs 1 or {} min,max pairs, got {}",
+                    bands.len(),
+                    rescale.len()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Request options win, anything unset comes from the layer. Bands and
+    /// expression replace each other, and the layer's rescale is only kept
+    /// for requested bands it still fits. Combinations that still don't work
+    /// together, such as a layer's hillshade and requested bands, are an
+    /// error.
+    pub fn merged_over(&self, base: &RenderOptions) -> Result<RenderOptions, String> {
+        let (bands, expression, base_rescale) = if self.bands.is_some() || self.expression.is_some()
+        {
+            let fits = |rescale: &Vec<(f32, f32)>| {
+                let (Some(bands), Some(_)) = (&self.bands, &base.bands) else {
+                    return false;
+                };
+                rescale.len() == 1 || rescale.len() == bands.len()
+            };
+            (
+                self.bands.clone(),
+                self.expression.clone(),
+                base.rescale.clone().filter(fits),
+            )
+        } else {
+            (
+                base.bands.clone(),
+                base.expression.clone(),
+                base.rescale.clone(),
+            )
+        };
+        let merged = RenderOptions {
+            bands,
+            expression,
+            rescale: self.rescale.clone().or(base_rescale),
+            resampling: self.resampling.or(base.resampling),
+            nodata: self.nodata.clone().or_else(|| base.nodata.clone()),
+            stops: self.stops.or(base.stops),
+            render: self.render.or(base.render),
+            azimuth: self.azimuth.or(base.azimuth),
+            altitude: self.altitude.or(base.altitude),
+            z_factor: self.z_factor.or(base.z_factor),
+            multidirectional: self.multidirectional.or(base.multidirectional),
+            base: self.base.or(base.base),
+            interval: self.interval.or(base.interval),
+            format: self.format.or(base.format),
+        };
+        merged.validate()?;
+        Ok(merged)
+    }
+
+    /// Canonical string of everything set, empty for the defaults. Used in
+    /// cache keys and ETags.
+    pub fn cache_key(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(bands) = &self.bands {
+            let bands: Vec<String> = bands.iter().map(|b| b.to_string()).collect();
+            parts.push(format!("bands={}", bands.join(",")));
+        }
+        if let Some(expression) = &self.expression {
+            // Whitespace doesn't change the result, keep it out of the key
+            let expression: String = expression.split_whitespace().collect();
+            parts.push(format!("expression={}", expression));
+        }
+        if let Some(rescale) = &self.rescale {
+            let pairs: Vec<String> = rescale
+                .iter()
+                .map(|(min, max)| format!("{},{}", min, max))
+                .collect();
+            parts.push(format!("rescale={}", pairs.join(",")));
+        }
+        if let Some(resampling) = self.resampling {
+            parts.push(format!("resampling={}", resampling));
+        }
+        if let Some(nodata) = &self.nodata {
+            let values: Vec<String> = nodata.iter().map(|v| v.to_string()).collect();
+            parts.push(format!("nodata={}", values.join(",")));
+        }
+        if let Some(stops) = self.stops {
+            parts.push(format!("stops={}", stops.as_str()));
+        }
+        if let Some(render) = self.render {
+            parts.push(format!("render={}", render.as_str()));
+        }
+        if let Some(azimuth) = self.azimuth {
+            parts.push(format!("azimuth={}", azimuth));
+        }
+        if let Some(altitude) = self.altitude {
+            parts.push(format!("altitude={}", altitude));
+        }
+        if let Some(z_factor) = self.z_factor {
+            parts.push(format!("z_factor={}", z_factor));
+        }
+        if let Some(multidirectional) = self.multidirectional {
+            parts.push(format!("multidirectional={}", multidirectional));
+        }
+        if let Some(base) = self.base {
+            parts.push(format!("base={}", base));
+        }
+        if let Some(interval) = self.interval {
+            parts.push(format!("interval={}", interval));
+        }
+        if let Some(format) = self.format {
+            parts.push(format!("format={}", format.as_str()));
+        }
+        parts.join("&")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(
+        bands: Option<&str>,
+        expression: Option<&str>,
+        rescale: Option<&str>,
+        resampling: Option<&str>,
+    ) -> Result<RenderOptions, String> {
+        RenderOptions::from_query(&RenderQuery {
+            bands: bands.map(Into::into),
+            expression: expression.map(Into::into),
+            rescale: rescale.map(Into::into),
+            resampling: resampling.map(Into::into),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_from_query() {
+        let options = parse(Some("4,3,2"), None, Some("0,3000"), None).unwrap();
+        assert_eq!(options.bands, Some(vec![4, 3, 2]));
+        assert_eq!(options.rescale, Some(vec![(0.0, 3000.0)]));
+        assert_eq!(options.cache_key(), "bands=4,3,2&rescale=0,3000");
+
+        let options = parse(Some("1,2,3,4"), None, Some("0,1,0,2,0,3,0,255"), None).unwrap();
+        assert_eq!(options.rescale.unwrap().len(), 4);
+
+        assert!(parse(Some("1,2"), None, None, None).is_err());
+        assert!(parse(Some("0,1,2"), None, None, None).is_err());
+        assert!(parse(Some("a,b,c"), None, None, None).is_err());
+        assert!(parse(Some("1,2,3"), None, Some("0,1,2"), None).is_err());
+        assert!(parse(Some("1,2,3"), None, Some("0,1,0,1"), None).is_err());
+        assert_eq!(parse(None, None, None, None).unwrap().cache_key(), "");
+    }
+
+    #[test]
+    fn test_request_overrides_sidecar() {
+        let sidecar: RenderOptions =
+            serde_json::from_str(r#"{"bands": [4, 3, 2], "rescale": [[0, 3000]]}"#).unwrap();
+        let request = parse(Some("5,4,3"), None, None, None).unwrap();
+        let merged = request.merged_over(&sidecar).unwrap();
+        assert_eq!(merged.bands, Some(vec![5, 4, 3]));
+        assert_eq!(merged.rescale, Some(vec![(0.0, 3000.0)]));
+
+        let request = parse(None, Some("(b4-b3)/(b4+b3)"), None, None).unwrap();
+        let merged = request.merged_over(&sidecar).unwrap();
+        assert_eq!(merged.bands, None);
+        assert_eq!(merged.expression.as_deref(), Some("(b4-b3)/(b4+b3)"));
+        assert_eq!(merged.rescale, None);
+
+        // Three pairs don't fit four bands, so they go with the sidecar's bands
+        let sidecar = RenderOptions::from_json(
+            r#"{"bands": [3, 2, 1], "rescale": [[0, 1], [0, 2], [0, 3]]}"#,
+        )
+        .unwrap();
+        let request = parse(Some("1,2,3,4"), None, None, None).unwrap();
+        let merged = request.merged_over(&sidecar).unwrap();
+        assert_eq!(merged.bands, Some(vec![1, 2, 3, 4]));
+        assert_eq!(merged.rescale, None);
+        let request = parse(Some("1,2,3"), None, None, None).unwrap();
+        assert_eq!(
+            request
+                .merged_over(&sidecar)
+                .unwrap()
+                .rescale
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // A shaded layer can't be drawn as a composite
+        let sidecar = RenderOptions::from_json(r#"{"render": "hillshade"}"#).unwrap();
+        assert!(request.merged_over(&sidecar).is_err());
+    }
+
+    #[test]
+    fn test_expression() {
+        let options = parse(None, Some("(b4 - b3) / (b4 + b3)"), Some("-1,1"), None).unwrap();
+        assert_eq!(
+            options.cache_key(),
+            "expression=(b4-b3)/(b4+b3)&rescale=-1,1"
+        );
+
+        assert!(parse(None, Some("b1 +"), None, None).is_err());
+        assert!(parse(Some("1,2,3"), Some("b1"), None, None).is_err());
+        assert!(parse(None, Some("b1"), Some("0,1,0,1"), None).is_err());
+        assert!(RenderOptions::from_json(r#"{"expression": "b2 / b1"}"#).is_ok());
+    }
+
+    #[test]
+    fn test_resampling() {
+        let options = parse(None, None, None, Some("CubicSpline")).unwrap();
+        assert_eq!(options.resampling, Some(Resampling::CubicSpline));
+        assert_eq!(options.cache_key(), "resampling=cubicspline");
+        assert!(parse(None, None, None, Some("sinc")).is_err());
+
+        let sidecar = RenderOptions::from_json(r#"{"resampling": "mode"}"#).unwrap();
+        let request = parse(Some("1,2,3"), None, None, None).unwrap();
+        assert_eq!(
+            request.merged_over(&sidecar).unwrap().resampling,
+            Some(Resampling::Mode)
+        );
+    }
+
+    #[test]
+    fn test_nodata_override() {
+        let sidecar =
+            RenderOptions::from_json(r#"{"nodata": [-9999, [-1e38, -1e30], [250, 255]]}"#).unwrap();
+        let nodata = sidecar.nodata.clone().unwrap();
+        assert!(nodata[0].contains(-9999.0));
+        assert!(!nodata[0].contains(0.0));
+        assert!(nodata[1].contains(-5e35));
+        assert!(nodata[2].contains(255.0));
+        assert!(sidecar.cache_key().ends_with(",250..255"));
+
+        let request = parse(None, None, None, Some("bilinear")).unwrap();
+        assert_eq!(request.merged_over(&sidecar).unwrap().nodata, Some(nodata));
+
+        assert!(RenderOptions::from_json(r#"{"nodata": [[10, 0]]}"#).is_err());
+    }
+
+    #[test]
+    fn test_stop_values() {
+        let request = RenderOptions::from_query(&RenderQuery {
+            stops: Some("Stretched".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(request.stops, Some(StopValues::Stretched));
+        assert_eq!(request.cache_key(), "stops=stretched");
+
+        let sidecar = RenderOptions::from_json(r#"{"stops": "absolute"}"#).unwrap();
+        assert_eq!(
+            request.merged_over(&sidecar).unwrap().stops,
+            Some(StopValues::Stretched)
+        );
+        assert!(RenderOptions::from_json(r#"{"stops": "relative"}"#).is_err());
+    }
+
+    #[test]
+    fn test_tile_format() {
+        let query = |format: &str, render: Option<&str>| {
+            RenderOptions::from_query(&RenderQuery {
+                format: Some(format.into()),
+                render: render.map(Into::into),
+                ..Default::default()
+            })
+        };
+        let options = query("NPY", Some("slope")).unwrap();
+        assert_eq!(options.format, Some(TileFormat::Npy));
+        assert_eq!(options.cache_key(), "render=slope&format=npy");
+        assert_eq!(
+            query("geotiff", None).unwrap().format,
+            Some(TileFormat::GTiff)
+        );
+        assert!(query("png", Some("hillshade")).is_ok());
+
+        assert!(query("webp", None).is_err());
+        assert!(query("npy", Some("hillshade")).is_err());
+        assert!(query("tif", Some("terrarium")).is_err());
+        assert!(RenderOptions::from_json(r#"{"format": "npy"}"#).is_err());
+    }
+
+    #[test]
+    fn test_render_mode() {
+        let request = RenderOptions::from_query(&RenderQuery {
+            render: Some("Hillshade".into()),
+            azimuth: Some("270".into()),
+            multidirectional: Some("true".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(request.render, Some(RenderMode::Hillshade));
+        assert_eq!(
+            request.cache_key(),
+            "render=hillshade&azimuth=270&multidirectional=true"
+        );
+
+        let sidecar =
+            RenderOptions::from_json(r#"{"render": "hypsometric", "z_factor": 0.3048}"#).unwrap();
+        let merged = request.merged_over(&sidecar).unwrap();
+        assert_eq!(merged.render, Some(RenderMode::Hillshade));
+        assert_eq!(merged.z_factor, Some(0.3048));
+
+        let slope = RenderOptions::from_json(r#"{"render": "slope_percent"}"#).unwrap();
+        assert_eq!(slope.render.unwrap().terrain(), Some(Terrain::SlopePercent));
+        assert_eq!(slope.cache_key(), "render=slope_percent");
+
+        let encoded = RenderOptions::from_query(&RenderQuery {
+            render: Some("terrain_rgb".into()),
+            base: Some("-500".into()),
+            interval: Some("0.01".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(encoded.render.unwrap().is_encoded());
+        assert!(!encoded.render.unwrap().uses_neighbours());
+        assert_eq!(
+            encoded.cache_key(),
+            "render=terrain_rgb&base=-500&interval=0.01"
+        );
+
+        for bad in [
+            r#"{"render": "relief"}"#,
+            r#"{"render": "terrarium", "bands": [1, 2, 3]}"#,
+            r#"{"render": "terrain_rgb", "interval": 0}"#,
+            r#"{"render": "aspect", "bands": [1, 2, 3]}"#,
+            r#"{"render": "hillshade", "bands": [1, 2, 3]}"#,
+            r#"{"altitude": 120}"#,
+            r#"{"z_factor": 0}"#,
+        ] {
+            assert!(RenderOptions::from_json(bad).is_err());
+        }
+    }
+}
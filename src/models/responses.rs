@@ -1,4 +1,96 @@
+use crate::models::render::Terrain;
+use serde::Serialize;
+
+#[derive(Clone)]
 pub struct TileResponse {
     pub bytes: Vec<u8>,
     pub content_type: String,
 }
+
+/// A response too big to hold in memory, sent from a file as it is read
+pub struct FileResponse {
+    pub file: tokio::fs::File,
+    pub content_type: String,
+}
+
+/// The pixel of a layer under a point
+#[derive(Debug, Clone, Serialize)]
+pub struct PointSample {
+    pub pixel: usize, // Column in the raster
+    pub line: usize,  // Row in the raster
+    pub values: Vec<BandValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terrain: Option<TerrainSample>, // Only when asked for
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BandValue {
+    pub band: usize,
+    pub value: Option<f64>, // As stored, None only for NaN
+    pub nodata: bool,       // Band nodata, masked, or in the layer's nodata overrides
+}
+
+impl BandValue {
+    /// The value, unless it is nodata
+    pub fn data(&self) -> Option<f64> {
+        self.value.filter(|_| !self.nodata)
+    }
+}
+
+/// Slope and aspect of an elevation band at a pixel, from its neighbours.
+/// None on nodata, and for the aspect of flat ground.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TerrainSample {
+    pub band: usize,
+    pub slope: Option<f64>, // Degrees
+    pub slope_percent: Option<f64>,
+    pub aspect: Option<f64>, // Degrees clockwise from north
+}
+
+/// Statistics of one band over the pixels inside a zone, or of its slope or
+/// aspect if `terrain` is set
+#[derive(Debug, Clone, Serialize)]
+pub struct ZonalStats {
+    pub band: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terrain: Option<Terrain>,
+    pub count: usize,        // Pixels inside with data
+    pub nodata_count: usize, // Pixels inside without
+    pub min: Option<f64>,    // The rest are None when count is 0
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    pub stddev: Option<f64>, // Population standard deviation
+    pub sum: Option<f64>,
+    pub percentiles: Vec<Percentile>,
+    pub histogram: Option<Histogram>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Percentile {
+    pub percentile: f64,
+    pub value: f64,
+}
+
+/// Equal width bins from min to max, `edges` has one more entry than `counts`
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    pub edges: Vec<f64>,
+    pub counts: Vec<usize>,
+}
+
+/// Values of one band sampled along a line
+#[derive(Debug, Clone, Serialize)]
+pub struct LineProfile {
+    pub band: usize,
+    pub length: f64,  // Meters, along the great circle between vertices
+    pub spacing: f64, // Meters between samples, the last may be closer
+    pub samples: Vec<ProfileSample>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileSample {
+    pub distance: f64, // Meters from the start of the line
+    pub x: f64,        // In the requested CRS
+    pub y: f64,
+    pub value: Option<f64>, // None off the raster or on nodata
+}
@@ -1,8 +1,32 @@
-#[derive(Debug, Clone)]
+use serde::Serialize;
+
+/// How colours are picked between stops, as in a QGIS colour map export
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    /// Blend linearly between the two surrounding stops
+    #[default]
+    Interpolated,
+    /// Each stop colours every value up to and including its own
+    Discrete,
+    /// Only values equal to a stop are coloured
+    Exact,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct ColourStop {
     pub value: f32,
     pub red: u8,
     pub green: u8,
     pub blue: u8,
     pub alpha: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// A parsed style.txt
+#[derive(Debug, Clone, Default)]
+pub struct ColourMap {
+    pub interpolation: Interpolation,
+    pub stops: Vec<ColourStop>,
 }
@@ -0,0 +1,446 @@
+use crate::geometry::projection::{MAX_ZOOM, WORLD_WIDTH};
+use crate::models::geometry::GeometryExtent;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// OGC standardised rendering pixel size, scale = cell size / 0.28mm
+const PIXEL_SIZE: f64 = 0.00028;
+// Half the width of the polar stereographic grids (2^22 m), as NASA GIBS
+const POLAR_HALF_WIDTH: f64 = 4_194_304.0;
+
+/// A CRS in TMS 2.0 JSON, either a bare URI or `{"uri": ...}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CrsRef {
+    Uri(String),
+    Object { uri: String },
+}
+
+impl CrsRef {
+    pub fn uri(&self) -> &str {
+        match self {
+            CrsRef::Uri(uri) | CrsRef::Object { uri } => uri,
+        }
+    }
+
+    /// The EPSG code behind the URI, CRS84 counts as 4326
+    pub fn epsg(&self) -> Option<i32> {
+        let uri = self.uri();
+        if uri.ends_with("CRS84") || uri.eq_ignore_ascii_case("CRS:84") {
+            return Some(4326);
+        }
+        // http://www.opengis.net/def/crs/EPSG/0/3857, EPSG:3857, urn:ogc:def:crs:EPSG::3857
+        if !uri.to_ascii_uppercase().contains("EPSG") {
+            return None;
+        }
+        uri.rsplit(['/', ':']).next()?.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CornerOfOrigin {
+    #[default]
+    TopLeft,
+    BottomLeft,
+}
+
+/// One zoom level of a tile matrix set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TileMatrix {
+    pub id: String,
+    pub scale_denominator: f64,
+    pub cell_size: f64,
+    #[serde(default)]
+    pub corner_of_origin: CornerOfOrigin,
+    pub point_of_origin: [f64; 2], // In the CRS's axis order
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub matrix_width: u32,
+    pub matrix_height: u32,
+}
+
+/// A tiling scheme as in OGC Two Dimensional Tile Matrix Set 2.0, so any
+/// definition in its JSON encoding can be loaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TileMatrixSet {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    pub crs: CrsRef,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ordered_axes: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub well_known_scale_set: Option<String>,
+    pub tile_matrices: Vec<TileMatrix>,
+    #[serde(skip)]
+    pub crs_code: i32, // EPSG code we render into
+}
+
+impl TileMatrixSet {
+    /// Parse an OGC TMS 2.0 JSON document
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let mut tms: TileMatrixSet = serde_json::from_str(json)?;
+        tms.crs_code = tms
+            .crs
+            .epsg()
+            .with_context(|| format!("unsupported CRS '{}'", tms.crs.uri()))?;
+        if tms.tile_matrices.is_empty() {
+            anyhow::bail!("no tileMatrices");
+        }
+        if tms.tile_matrices.len() > u8::MAX as usize {
+            anyhow::bail!("more than {} tileMatrices", u8::MAX);
+        }
+        Ok(tms)
+    }
+
+    /// Google Maps compatible Web Mercator, the XYZ grid
+    pub fn web_mercator_quad() -> Self {
+        let half = WORLD_WIDTH / 2.0;
+        Self::quad(
+            "WebMercatorQuad",
+            "Google Maps Compatible for the World",
+            "http://www.opengis.net/def/crs/EPSG/0/3857",
+            3857,
+            ["E", "N"],
+            [-half, half],
+            WORLD_WIDTH / 256.0,
+            1,
+        )
+        .with_scale_set("GoogleMapsCompatible")
+    }
+
+    /// Plate carrée lon/lat, two tiles wide at the top level
+    pub fn world_crs84_quad() -> Self {
+        Self::quad(
+            "WorldCRS84Quad",
+            "CRS84 for the World",
+            "http://www.opengis.net/def/crs/OGC/1.3/CRS84",
+            4326,
+            ["Lon", "Lat"],
+            [-180.0, 90.0],
+            180.0 / 256.0,
+            2,
+        )
+        .with_scale_set("GoogleCRS84Quad")
+    }
+
+    /// A polar stereographic grid of ±2^22 m around the pole, one tile at the top level
+    pub fn polar(id: &str, title: &str, crs_code: i32) -> Self {
+        let mut tms = Self::quad(
+            id,
+            title,
+            &format!("http://www.opengis.net/def/crs/EPSG/0/{}", crs_code),
+            crs_code,
+            ["E", "N"],
+            [-POLAR_HALF_WIDTH, POLAR_HALF_WIDTH],
+            2.0 * POLAR_HALF_WIDTH / 256.0,
+            1,
+        );
+        tms.uri = None; // Not registered with OGC
+        tms
+    }
+
+    /// A 256px grid that doubles in both directions at each level
+    #[allow(clippy::too_many_arguments)]
+    fn quad(
+        id: &str,
+        title: &str,
+        crs: &str,
+        crs_code: i32,
+        axes: [&str; 2],
+        origin: [f64; 2],
+        cell_size_z0: f64,
+        width_z0: u32,
+    ) -> Self {
+        // Scale denominators are in metres, degrees are converted at the equator
+        let metres_per_unit = if crs_code == 4326 {
+            WORLD_WIDTH / 360.0
+        } else {
+            1.0
+        };
+        let tile_matrices = (0..=MAX_ZOOM)
+            .map(|z| {
+                let cell_size = cell_size_z0 / (1u64 << z) as f64;
+                TileMatrix {
+                    id: z.to_string(),
+                    scale_denominator: cell_size * metres_per_unit / PIXEL_SIZE,
+                    cell_size,
+                    corner_of_origin: CornerOfOrigin::TopLeft,
+                    point_of_origin: origin,
+                    tile_width: 256,
+                    tile_height: 256,
+                    matrix_width: width_z0 << z,
+                    matrix_height: 1 << z,
+                }
+            })
+            .collect();
+        TileMatrixSet {
+            id: id.to_string(),
+            title: Some(title.to_string()),
+            uri: Some(format!(
+                "http://www.opengis.net/def/tilematrixset/OGC/1.0/{}",
+                id
+            )),
+            crs: CrsRef::Uri(crs.to_string()),
+            ordered_axes: Some(axes.iter().map(|a| a.to_string()).collect()),
+            well_known_scale_set: None,
+            tile_matrices,
+            crs_code,
+        }
+    }
+
+    fn with_scale_set(mut self, name: &str) -> Self {
+        self.well_known_scale_set =
+            Some(format!("http://www.opengis.net/def/wkss/OGC/1.0/{}", name));
+        self
+    }
+
+    /// True when the first axis is northing/latitude, e.g. EPSG:4326 proper
+    fn north_first(&self) -> bool {
+        self.ordered_axes
+            .as_ref()
+            .and_then(|axes| axes.first())
+            .is_some_and(|axis| {
+                matches!(
+                    axis.to_ascii_lowercase().as_str(),
+                    "n" | "lat" | "latitude" | "northing"
+                )
+            })
+    }
+
+    pub fn matrix(&self, z: u8) -> Option<&TileMatrix> {
+        self.tile_matrices.get(z as usize)
+    }
+
+    /// The zoom (index) of the matrix with this identifier
+    pub fn matrix_index(&self, id: &str) -> Option<u8> {
+        self.tile_matrices
+            .iter()
+            .position(|m| m.id == id)
+            .map(|i| i as u8)
+    }
+
+    /// The extent of a tile in x/y (east/north) order, None outside the matrix
+    pub fn tile_bounds(&self, z: u8, col: u32, row: u32) -> Option<GeometryExtent> {
+        let m = self.matrix(z)?;
+        if col >= m.matrix_width || row >= m.matrix_height {
+            return None;
+        }
+        let [a, b] = m.point_of_origin;
+        let (ox, oy) = if self.north_first() { (b, a) } else { (a, b) };
+        let width = m.tile_width as f64 * m.cell_size;
+        let height = m.tile_height as f64 * m.cell_size;
+
+        let minx = ox + col as f64 * width;
+        let miny = match m.corner_of_origin {
+            CornerOfOrigin::TopLeft => oy - (row + 1) as f64 * height,
+            CornerOfOrigin::BottomLeft => oy + row as f64 * height,
+        };
+        Some(GeometryExtent::from((
+            minx,
+            miny,
+            minx + width,
+            miny + height,
+        )))
+    }
+
+    /// The tiles of matrix `z` that cover `extent` (in this set's CRS), as
+    /// (min row, max row, min col, max col). None if it misses the matrix.
+    pub fn tile_limits(&self, z: u8, extent: &GeometryExtent) -> Option<(u32, u32, u32, u32)> {
+        let m = self.matrix(z)?;
+        let [a, b] = m.point_of_origin;
+        let (ox, oy) = if self.north_first() { (b, a) } else { (a, b) };
+        let width = m.tile_width as f64 * m.cell_size;
+        let height = m.tile_height as f64 * m.cell_size;
+
+        // Fractional tile positions of the edges, an edge exactly on a tile
+        // boundary doesn't pull in the next tile
+        let (x0, x1) = ((extent.minx - ox) / width, (extent.maxx - ox) / width);
+        let (y0, y1) = match m.corner_of_origin {
+            CornerOfOrigin::TopLeft => ((oy - extent.maxy) / height, (oy - extent.miny) / height),
+            CornerOfOrigin::BottomLeft => {
+                ((extent.miny - oy) / height, (extent.maxy - oy) / height)
+            }
+        };
+        let span = |lo: f64, hi: f64, size: u32| {
+            let (first, last) = (lo.floor(), (hi.ceil() - 1.0).max(lo.floor()));
+            if last < 0.0 || first >= size as f64 {
+                return None;
+            }
+            Some((first.max(0.0) as u32, last.min((size - 1) as f64) as u32))
+        };
+        let (min_row, max_row) = span(y0, y1, m.matrix_height)?;
+        let (min_col, max_col) = span(x0, x1, m.matrix_width)?;
+        Some((min_row, max_row, min_col, max_col))
+    }
+
+    /// The first matrix with cells at least as fine as `resolution` (CRS
+    /// units per pixel), or the finest one
+    pub fn native_zoom(&self, resolution: f64) -> u8 {
+        self.tile_matrices
+            .iter()
+            .position(|m| m.cell_size <= resolution * (1.0 + 1e-6))
+            .unwrap_or(self.tile_matrices.len() - 1) as u8
+    }
+}
+
+/// Every tile matrix set the server offers, built-ins first
+#[derive(Debug, Clone)]
+pub struct TileMatrixSets {
+    sets: Vec<TileMatrixSet>,
+}
+
+impl Default for TileMatrixSets {
+    fn default() -> Self {
+        TileMatrixSets {
+            sets: vec![
+                TileMatrixSet::web_mercator_quad(),
+                TileMatrixSet::world_crs84_quad(),
+                TileMatrixSet::polar("EPSG3413", "NSIDC Sea Ice Polar Stereographic North", 3413),
+                TileMatrixSet::polar("EPSG3031", "Antarctic Polar Stereographic", 3031),
+            ],
+        }
+    }
+}
+
+impl TileMatrixSets {
+    /// Add every `*.json` TMS definition in `dir`, replacing built-ins with
+    /// the same id
+    pub fn load_dir(&mut self, dir: &Path) -> anyhow::Result<()> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)
+            .with_context(|| format!("reading {}", dir.display()))?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+            })
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let tms = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| TileMatrixSet::from_json(&json))
+                .with_context(|| format!("loading {}", path.display()))?;
+            self.sets.retain(|t| t.id != tms.id);
+            self.sets.push(tms);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&TileMatrixSet> {
+        self.sets.iter().find(|t| t.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TileMatrixSet> {
+        self.sets.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: &GeometryExtent, b: (f64, f64, f64, f64)) -> bool {
+        [a.minx - b.0, a.miny - b.1, a.maxx - b.2, a.maxy - b.3]
+            .iter()
+            .all(|d| d.abs() < 1e-6)
+    }
+
+    #[test]
+    fn test_web_mercator_quad() {
+        let tms = TileMatrixSet::web_mercator_quad();
+        assert_eq!(tms.crs_code, 3857);
+        assert!((tms.tile_matrices[0].scale_denominator - 559_082_264.028_717_8).abs() < 1e-6);
+
+        let half = WORLD_WIDTH / 2.0;
+        let bounds = tms.tile_bounds(1, 1, 0).unwrap();
+        assert!(approx(&bounds, (0.0, 0.0, half, half)));
+        assert!(tms.tile_bounds(1, 2, 0).is_none());
+        assert!(tms.tile_bounds(MAX_ZOOM + 1, 0, 0).is_none());
+    }
+
+    #[test]
+    fn test_world_crs84_quad() {
+        let tms = TileMatrixSet::world_crs84_quad();
+        assert_eq!(tms.crs_code, 4326);
+        assert!((tms.tile_matrices[0].scale_denominator - 279_541_132.014_358_9).abs() < 1e-6);
+        assert_eq!(tms.tile_matrices[0].matrix_width, 2);
+
+        let bounds = tms.tile_bounds(0, 1, 0).unwrap();
+        assert!(approx(&bounds, (0.0, -90.0, 180.0, 90.0)));
+
+        // Australia at z3 (22.5° tiles)
+        let extent = GeometryExtent::from((110.0, -45.0, 155.0, -10.0));
+        assert_eq!(tms.tile_limits(3, &extent), Some((4, 5, 12, 14)));
+    }
+
+    #[test]
+    fn test_from_json() {
+        // Lat/lon EPSG:4326 with a bottom left origin
+        let tms = TileMatrixSet::from_json(
+            r#"{
+                "id": "Custom",
+                "crs": {"uri": "http://www.opengis.net/def/crs/EPSG/0/4326"},
+                "orderedAxes": ["Lat", "Lon"],
+                "tileMatrices": [{
+                    "id": "a",
+                    "scaleDenominator": 1,
+                    "cellSize": 1,
+                    "cornerOfOrigin": "bottomLeft",
+                    "pointOfOrigin": [-90, -180],
+                    "tileWidth": 90,
+                    "tileHeight": 90,
+                    "matrixWidth": 4,
+                    "matrixHeight": 2
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(tms.crs_code, 4326);
+        assert_eq!(tms.matrix_index("a"), Some(0));
+        let bounds = tms.tile_bounds(0, 1, 1).unwrap();
+        assert!(approx(&bounds, (-90.0, 0.0, 0.0, 90.0)));
+
+        assert!(
+            TileMatrixSet::from_json(r#"{"id": "x", "crs": "EPSG:3857", "tileMatrices": []}"#)
+                .is_err()
+        );
+        assert!(
+            TileMatrixSet::from_json(
+                r#"{"id": "x", "crs": "http://example.com/mycrs", "tileMatrices": []}"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_crs_uris() {
+        let epsg = |uri: &str| CrsRef::Uri(uri.to_string()).epsg();
+        assert_eq!(
+            epsg("http://www.opengis.net/def/crs/EPSG/0/3031"),
+            Some(3031)
+        );
+        assert_eq!(epsg("urn:ogc:def:crs:EPSG::3413"), Some(3413));
+        assert_eq!(epsg("EPSG:2193"), Some(2193));
+        assert_eq!(
+            epsg("http://www.opengis.net/def/crs/OGC/1.3/CRS84"),
+            Some(4326)
+        );
+        assert_eq!(epsg("http://example.com/mycrs"), None);
+    }
+
+    #[test]
+    fn test_native_zoom() {
+        let tms = TileMatrixSet::web_mercator_quad();
+        assert_eq!(tms.native_zoom(1113.2), 8);
+        assert_eq!(tms.native_zoom(1e-6), MAX_ZOOM);
+        assert_eq!(tms.native_zoom(1e9), 0);
+    }
+}
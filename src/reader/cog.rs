@@ -1,14 +1,31 @@
 use crate::models::geometry::GeometryExtent;
-use crate::models::layer::Layer;
-use crate::{Config, utils::style::get_builtin_gradient};
+use crate::models::layer::{Layer, LayerGeometry};
+use crate::models::render::{
+    NodataValue, RenderMode, RenderOptions, Resampling, StopValues, TileFormat,
+};
+use crate::models::style::Interpolation;
+use crate::reader::export::{ExportFormat, tiff_bytes};
+use crate::reader::terrain::{
+    Lighting, crop_halo, encode_elevation, encoding_scale, ground_resolution, hillshade, terrain,
+};
+use crate::utils::{expression::Expression, npy, xml};
+use crate::{
+    Config,
+    utils::style::{colour_at, get_builtin_gradient},
+};
+use gdal::raster::{Buffer, RasterBand, ResampleAlg};
 use gdal::spatial_ref::SpatialRef;
 use gdal::{Dataset, DriverManager, errors::GdalError};
-use gdal_sys::{GDALReprojectImage, GDALResampleAlg};
+use gdal_sys::{CPLErr, GDALReprojectImage, GDALResampleAlg};
 use image::{ColorType, ImageEncoder, Rgba, RgbaImage, codecs::png::PngEncoder};
 use proj::Proj;
-use std::{io::Cursor, path::PathBuf};
+use std::{io::Cursor, path::Path, path::PathBuf};
 use tokio::task;
 
+/// Pixels warped around a tile for shading, slope and aspect, so
+/// neighbouring tiles agree along their shared edge
+const RELIEF_HALO: usize = 1;
+
 // Returns true if the value should be treated as nodata (currently, if it is NaN)
 fn is_nodata(val: f32) -> bool {
     val.is_nan()
@@ -19,237 +36,988 @@ pub async fn process_cog(
     extent_3857: GeometryExtent,
     layer_obj: Layer,
     tile_size: (usize, usize),
+    options: RenderOptions,
+) -> gdal::errors::Result<Vec<u8>> {
+    render_extent(input_path, extent_3857, 3857, layer_obj, tile_size, options).await
+}
+
+/// Render any extent of a raster in the given EPSG code (x/y in traditional
+/// lon/lat order) to a PNG of `size` pixels
+pub async fn render_extent(
+    input_path: PathBuf,
+    extent: GeometryExtent,
+    crs: i32,
+    layer_obj: Layer,
+    size: (usize, usize),
+    options: RenderOptions,
 ) -> gdal::errors::Result<Vec<u8>> {
     task::spawn_blocking(move || {
+        let tile_size = size;
         let (tile_size_x, tile_size_y) = tile_size;
         let source_crs = format!("{}:{}", "EPSG", layer_obj.source_geometry.crs_code);
-        let to_merc = Proj::new_known_crs(&source_crs, "EPSG:3857", None)
-            .map_err(|e| GdalError::BadArgument(e.to_string()))?;
-
-        // Reproject both corners into 3857
-        let (x0, y0) = to_merc
-            .convert((
-                layer_obj.source_geometry.extent.minx,
-                layer_obj.source_geometry.extent.miny,
-            ))
-            .map_err(|e| GdalError::BadArgument(format!("failed to reproj min corner: {}", e)))?;
-        let (x1, y1) = to_merc
-            .convert((
-                layer_obj.source_geometry.extent.maxx,
-                layer_obj.source_geometry.extent.maxy,
-            ))
-            .map_err(|e| GdalError::BadArgument(format!("failed to reproj max corner: {}", e)))?;
-        let orig_minx_3857 = x0.min(x1);
-        let orig_maxx_3857 = x0.max(x1);
-        let orig_miny_3857 = y0.min(y1);
-        let orig_maxy_3857 = y0.max(y1);
 
-        // Open source dataset, S3 is /vsis3/{bucket}/{key}, otherwise file.
-        let src_ds = Dataset::open(&input_path)?;
+        // Reproject both corners into the output CRS. That box is only the
+        // layer's footprint for the lon/lat-aligned outputs, polar and other
+        // grids rely on the warp leaving the outside as NaN.
+        let footprint = if matches!(crs, 3857 | 4326) {
+            let to_target = Proj::new_known_crs(&source_crs, &format!("EPSG:{}", crs), None)
+                .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+            let (x0, y0) = to_target
+                .convert((
+                    layer_obj.source_geometry.extent.minx,
+                    layer_obj.source_geometry.extent.miny,
+                ))
+                .map_err(|e| {
+                    GdalError::BadArgument(format!("failed to reproj min corner: {}", e))
+                })?;
+            let (x1, y1) = to_target
+                .convert((
+                    layer_obj.source_geometry.extent.maxx,
+                    layer_obj.source_geometry.extent.maxy,
+                ))
+                .map_err(|e| {
+                    GdalError::BadArgument(format!("failed to reproj max corner: {}", e))
+                })?;
+            Some(GeometryExtent::from((
+                x0.min(x1),
+                y0.min(y1),
+                x0.max(x1),
+                y0.max(y1),
+            )))
+        } else {
+            None
+        };
 
-        // Retrieve nodata value from the source dataset (band 1)
-        let src_band = src_ds
-            .rasterband(Config::default().default_raster_band)
-            .map_err(|e| GdalError::BadArgument(e.to_string()))?;
-        let src_nodata_opt: Option<f32> = src_band.no_data_value().map(|v| v as f32);
+        let src_ds = open_source(&input_path)?;
 
-        // Prepare an in‐memory 256×256 target in Web mercator 3857
-        // let (minx, miny, maxx, maxy) = bbox_3857;
-        let res_x = (extent_3857.maxx - extent_3857.minx) / (tile_size_x as f64);
-        let res_y = (extent_3857.maxy - extent_3857.miny) / (tile_size_y as f64);
+        let (bands, expression) = render_bands(&options)?;
+        let resampling = render_resampling(&src_ds, &layer_obj, &options, bands[0])?;
+        let nodata = options.nodata.clone().unwrap_or_default();
 
-        let mem_drv = DriverManager::get_driver_by_name("MEM")
-            .map_err(|e| GdalError::BadArgument(e.to_string()))?;
-        let mut dst_ds = mem_drv
-            .create_with_band_type::<f32, _>(
-                "memory_dataset",
-                tile_size_x,
-                tile_size_y,
-                Config::default().default_raster_band,
-            )
-            .map_err(|e| GdalError::BadArgument(e.to_string()))?;
-
-        let merc_sref =
-            SpatialRef::from_epsg(3857).map_err(|e| GdalError::BadArgument(e.to_string()))?;
-        dst_ds
-            .set_projection(
-                &merc_sref
-                    .to_wkt()
-                    .map_err(|e| GdalError::BadArgument(e.to_string()))?,
-            )
-            .map_err(|e| GdalError::BadArgument(e.to_string()))?;
-        dst_ds
-            .set_geo_transform(&[extent_3857.minx, res_x, 0.0, extent_3857.maxy, 0.0, -res_y])
-            .map_err(|e| GdalError::BadArgument(e.to_string()))?;
-
-        // Set the nodata value for the destination raster band BEFORE reprojection
-        if let Some(src_nodata) = src_nodata_opt {
-            let mut dst_band = dst_ds
-                .rasterband(Config::default().default_raster_band)
-                .map_err(|e| GdalError::BadArgument(e.to_string()))?;
-            dst_band
-                .set_no_data_value(Some(src_nodata as f64))
-                .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+        // Shading, slope and aspect look at neighbours, so warp a little more
+        // than the tile
+        let mode = options.render.unwrap_or_default();
+        let halo = if mode.uses_neighbours() {
+            RELIEF_HALO
+        } else {
+            0
+        };
+        let (halo_x, halo_y) = (
+            halo as f64 * (extent.maxx - extent.minx) / tile_size_x as f64,
+            halo as f64 * (extent.maxy - extent.miny) / tile_size_y as f64,
+        );
+        let warped = GeometryExtent::from((
+            extent.minx - halo_x,
+            extent.miny - halo_y,
+            extent.maxx + halo_x,
+            extent.maxy + halo_y,
+        ));
+        let grid = (tile_size_x + 2 * halo, tile_size_y + 2 * halo);
+        let buffers = warp_bands(
+            &src_ds,
+            &input_path,
+            &bands,
+            &nodata,
+            &LayerGeometry {
+                crs_code: crs,
+                extent: warped.clone(),
+            },
+            grid,
+            resampling,
+        )?;
+        let mut prepared = prepare(buffers, expression, options, crs, &warped, grid, halo);
+
+        // Any pixel whose geographic coordinate falls outside the original extent
+        // should be treated as nodata (NaN), not 0.0.
+        if let Some(footprint) = footprint {
+            let res_x = (extent.maxx - extent.minx) / (tile_size_x as f64);
+            let res_y = (extent.maxy - extent.miny) / (tile_size_y as f64);
+            for y in 0..tile_size_y {
+                for x in 0..tile_size_x {
+                    let gx = extent.minx + (x as f64) * res_x;
+                    let gy = extent.maxy - (y as f64) * res_y;
+                    if gx < footprint.minx
+                        || gx > footprint.maxx
+                        || gy < footprint.miny
+                        || gy > footprint.maxy
+                    {
+                        for buffer in prepared.buffers.iter_mut() {
+                            buffer[y * tile_size_x + x] = f32::NAN;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Nothing to shade where the footprint was cut away
+        if let Some(shade) = prepared.shade.as_mut() {
+            for (i, s) in shade.iter_mut().enumerate() {
+                if prepared.buffers.iter().any(|b| is_nodata(b[i])) {
+                    *s = f32::NAN;
+                }
+            }
         }
 
-        // Setup reprojection of tile. Potential memory issues with unsafe code
-        // however gdalwarp is not available in gdal crate as yet.
-        unsafe {
-            GDALReprojectImage(
-                src_ds.c_dataset(),
-                std::ptr::null(),
-                dst_ds.c_dataset(),
-                std::ptr::null(),
-                GDALResampleAlg::GRA_NearestNeighbour,
-                f64::NAN, // treat outside pixels as nodata
-                f64::NAN,
-                None,
-                std::ptr::null_mut(),
-                std::ptr::null_mut(),
+        encode_output(
+            &src_ds, &layer_obj, &bands, prepared, tile_size, crs, &extent,
+        )
+    })
+    .await
+    .map_err(|e| GdalError::BadArgument(e.to_string()))?
+}
+
+/// Render part of a raster in its own CRS, `extent` in source coordinates,
+/// to a PNG of `size` pixels. Nothing is warped, so GDAL reads from the
+/// overviews when shrinking, which keeps previews of huge rasters quick.
+pub async fn render_preview(
+    layer_obj: Layer,
+    extent: GeometryExtent,
+    size: (usize, usize),
+    options: RenderOptions,
+) -> gdal::errors::Result<Vec<u8>> {
+    task::spawn_blocking(move || {
+        let src_ds = open_source(&layer_obj.path)?;
+        let (bands, expression) = render_bands(&options)?;
+        let resampling = render_resampling(&src_ds, &layer_obj, &options, bands[0])?;
+        let nodata = options.nodata.clone().unwrap_or_default();
+
+        // Fractional source pixels of the extent, and the whole ones we have
+        let gt = src_ds.geo_transform()?;
+        let (width, height) = src_ds.raster_size();
+        let (c0, c1) = ((extent.minx - gt[0]) / gt[1], (extent.maxx - gt[0]) / gt[1]);
+        let (r0, r1) = ((extent.maxy - gt[3]) / gt[5], (extent.miny - gt[3]) / gt[5]);
+        let col = c0.max(0.0).floor();
+        let row = r0.max(0.0).floor();
+        let col_end = c1.min(width as f64).ceil();
+        let row_end = r1.min(height as f64).ceil();
+
+        // Where those land in the image, the rest stays nodata
+        let (scale_x, scale_y) = (size.0 as f64 / (c1 - c0), size.1 as f64 / (r1 - r0));
+        let x0 = (((col - c0) * scale_x).round().max(0.0) as usize).min(size.0);
+        let y0 = (((row - r0) * scale_y).round().max(0.0) as usize).min(size.1);
+        let x1 = (((col_end - c0) * scale_x).round().max(0.0) as usize).min(size.0);
+        let y1 = (((row_end - r0) * scale_y).round().max(0.0) as usize).min(size.1);
+
+        let mut buffers = vec![vec![f32::NAN; size.0 * size.1]; bands.len()];
+        if col < col_end && row < row_end && x0 < x1 && y0 < y1 {
+            let window = (
+                (col as isize, row as isize),
+                ((col_end - col) as usize, (row_end - row) as usize),
             );
+            let read = read_window(
+                &src_ds,
+                &bands,
+                &nodata,
+                window,
+                (x1 - x0, y1 - y0),
+                Some(raster_io_resampling(resampling)),
+            )?;
+            for (buffer, part) in buffers.iter_mut().zip(read) {
+                for (y, line) in part.chunks(x1 - x0).enumerate() {
+                    let start = (y0 + y) * size.0 + x0;
+                    buffer[start..start + line.len()].copy_from_slice(line);
+                }
+            }
         }
 
-        let dst_band = dst_ds
-            .rasterband(Config::default().default_raster_band)
-            .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+        // One image has no seams, so the edges are shaded with what's there
+        let crs = layer_obj.source_geometry.crs_code;
+        let prepared = prepare(buffers, expression, options, crs, &extent, size, 0);
+        encode_output(&src_ds, &layer_obj, &bands, prepared, size, crs, &extent)
+    })
+    .await
+    .map_err(|e| GdalError::BadArgument(e.to_string()))?
+}
+
+/// The bytes of a rendered extent: its values as they are for the raw
+/// formats, otherwise a coloured (and maybe shaded) PNG
+fn encode_output(
+    src_ds: &Dataset,
+    layer_obj: &Layer,
+    bands: &[usize],
+    prepared: Prepared,
+    size: (usize, usize),
+    crs: i32,
+    extent: &GeometryExtent,
+) -> gdal::errors::Result<Vec<u8>> {
+    let Prepared {
+        buffers,
+        expression,
+        options,
+        shade,
+    } = prepared;
+    let values = |buffers: Vec<Vec<f32>>| match &expression {
+        Some(expression) => vec![expression.eval_buffers(&buffers)],
+        None => buffers,
+    };
+    match options.format.unwrap_or_default() {
+        TileFormat::Png => {
+            let mode = options.render.unwrap_or_default();
+            let img = colourise(src_ds, layer_obj, bands, buffers, expression, options, size)?;
+            let img = match shade {
+                Some(shade) => relief_image(img, &shade, mode),
+                None => img,
+            };
+            encode_png(&img)
+        }
+        TileFormat::Npy => {
+            let values = values(buffers);
+            let mask: Vec<f32> = (0..size.0 * size.1)
+                .map(|i| {
+                    if values.iter().any(|v| is_nodata(v[i])) {
+                        0.0
+                    } else {
+                        255.0
+                    }
+                })
+                .collect();
+            Ok(npy::encode_f32(
+                &[values.len() + 1, size.1, size.0],
+                values.into_iter().chain([mask]).flatten(),
+            ))
+        }
+        TileFormat::GTiff => {
+            let values = values(buffers);
+            let mut ds = DriverManager::get_driver_by_name("MEM")?
+                .create_with_band_type::<f32, _>("", size.0, size.1, values.len())?;
+            ds.set_geo_transform(&[
+                extent.minx,
+                (extent.maxx - extent.minx) / size.0 as f64,
+                0.0,
+                extent.maxy,
+                0.0,
+                -(extent.maxy - extent.miny) / size.1 as f64,
+            ])?;
+            ds.set_spatial_ref(&SpatialRef::from_epsg(crs as u32)?)?;
+            for (i, values) in values.into_iter().enumerate() {
+                let mut band = ds.rasterband(i + 1)?;
+                band.set_no_data_value(Some(f64::NAN))?;
+                band.write((0, 0), size, &mut Buffer::new(size, values))?;
+            }
+            tiff_bytes(&ds, ExportFormat::GTiff)
+        }
+    }
+}
+
+/// Band 1 goes through the style, a band list is rendered as a composite
+/// and an expression reads whichever bands it mentions
+fn render_bands(options: &RenderOptions) -> gdal::errors::Result<(Vec<usize>, Option<Expression>)> {
+    let expression = options
+        .expression
+        .as_deref()
+        .map(Expression::parse)
+        .transpose()
+        .map_err(GdalError::BadArgument)?;
+    let bands: Vec<usize> = match (&options.bands, &expression) {
+        (Some(bands), _) => bands.clone(),
+        (None, Some(expression)) => expression.bands().to_vec(),
+        (None, None) => vec![Config::default().default_raster_band],
+    };
+    Ok((bands, expression))
+}
+
+fn render_resampling(
+    src_ds: &Dataset,
+    layer_obj: &Layer,
+    options: &RenderOptions,
+    first_band: usize,
+) -> gdal::errors::Result<Resampling> {
+    Ok(match options.resampling {
+        Some(resampling) => resampling,
+        // Terrain for 3D should be smooth whatever the raster's type
+        None if options.render.is_some_and(|r| r.is_encoded()) => Resampling::Bilinear,
+        // Classes shouldn't be blended into values that aren't classes
+        None if layer_obj.interpolation == Interpolation::Exact && options.bands.is_none() => {
+            Resampling::Nearest
+        }
+        None => default_resampling(&src_ds.rasterband(first_band)?),
+    })
+}
+
+/// Colour rendered buffers as the options ask: bands as a composite, an
+/// expression through the style, a single band through the style, or
+/// either encoded as elevation
+fn colourise(
+    src_ds: &Dataset,
+    layer_obj: &Layer,
+    bands: &[usize],
+    mut buffers: Vec<Vec<f32>>,
+    expression: Option<Expression>,
+    options: RenderOptions,
+    size: (usize, usize),
+) -> gdal::errors::Result<RgbaImage> {
+    let nodata = options.nodata.clone().unwrap_or_default();
+    let img = if let Some(mode) = options.render.filter(|r| r.is_encoded()) {
+        let values = match expression {
+            Some(expression) => expression.eval_buffers(&buffers),
+            None => buffers.swap_remove(0),
+        };
+        let scale = encoding_scale(mode, &options);
+        let z_factor = options.z_factor.unwrap_or(1.0) as f32;
+        let mut img = RgbaImage::new(size.0 as u32, size.1 as u32);
+        for (px, value) in img.pixels_mut().zip(values) {
+            let [r, g, b] = encode_elevation(value * z_factor, scale);
+            *px = Rgba([r, g, b, 255]);
+        }
+        img
+    } else if options.bands.is_some() {
+        colourise_composite(
+            src_ds,
+            layer_obj,
+            bands,
+            &buffers,
+            &nodata,
+            options.rescale.as_deref(),
+            size,
+        )?
+    } else if let Some(expression) = expression {
+        let buffer = expression.eval_buffers(&buffers);
+        let range = match options.rescale.as_deref() {
+            Some([range]) => *range,
+            _ => expression_range(src_ds, layer_obj, &expression, bands, &nodata)?,
+        };
+        colourise_band(
+            &buffer,
+            layer_obj,
+            range,
+            options.stops.unwrap_or_default(),
+            size,
+        )
+    } else {
+        let buffer = buffers.swap_remove(0);
+        // The layer's min/max only knows about the raster's own nodata
+        let range = match options.rescale.as_deref() {
+            Some([range]) => *range,
+            _ if !nodata.is_empty() => band_range(src_ds, layer_obj, bands[0], &nodata)?,
+            _ => (layer_obj.min_value, layer_obj.max_value),
+        };
+        colourise_band(
+            &buffer,
+            layer_obj,
+            range,
+            options.stops.unwrap_or_default(),
+            size,
+        )
+    };
+    Ok(img)
+}
+
+/// Buffers ready to colour once the render mode has had its say
+struct Prepared {
+    buffers: Vec<Vec<f32>>,
+    expression: Option<Expression>,
+    options: RenderOptions,
+    shade: Option<Vec<f32>>, // Relief to apply after colouring
+}
+
+/// Apply the render mode to buffers over `extent`, which have a `halo`
+/// pixel border that is cropped off. Slope and aspect of what would be
+/// coloured (the band or the expression) replace it, stretched over their
+/// natural range unless rescaled. The relief modes get a shade.
+fn prepare(
+    buffers: Vec<Vec<f32>>,
+    expression: Option<Expression>,
+    mut options: RenderOptions,
+    crs: i32,
+    extent: &GeometryExtent,
+    grid: (usize, usize),
+    halo: usize,
+) -> Prepared {
+    let mode = options.render.unwrap_or_default();
+    if !mode.uses_neighbours() {
+        return Prepared {
+            buffers,
+            expression,
+            options,
+            shade: None,
+        };
+    }
+
+    let values = match &expression {
+        Some(expression) => expression.eval_buffers(&buffers),
+        None => buffers[0].clone(),
+    };
+    let resolution = ground_resolution(crs, extent, grid);
+    let lighting = Lighting::from_options(&options);
+    match mode.terrain() {
+        Some(product) => {
+            let buffer = terrain(&values, grid, halo, &resolution, product, lighting.z_factor);
+            options.expression = None;
+            options
+                .rescale
+                .get_or_insert_with(|| vec![product.default_range()]);
+            Prepared {
+                buffers: vec![buffer],
+                expression: None,
+                options,
+                shade: None,
+            }
+        }
+        None => Prepared {
+            buffers: buffers.iter().map(|b| crop_halo(b, grid, halo)).collect(),
+            expression,
+            options,
+            shade: Some(hillshade(&values, grid, halo, &resolution, &lighting)),
+        },
+    }
+}
+
+/// Grey relief on its own, or the style's colours darkened by it
+fn relief_image(mut img: RgbaImage, shade: &[f32], mode: RenderMode) -> RgbaImage {
+    for (px, &s) in img.pixels_mut().zip(shade) {
+        let [r, g, b, a] = px.0;
+        *px = if s.is_nan() {
+            Rgba([0, 0, 0, 0])
+        } else if mode == RenderMode::Hillshade {
+            let lum = (s * 255.0).round() as u8;
+            Rgba([lum, lum, lum, 255])
+        } else {
+            let dim = |c: u8| (c as f32 * s).round() as u8;
+            Rgba([dim(r), dim(g), dim(b), a])
+        };
+    }
+    img
+}
+
+fn encode_png(img: &RgbaImage) -> gdal::errors::Result<Vec<u8>> {
+    let mut png_data = Vec::new();
+    PngEncoder::new(Cursor::new(&mut png_data))
+        .write_image(
+            img.as_raw(),
+            img.width(),
+            img.height(),
+            ColorType::Rgba8.into(),
+        )
+        .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+    Ok(png_data)
+}
 
-        // Read the warped 256×256 band into a buffer
-        let mut buffer = dst_band
+/// Open a layer's source dataset, S3 is /vsis3/{bucket}/{key}, otherwise file
+pub(crate) fn open_source(path: &Path) -> gdal::errors::Result<Dataset> {
+    Dataset::open(path)
+}
+
+/// A band of a layer's source, or an error if it has no such band
+pub(crate) fn open_band(ds: &Dataset, band: usize) -> gdal::errors::Result<RasterBand<'_>> {
+    if band == 0 || band > ds.raster_count() {
+        return Err(GdalError::BadArgument(format!(
+            "Band {} does not exist, the layer has {}",
+            band,
+            ds.raster_count()
+        )));
+    }
+    ds.rasterband(band)
+}
+
+/// Nearest for categorical data, where blending class values would invent
+/// new classes, and bilinear for continuous data. A colour table or integer
+/// band is taken to be categorical, a layer's sidecar can still ask for
+/// bilinear.
+pub(crate) fn default_resampling(band: &RasterBand) -> Resampling {
+    if band.color_table().is_some() || band.band_type().is_integer() {
+        Resampling::Nearest
+    } else {
+        Resampling::Bilinear
+    }
+}
+
+fn gdal_resample_alg(resampling: Resampling) -> GDALResampleAlg::Type {
+    match resampling {
+        Resampling::Nearest => GDALResampleAlg::GRA_NearestNeighbour,
+        Resampling::Bilinear => GDALResampleAlg::GRA_Bilinear,
+        Resampling::Cubic => GDALResampleAlg::GRA_Cubic,
+        Resampling::CubicSpline => GDALResampleAlg::GRA_CubicSpline,
+        Resampling::Lanczos => GDALResampleAlg::GRA_Lanczos,
+        Resampling::Average => GDALResampleAlg::GRA_Average,
+        Resampling::Mode => GDALResampleAlg::GRA_Mode,
+        Resampling::Min => GDALResampleAlg::GRA_Min,
+        Resampling::Max => GDALResampleAlg::GRA_Max,
+        Resampling::Median => GDALResampleAlg::GRA_Med,
+    }
+}
+
+/// A VRT over the source with the given bands as Float32, followed by the
+/// mask bands they need. Returns the dataset and, per requested band, the
+/// VRT band holding its mask, if the band has a real mask (an alpha band or
+/// explicit mask, not just a nodata value).
+fn band_selection_vrt(
+    src_ds: &Dataset,
+    source: &Path,
+    bands: &[usize],
+) -> gdal::errors::Result<(Dataset, Vec<Option<usize>>)> {
+    let band_count = src_ds.raster_count();
+    if let Some(bad) = bands.iter().find(|&&b| b == 0 || b > band_count) {
+        return Err(GdalError::BadArgument(format!(
+            "band {} does not exist, the raster has {} band(s)",
+            bad, band_count
+        )));
+    }
+
+    let (width, height) = src_ds.raster_size();
+    let gt = src_ds.geo_transform()?;
+    let source = xml::escape(&source.to_string_lossy());
+    let mut vrt = format!(
+        "<VRTDataset rasterXSize=\"{}\" rasterYSize=\"{}\"><SRS>{}</SRS><GeoTransform>{}, {}, {}, {}, {}, {}</GeoTransform>",
+        width,
+        height,
+        xml::escape(&src_ds.projection()),
+        gt[0],
+        gt[1],
+        gt[2],
+        gt[3],
+        gt[4],
+        gt[5]
+    );
+    let simple_source = |source_band: &str| {
+        format!(
+            "<SimpleSource><SourceFilename relativeToVRT=\"0\">{}</SourceFilename><SourceBand>{}</SourceBand></SimpleSource>",
+            source, source_band
+        )
+    };
+
+    for (i, &b) in bands.iter().enumerate() {
+        let nodata = src_ds
+            .rasterband(b)?
+            .no_data_value()
+            .map(|v| format!("<NoDataValue>{}</NoDataValue>", v))
+            .unwrap_or_default();
+        vrt.push_str(&format!(
+            "<VRTRasterBand dataType=\"Float32\" band=\"{}\">{}{}</VRTRasterBand>",
+            i + 1,
+            nodata,
+            simple_source(&b.to_string())
+        ));
+    }
+
+    // A mask shared by every band (alpha band, .msk) only needs warping once
+    let mut masks = Vec::with_capacity(bands.len());
+    let mut shared_mask = None;
+    let mut next_band = bands.len() + 1;
+    for &b in bands {
+        let flags = src_ds.rasterband(b)?.mask_flags()?;
+        if flags.is_all_valid() || (flags.is_nodata() && !flags.is_per_dataset()) {
+            masks.push(None);
+            continue;
+        }
+        if flags.is_per_dataset() && shared_mask.is_some() {
+            masks.push(shared_mask);
+            continue;
+        }
+        vrt.push_str(&format!(
+            "<VRTRasterBand dataType=\"Byte\" band=\"{}\">{}</VRTRasterBand>",
+            next_band,
+            simple_source(&format!("mask,{}", b))
+        ));
+        if flags.is_per_dataset() {
+            shared_mask = Some(next_band);
+        }
+        masks.push(Some(next_band));
+        next_band += 1;
+    }
+    vrt.push_str("</VRTDataset>");
+
+    Ok((Dataset::open(Path::new(&vrt))?, masks))
+}
+
+/// Warp the given source bands into an in-memory image in `crs` and
+/// return one buffer per band. Anything the raster marks as nodata (its
+/// nodata value, mask or alpha band), anything in `nodata` and anything the
+/// warp did not cover comes back as NaN.
+pub(crate) fn warp_bands(
+    src_ds: &Dataset,
+    source: &Path,
+    bands: &[usize],
+    nodata: &[NodataValue],
+    target: &LayerGeometry,
+    tile_size: (usize, usize),
+    resampling: Resampling,
+) -> gdal::errors::Result<Vec<Vec<f32>>> {
+    let (tile_size_x, tile_size_y) = tile_size;
+    let extent = &target.extent;
+
+    // GDALReprojectImage warps every band of the source, so pick out the ones
+    // we want, and their masks, with a VRT
+    let (vrt_ds, masks) = band_selection_vrt(src_ds, source, bands)?;
+    let warp_count = vrt_ds.raster_count();
+
+    // Prepare an in‐memory target, a 256×256 tile in Web mercator 3857 usually
+    let res_x = (extent.maxx - extent.minx) / (tile_size_x as f64);
+    let res_y = (extent.maxy - extent.miny) / (tile_size_y as f64);
+
+    let mem_drv = DriverManager::get_driver_by_name("MEM")
+        .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+    let mut dst_ds = mem_drv
+        .create_with_band_type::<f32, _>("memory_dataset", tile_size_x, tile_size_y, warp_count)
+        .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+
+    let merc_sref = SpatialRef::from_epsg(target.crs_code as u32)
+        .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+    dst_ds
+        .set_projection(
+            &merc_sref
+                .to_wkt()
+                .map_err(|e| GdalError::BadArgument(e.to_string()))?,
+        )
+        .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+    dst_ds
+        .set_geo_transform(&[extent.minx, res_x, 0.0, extent.maxy, 0.0, -res_y])
+        .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+
+    // Set the nodata value for the destination raster bands BEFORE reprojection,
+    // and start from NaN so pixels the warp never writes stay empty
+    let mut band_nodata: Vec<Option<f32>> = Vec::with_capacity(warp_count);
+    for i in 1..=warp_count {
+        let src_nodata = vrt_ds.rasterband(i)?.no_data_value().map(|v| v as f32);
+        let mut dst_band = dst_ds.rasterband(i)?;
+        if let Some(src_nodata) = src_nodata {
+            dst_band.set_no_data_value(Some(src_nodata as f64))?;
+        }
+        dst_band.fill(f64::NAN, None)?;
+        band_nodata.push(src_nodata);
+    }
+
+    // Setup reprojection of tile. Potential memory issues with unsafe code
+    // however gdalwarp is not available in gdal crate as yet.
+    let rv = unsafe {
+        GDALReprojectImage(
+            vrt_ds.c_dataset(),
+            std::ptr::null(),
+            dst_ds.c_dataset(),
+            std::ptr::null(),
+            gdal_resample_alg(resampling),
+            f64::NAN, // treat outside pixels as nodata
+            f64::NAN,
+            None,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    if rv != CPLErr::CE_None {
+        return Err(GdalError::BadArgument(format!(
+            "reprojection failed (CPLErr {})",
+            rv
+        )));
+    }
+
+    // Read the warped bands into buffers, mapping nodata values to NaN
+    let mut warped = Vec::with_capacity(warp_count);
+    for (i, nodata) in band_nodata.into_iter().enumerate() {
+        let mut buffer = dst_ds
+            .rasterband(i + 1)?
             .read_as::<f32>((0, 0), tile_size, tile_size, None)?
             .data()
             .to_vec();
-
-        // Map nodata values (including 0.0) to NaN in the buffer used for rendering
-        if let Some(src_nodata) = src_nodata_opt {
+        if let Some(nodata) = nodata {
             for value in buffer.iter_mut() {
-                if *value == src_nodata {
+                if *value == nodata {
                     *value = f32::NAN;
                 }
             }
         }
-        // Also treat 0.0 as nodata
-        for value in buffer.iter_mut() {
-            if *value == 0.0 {
-                *value = f32::NAN;
+        warped.push(buffer);
+    }
+
+    // Masks are 0 (nodata) to 255 (data), and resampling can land in between
+    let mut buffers: Vec<Vec<f32>> = warped.drain(..bands.len()).collect();
+    for (buffer, mask) in buffers.iter_mut().zip(&masks) {
+        if let Some(mask) = mask {
+            let mask = &warped[mask - bands.len() - 1];
+            for (value, &m) in buffer.iter_mut().zip(mask) {
+                if m.is_nan() || m < 128.0 {
+                    *value = f32::NAN;
+                }
             }
         }
+        mask_nodata(buffer, nodata);
+    }
 
-        // Any pixel whose geographic coordinate falls outside the original extent
-        // should be treated as nodata (NaN), not 0.0.
+    Ok(buffers)
+}
 
-        for y in 0..tile_size_y {
-            for x in 0..tile_size_x {
-                let gx = extent_3857.minx + (x as f64) * res_x;
-                let gy = extent_3857.maxy - (y as f64) * res_y;
-                if gx < orig_minx_3857
-                    || gx > orig_maxx_3857
-                    || gy < orig_miny_3857
-                    || gy > orig_maxy_3857
-                {
-                    buffer[y * tile_size_x + x] = f32::NAN;
-                }
-            }
+/// Set any value matching the layer's nodata overrides to NaN
+fn mask_nodata(buffer: &mut [f32], nodata: &[NodataValue]) {
+    if nodata.is_empty() {
+        return;
+    }
+    for value in buffer.iter_mut() {
+        if nodata.iter().any(|n| n.contains(*value)) {
+            *value = f32::NAN;
         }
+    }
+}
 
-        // Colourise into a 256×256 RGBA image
-        let mut img = RgbaImage::new(tile_size_x as u32, tile_size_y as u32);
+/// Read bands of the whole raster at low resolution, which GDAL serves from
+/// overviews, with nodata as NaN. Used to work out value ranges that are the
+/// same for every tile.
+fn read_overview(
+    src_ds: &Dataset,
+    bands: &[usize],
+    nodata: &[NodataValue],
+) -> gdal::errors::Result<Vec<Vec<f32>>> {
+    let (width, height) = src_ds.raster_size();
+    let scale = (256.0 / width.max(height) as f64).min(1.0);
+    let size = (
+        ((width as f64 * scale) as usize).max(1),
+        ((height as f64 * scale) as usize).max(1),
+    );
+    read_window(src_ds, bands, nodata, ((0, 0), (width, height)), size, None)
+}
 
-        if let Some(grad) = get_builtin_gradient(&layer_obj.style) {
-            // Use the gradient to colourise the image
-            for (i, &raw) in buffer.iter().enumerate() {
-                let px = if is_nodata(raw) {
-                    Rgba([0, 0, 0, 0])
-                } else {
-                    let t = ((raw - layer_obj.min_value)
-                        / (layer_obj.max_value - layer_obj.min_value))
-                        .clamp(0.0, 1.0);
-                    let [r, g, b, a] = grad.at(t).to_rgba8();
-                    Rgba([r, g, b, a])
-                };
-                let x = (i % tile_size_x) as u32;
-                let y = (i / tile_size_y) as u32;
-                img.put_pixel(x, y, px);
+/// Read a window of source pixels into buffers of `size`, with nodata as
+/// NaN. GDAL picks an overview when `size` is smaller than the window.
+pub(crate) fn read_window(
+    src_ds: &Dataset,
+    bands: &[usize],
+    nodata: &[NodataValue],
+    (offset, window): ((isize, isize), (usize, usize)),
+    size: (usize, usize),
+    resampling: Option<ResampleAlg>,
+) -> gdal::errors::Result<Vec<Vec<f32>>> {
+    let mut buffers = Vec::with_capacity(bands.len());
+    for &b in bands {
+        let band = src_ds.rasterband(b)?;
+        let band_nodata = band.no_data_value().map(|v| v as f32);
+        let mut buffer = band
+            .read_as::<f32>(offset, window, size, resampling)?
+            .data()
+            .to_vec();
+        let mask_flags = band.mask_flags()?;
+        if !mask_flags.is_all_valid() && !mask_flags.is_nodata() {
+            let mask = band
+                .open_mask_band()?
+                .read_as::<u8>(offset, window, size, None)?;
+            for (value, &m) in buffer.iter_mut().zip(mask.data()) {
+                if m < 128 {
+                    *value = f32::NAN;
+                }
             }
-        } else if layer_obj.colour_stops.is_empty() {
-            // Fallback to grayscale
-            for (i, &raw) in buffer.iter().enumerate() {
-                let px = if is_nodata(raw) {
-                    Rgba([0, 0, 0, 0])
-                } else {
-                    let norm =
-                        (raw - layer_obj.min_value) / (layer_obj.max_value - layer_obj.min_value);
-                    let lum = (norm.clamp(0.0, 1.0) * 255.0) as u8;
-                    Rgba([lum, lum, lum, 255])
-                };
-                let x = (i % tile_size_x) as u32;
-                let y = (i / tile_size_y) as u32;
-                img.put_pixel(x, y, px);
+        }
+        if let Some(band_nodata) = band_nodata {
+            for value in buffer.iter_mut() {
+                if *value == band_nodata {
+                    *value = f32::NAN;
+                }
             }
+        }
+        mask_nodata(&mut buffer, nodata);
+        buffers.push(buffer);
+    }
+    Ok(buffers)
+}
+
+/// RasterIO's closest match to a warp resampling, it has no order statistics
+fn raster_io_resampling(resampling: Resampling) -> ResampleAlg {
+    match resampling {
+        Resampling::Nearest => ResampleAlg::NearestNeighbour,
+        Resampling::Bilinear => ResampleAlg::Bilinear,
+        Resampling::Cubic => ResampleAlg::Cubic,
+        Resampling::CubicSpline => ResampleAlg::CubicSpline,
+        Resampling::Lanczos => ResampleAlg::Lanczos,
+        Resampling::Average | Resampling::Min | Resampling::Max | Resampling::Median => {
+            ResampleAlg::Average
+        }
+        Resampling::Mode => ResampleAlg::Mode,
+    }
+}
+
+/// The range of a band over the whole raster, with `nodata` masked.
+/// Approximate is fine here and lets GDAL use the overviews. It is the
+/// same for every tile, so it is kept on the layer.
+pub(crate) fn band_range(
+    src_ds: &Dataset,
+    layer_obj: &Layer,
+    band: usize,
+    nodata: &[NodataValue],
+) -> gdal::errors::Result<(f32, f32)> {
+    let key = format!("band={}&nodata={}", band, nodata_key(nodata));
+    layer_obj.cached_range(&key, || {
+        if nodata.is_empty() {
+            let stats = src_ds.rasterband(band)?.compute_raster_min_max(true)?;
+            Ok((stats.min as f32, stats.max as f32))
         } else {
-            // Use the colour stops to colourise the image
-            let cs = &layer_obj.colour_stops;
-            let style_min = cs.first().unwrap().value;
-            let style_max = cs.last().unwrap().value;
-            for (i, &raw) in buffer.iter().enumerate() {
-                let px = if is_nodata(raw) {
-                    Rgba([0, 0, 0, 0])
-                } else {
-                    let norm =
-                        (raw - layer_obj.min_value) / (layer_obj.max_value - layer_obj.min_value);
-                    let scaled = style_min + norm.clamp(0.0, 1.0) * (style_max - style_min);
-                    let mut colour = Rgba([0, 0, 0, 0]);
-                    for w in cs.windows(2) {
-                        let a = &w[0];
-                        let b = &w[1];
-                        if scaled >= a.value && scaled <= b.value {
-                            let t = (scaled - a.value) / (b.value - a.value);
-                            let r = ((1.0 - t) * a.red as f32 + t * b.red as f32) as u8;
-                            let g = ((1.0 - t) * a.green as f32 + t * b.green as f32) as u8;
-                            let b_ = ((1.0 - t) * a.blue as f32 + t * b.blue as f32) as u8;
-                            let a_ = ((1.0 - t) * a.alpha as f32 + t * b.alpha as f32) as u8;
-                            colour = Rgba([r, g, b_, a_]);
-                            break;
-                        }
+            Ok(value_range(&read_overview(src_ds, &[band], nodata)?[0]))
+        }
+    })
+}
+
+/// The range of an expression over the whole raster at low resolution,
+/// kept on the layer like a band's
+fn expression_range(
+    src_ds: &Dataset,
+    layer_obj: &Layer,
+    expression: &Expression,
+    bands: &[usize],
+    nodata: &[NodataValue],
+) -> gdal::errors::Result<(f32, f32)> {
+    let key = format!("expression={:?}&nodata={}", expression, nodata_key(nodata));
+    layer_obj.cached_range(&key, || {
+        Ok(value_range(
+            &expression.eval_buffers(&read_overview(src_ds, bands, nodata)?),
+        ))
+    })
+}
+
+fn nodata_key(nodata: &[NodataValue]) -> String {
+    let values: Vec<String> = nodata.iter().map(|v| v.to_string()).collect();
+    values.join(",")
+}
+
+/// Min and max of the values that aren't nodata
+fn value_range(values: &[f32]) -> (f32, f32) {
+    let (min, max) = values
+        .iter()
+        .filter(|v| !is_nodata(**v))
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &v| {
+            (min.min(v), max.max(v))
+        });
+    if min > max {
+        // Nothing but nodata, any range will do
+        return (0.0, 1.0);
+    }
+    (min, max)
+}
+
+/// Colour a single band with the layer's style: a built-in gradient, the
+/// style.txt colour stops, or grayscale. Values are stretched over `range`,
+/// except for colour stops holding absolute values.
+fn colourise_band(
+    buffer: &[f32],
+    layer_obj: &Layer,
+    range: (f32, f32),
+    stops: StopValues,
+    tile_size: (usize, usize),
+) -> RgbaImage {
+    let (tile_size_x, tile_size_y) = tile_size;
+    let (min_value, max_value) = range;
+
+    // Colourise into a 256×256 RGBA image
+    let mut img = RgbaImage::new(tile_size_x as u32, tile_size_y as u32);
+    if let Some(grad) = get_builtin_gradient(&layer_obj.style) {
+        // Use the gradient to colourise the image
+        for (i, &raw) in buffer.iter().enumerate() {
+            let px = if is_nodata(raw) {
+                Rgba([0, 0, 0, 0])
+            } else {
+                let t = ((raw - min_value) / (max_value - min_value)).clamp(0.0, 1.0);
+                let [r, g, b, a] = grad.at(t).to_rgba8();
+                Rgba([r, g, b, a])
+            };
+            let x = (i % tile_size_x) as u32;
+            let y = (i / tile_size_x) as u32;
+            img.put_pixel(x, y, px);
+        }
+    } else if layer_obj.colour_stops.is_empty() {
+        // Fallback to grayscale
+        for (i, &raw) in buffer.iter().enumerate() {
+            let px = if is_nodata(raw) {
+                Rgba([0, 0, 0, 0])
+            } else {
+                let norm = (raw - min_value) / (max_value - min_value);
+                let lum = (norm.clamp(0.0, 1.0) * 255.0) as u8;
+                Rgba([lum, lum, lum, 255])
+            };
+            let x = (i % tile_size_x) as u32;
+            let y = (i / tile_size_x) as u32;
+            img.put_pixel(x, y, px);
+        }
+    } else {
+        // Use the colour stops to colourise the image
+        let cs = &layer_obj.colour_stops;
+        let style_min = cs.first().unwrap().value;
+        let style_max = cs.last().unwrap().value;
+        for (i, &raw) in buffer.iter().enumerate() {
+            let px = if is_nodata(raw) {
+                Rgba([0, 0, 0, 0])
+            } else {
+                // Exact matches are class values, stretching them would match
+                // nothing. Past the ends a ramp keeps its end colours, like QGIS
+                // without clipping.
+                let value = match (layer_obj.interpolation, stops) {
+                    (Interpolation::Exact, _) => raw,
+                    (Interpolation::Interpolated, StopValues::Absolute) => {
+                        raw.clamp(style_min, style_max)
+                    }
+                    (Interpolation::Discrete, StopValues::Absolute) => raw,
+                    (_, StopValues::Stretched) => {
+                        let norm = (raw - min_value) / (max_value - min_value);
+                        style_min + norm.clamp(0.0, 1.0) * (style_max - style_min)
                     }
-                    colour
                 };
-                let x = (i % tile_size_x) as u32;
-                let y = (i / tile_size_y) as u32;
-                img.put_pixel(x, y, px);
-            }
+                colour_at(cs, layer_obj.interpolation, value)
+                    .map(Rgba)
+                    .unwrap_or(Rgba([0, 0, 0, 0]))
+            };
+            let x = (i % tile_size_x) as u32;
+            let y = (i / tile_size_x) as u32;
+            img.put_pixel(x, y, px);
         }
+    }
 
-        let mut png_data = Vec::new();
-        PngEncoder::new(Cursor::new(&mut png_data))
-            .write_image(
-                img.as_raw(),
-                tile_size_x as u32,
-                tile_size_y as u32,
-                ColorType::Rgba8.into(),
-            )
-            .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+    img
+}
 
-        Ok(png_data)
-    })
-    .await
-    .map_err(|e| GdalError::BadArgument(e.to_string()))?
+/// Stretch each band to 0–255 and use them directly as R, G, B (and A)
+fn colourise_composite(
+    src_ds: &Dataset,
+    layer_obj: &Layer,
+    bands: &[usize],
+    buffers: &[Vec<f32>],
+    nodata: &[NodataValue],
+    rescale: Option<&[(f32, f32)]>,
+    tile_size: (usize, usize),
+) -> gdal::errors::Result<RgbaImage> {
+    let (tile_size_x, tile_size_y) = tile_size;
+
+    // Without an explicit stretch use each band's own range
+    let ranges: Vec<(f32, f32)> = match rescale {
+        Some([one]) => vec![*one; bands.len()],
+        Some(pairs) => pairs.to_vec(),
+        None => bands
+            .iter()
+            .map(|&b| band_range(src_ds, layer_obj, b, nodata))
+            .collect::<gdal::errors::Result<_>>()?,
+    };
+
+    let stretch = |raw: f32, (min, max): (f32, f32)| -> u8 {
+        (((raw - min) / (max - min)).clamp(0.0, 1.0) * 255.0) as u8
+    };
+
+    let mut img = RgbaImage::new(tile_size_x as u32, tile_size_y as u32);
+    for i in 0..tile_size_x * tile_size_y {
+        let px = if buffers.iter().any(|b| is_nodata(b[i])) {
+            Rgba([0, 0, 0, 0])
+        } else {
+            let r = stretch(buffers[0][i], ranges[0]);
+            let g = stretch(buffers[1][i], ranges[1]);
+            let b = stretch(buffers[2][i], ranges[2]);
+            let a = buffers
+                .get(3)
+                .map(|a| stretch(a[i], ranges[3]))
+                .unwrap_or(255);
+            Rgba([r, g, b, a])
+        };
+        img.put_pixel((i % tile_size_x) as u32, (i / tile_size_x) as u32, px);
+    }
+
+    Ok(img)
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::models::render::RenderQuery;
     use crate::models::{
         geometry::GeometryExtent,
         layer::{Layer, LayerGeometry},
-        style::ColourStop,
+        style::{ColourMap, ColourStop},
     };
-    use crate::reader::cog::process_cog;
+    use crate::reader::{cog::process_cog, metadata::read_layer_metadata};
     use gdal::spatial_ref::SpatialRef;
     use gdal::{Dataset, DriverManager};
     use image::{ColorType, ImageDecoder, codecs::png::PngDecoder};
     use rand::rngs::StdRng;
     use rand::{Rng, SeedableRng};
-    use std::{fs, io::Cursor, path::PathBuf};
+    use std::{fs, io::Cursor, path::PathBuf, time::UNIX_EPOCH};
     use tempfile::TempDir;
 
     async fn make_layer(min_value: f32, max_value: f32) -> Layer {
@@ -261,6 +1029,7 @@ mod tests {
                 green: 25,
                 blue: 28,
                 alpha: 255,
+                label: None,
             },
             ColourStop {
                 value: 100.0,
@@ -268,6 +1037,7 @@ mod tests {
                 green: 174,
                 blue: 97,
                 alpha: 255,
+                label: None,
             },
             ColourStop {
                 value: 200.0,
@@ -275,6 +1045,7 @@ mod tests {
                 green: 255,
                 blue: 191,
                 alpha: 255,
+                label: None,
             },
             ColourStop {
                 value: 300.0,
@@ -282,6 +1053,7 @@ mod tests {
                 green: 221,
                 blue: 164,
                 alpha: 255,
+                label: None,
             },
             ColourStop {
                 value: 400.0,
@@ -289,6 +1061,7 @@ mod tests {
                 green: 131,
                 blue: 186,
                 alpha: 255,
+                label: None,
             },
         ];
 
@@ -308,12 +1081,16 @@ mod tests {
             path,
             size_bytes: 0,
             source_geometry,
+            raster_size: (256, 256),
             cached_geometry,
             colour_stops,
+            interpolation: Interpolation::Interpolated,
             min_value,
             max_value,
             is_cog: true,
             last_modified: std::time::SystemTime::UNIX_EPOCH,
+            render: RenderOptions::default(),
+            cache: Default::default(),
         }
     }
 
@@ -378,6 +1155,7 @@ mod tests {
             (0.0, 256.0, 0.0, 256.0).into(),
             layer,
             tile_size,
+            RenderOptions::default(),
         )
         .await
         .expect("process_cog should succeed");
@@ -412,4 +1190,500 @@ mod tests {
         assert_eq!(mask.len(), data.len(), "Mask length must match data length");
         drop(tmp);
     }
+
+    /// Writes a 256×256 EPSG:3857 GeoTIFF (covering 0..256 on both axes) with
+    /// a constant value per band
+    fn generate_constant_bands(values: &[f32]) -> (TempDir, PathBuf) {
+        let tmp = TempDir::new().expect("failed to create temp dir");
+        let file_path = tmp.path().join("bands.tif");
+
+        let driver = DriverManager::get_driver_by_name("GTIFF").unwrap();
+        let mut ds = driver
+            .create_with_band_type::<f32, _>(file_path.to_str().unwrap(), 256, 256, values.len())
+            .unwrap();
+        let sref = SpatialRef::from_epsg(3857).unwrap();
+        ds.set_projection(&sref.to_wkt().unwrap()).unwrap();
+        ds.set_geo_transform(&[0.0, 1.0, 0.0, 256.0, 0.0, -1.0])
+            .unwrap();
+        for (i, &value) in values.iter().enumerate() {
+            let mut band = ds.rasterband(i + 1).unwrap();
+            let mut buffer = gdal::raster::Buffer::<f32>::new((256, 256), vec![value; 256 * 256]);
+            band.write((0, 0), (256, 256), &mut buffer).unwrap();
+        }
+        ds.flush_cache().unwrap();
+
+        (tmp, file_path)
+    }
+
+    /// A one band Float32 GeoTIFF in EPSG:3857 loaded as a layer, shared
+    /// with the other reader tests
+    pub(crate) fn test_raster(
+        size: (usize, usize),
+        geo_transform: [f64; 6],
+        values: Vec<f32>,
+        nodata: Option<f64>,
+    ) -> (TempDir, Layer) {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("raster.tif");
+        {
+            let driver = DriverManager::get_driver_by_name("GTIFF").unwrap();
+            let mut ds = driver
+                .create_with_band_type::<f32, _>(path.to_str().unwrap(), size.0, size.1, 1)
+                .unwrap();
+            ds.set_projection(&SpatialRef::from_epsg(3857).unwrap().to_wkt().unwrap())
+                .unwrap();
+            ds.set_geo_transform(&geo_transform).unwrap();
+            let mut band = ds.rasterband(1).unwrap();
+            if nodata.is_some() {
+                band.set_no_data_value(nodata).unwrap();
+            }
+            band.write((0, 0), size, &mut Buffer::new(size, values))
+                .unwrap();
+        }
+        let layer = read_layer_metadata(&path, 0, UNIX_EPOCH, ColourMap::default()).unwrap();
+        (tmp, layer)
+    }
+
+    #[tokio::test]
+    async fn test_process_cog_rgb_composite() {
+        let (tmp, path) = generate_constant_bands(&[10.0, 50.0, 100.0, 20.0]);
+        let mut layer = make_layer(1.0, 100.0).await;
+        layer.path = path.clone();
+
+        // Bands reversed, so red comes from band 3
+        let options = RenderOptions::from_query(&RenderQuery {
+            bands: Some("3,2,1".into()),
+            rescale: Some("0,100".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let buffer = process_cog(
+            path.clone(),
+            (0.0, 0.0, 256.0, 256.0).into(),
+            layer,
+            (256, 256),
+            options,
+        )
+        .await
+        .expect("process_cog should succeed");
+
+        let img = image::load_from_memory(&buffer).unwrap().to_rgba8();
+        assert_eq!(img.get_pixel(128, 128), &Rgba([255, 127, 25, 255]));
+
+        // Four requested bands over a sidecar's three with a rescale each
+        let mut layer = make_layer(1.0, 100.0).await;
+        layer.path = path.clone();
+        layer.render = RenderOptions::from_json(
+            r#"{"bands": [3, 2, 1], "rescale": [[0, 100], [0, 100], [0, 100]]}"#,
+        )
+        .unwrap();
+        let request = RenderOptions::from_query(&RenderQuery {
+            bands: Some("1,2,3,4".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let options = request.merged_over(&layer.render).unwrap();
+        let buffer = process_cog(
+            path.clone(),
+            (0.0, 0.0, 256.0, 256.0).into(),
+            layer,
+            (256, 256),
+            options,
+        )
+        .await
+        .expect("process_cog should succeed");
+        assert!(image::load_from_memory(&buffer).is_ok());
+
+        drop(tmp);
+    }
+
+    #[tokio::test]
+    async fn test_band_range_is_kept() {
+        let (tmp, path) = generate_constant_bands(&[10.0, 50.0]);
+        let layer = make_layer(1.0, 100.0).await;
+        let ds = Dataset::open(&path).unwrap();
+        assert_eq!(band_range(&ds, &layer, 2, &[]).unwrap(), (50.0, 50.0));
+
+        // Clones of the layer share the range, another raster isn't read
+        let (other_tmp, other_path) = generate_constant_bands(&[99.0, 99.0]);
+        let other = Dataset::open(&other_path).unwrap();
+        assert_eq!(
+            band_range(&other, &layer.clone(), 2, &[]).unwrap(),
+            (50.0, 50.0)
+        );
+        assert_eq!(band_range(&other, &layer, 1, &[]).unwrap(), (99.0, 99.0));
+
+        drop((tmp, other_tmp));
+    }
+
+    #[tokio::test]
+    async fn test_process_cog_expression() {
+        let (tmp, path) = generate_constant_bands(&[10.0, 50.0, 100.0, 20.0]);
+        let mut layer = make_layer(1.0, 100.0).await;
+        layer.path = path.clone();
+        layer.colour_stops.clear(); // grayscale, easy to check
+
+        // NDVI-style ratio is -2/3, a sixth of the way along -1..1
+        let options = RenderOptions::from_query(&RenderQuery {
+            expression: Some("(b4-b3)/(b4+b3)".into()),
+            rescale: Some("-1,1".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let buffer = process_cog(
+            path.clone(),
+            (0.0, 0.0, 256.0, 256.0).into(),
+            layer.clone(),
+            (256, 256),
+            options,
+        )
+        .await
+        .expect("process_cog should succeed");
+        let img = image::load_from_memory(&buffer).unwrap().to_rgba8();
+        assert_eq!(img.get_pixel(128, 128), &Rgba([42, 42, 42, 255]));
+
+        // log(0) is not a number, so the pixel is nodata
+        let options = RenderOptions::from_query(&RenderQuery {
+            expression: Some("log(b1 - 10)".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let buffer = process_cog(
+            path.clone(),
+            (0.0, 0.0, 256.0, 256.0).into(),
+            layer,
+            (256, 256),
+            options,
+        )
+        .await
+        .expect("process_cog should succeed");
+        let img = image::load_from_memory(&buffer).unwrap().to_rgba8();
+        assert_eq!(img.get_pixel(128, 128)[3], 0);
+
+        drop(tmp);
+    }
+
+    async fn render_centre(path: &Path, options: RenderOptions) -> Rgba<u8> {
+        let mut layer = make_layer(-10.0, 10.0).await;
+        layer.path = path.to_path_buf();
+        layer.colour_stops.clear();
+        let buffer = process_cog(
+            path.to_path_buf(),
+            (0.0, 0.0, 256.0, 256.0).into(),
+            layer,
+            (256, 256),
+            options,
+        )
+        .await
+        .expect("process_cog should succeed");
+        *image::load_from_memory(&buffer)
+            .unwrap()
+            .to_rgba8()
+            .get_pixel(128, 128)
+    }
+
+    #[tokio::test]
+    async fn test_process_cog_zero_is_data() {
+        let (tmp, path) = generate_constant_bands(&[0.0]);
+
+        // Halfway along -10..10
+        let px = render_centre(&path, RenderOptions::default()).await;
+        assert_eq!(px, Rgba([127, 127, 127, 255]));
+
+        // Unless the sidecar says otherwise
+        let options = RenderOptions::from_json(r#"{"nodata": [0]}"#).unwrap();
+        assert_eq!(render_centre(&path, options).await[3], 0);
+        let options = RenderOptions::from_json(r#"{"nodata": [[-1, 1]]}"#).unwrap();
+        assert_eq!(render_centre(&path, options).await[3], 0);
+
+        drop(tmp);
+    }
+
+    #[tokio::test]
+    async fn test_process_cog_band_nodata_and_alpha() {
+        let (tmp, path) = generate_constant_bands(&[5.0, 0.0]);
+        {
+            let ds = Dataset::open_ex(
+                &path,
+                gdal::DatasetOptions {
+                    open_flags: gdal::GdalOpenFlags::GDAL_OF_UPDATE,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let mut band = ds.rasterband(1).unwrap();
+            band.set_no_data_value(Some(5.0)).unwrap();
+        }
+        let px = render_centre(&path, RenderOptions::default()).await;
+        assert_eq!(px[3], 0, "band nodata value should be transparent");
+        drop(tmp);
+
+        // Band 2 as alpha, fully transparent, masks band 1
+        let (tmp, path) = generate_constant_bands(&[5.0, 0.0]);
+        {
+            let ds = Dataset::open_ex(
+                &path,
+                gdal::DatasetOptions {
+                    open_flags: gdal::GdalOpenFlags::GDAL_OF_UPDATE,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let mut band = ds.rasterband(2).unwrap();
+            band.set_color_interpretation(gdal::raster::ColorInterpretation::AlphaBand)
+                .unwrap();
+        }
+        let px = render_centre(&path, RenderOptions::default()).await;
+        assert_eq!(px[3], 0, "alpha band should mask the data");
+        drop(tmp);
+    }
+
+    #[tokio::test]
+    async fn test_process_cog_absolute_stops() {
+        let (tmp, path) = generate_constant_bands(&[100.0]);
+        let render = |stops: &str| {
+            let path = path.clone();
+            let options = RenderOptions::from_json(&format!(r#"{{"stops": "{}"}}"#, stops));
+            async move {
+                let mut layer = make_layer(0.0, 200.0).await;
+                layer.path = path.clone();
+                let buffer = process_cog(
+                    path,
+                    (0.0, 0.0, 256.0, 256.0).into(),
+                    layer,
+                    (256, 256),
+                    options.unwrap(),
+                )
+                .await
+                .expect("process_cog should succeed");
+                *image::load_from_memory(&buffer)
+                    .unwrap()
+                    .to_rgba8()
+                    .get_pixel(128, 128)
+            }
+        };
+
+        // 100 is the 100 stop, whatever the layer's range
+        assert_eq!(render("absolute").await, Rgba([253, 174, 97, 255]));
+        // Stretched, 100 is halfway along 0..200 so halfway along the stops
+        assert_eq!(render("stretched").await, Rgba([255, 255, 191, 255]));
+
+        drop(tmp);
+    }
+
+    #[tokio::test]
+    async fn test_render_preview() {
+        let (tmp, path) = generate_constant_bands(&[100.0]);
+        let mut layer = make_layer(0.0, 200.0).await;
+        layer.path = path.clone();
+
+        let whole = render_preview(
+            layer.clone(),
+            layer.source_geometry.extent.clone(),
+            (64, 32),
+            RenderOptions::default(),
+        )
+        .await
+        .expect("render_preview should succeed");
+        let img = image::load_from_memory(&whole).unwrap().to_rgba8();
+        assert_eq!(img.dimensions(), (64, 32));
+        assert_eq!(img.get_pixel(32, 16), &Rgba([253, 174, 97, 255]));
+
+        // Half of this bbox is off the raster, and stays transparent
+        let part = render_preview(
+            layer,
+            (128.0, 0.0, 384.0, 256.0).into(),
+            (64, 64),
+            RenderOptions::default(),
+        )
+        .await
+        .expect("render_preview should succeed");
+        let img = image::load_from_memory(&part).unwrap().to_rgba8();
+        assert_eq!(img.get_pixel(16, 32), &Rgba([253, 174, 97, 255]));
+        assert_eq!(img.get_pixel(48, 32)[3], 0);
+
+        drop(tmp);
+    }
+
+    #[tokio::test]
+    async fn test_process_cog_hillshade() {
+        let (tmp, path) = generate_constant_bands(&[100.0]);
+        let mut layer = make_layer(0.0, 200.0).await;
+        layer.path = path.clone();
+
+        // Flat ground is lit at sin(45°) by default, right up to the edges
+        let render = |mode: &str| {
+            RenderOptions::from_query(&RenderQuery {
+                render: Some(mode.into()),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let extent: GeometryExtent = (0.0, 0.0, 256.0, 256.0).into();
+        let buffer = process_cog(
+            path.clone(),
+            extent.clone(),
+            layer.clone(),
+            (256, 256),
+            render("hillshade"),
+        )
+        .await
+        .expect("process_cog should succeed");
+        let img = image::load_from_memory(&buffer).unwrap().to_rgba8();
+        assert_eq!(img.get_pixel(128, 128), &Rgba([180, 180, 180, 255]));
+        assert_eq!(img.get_pixel(0, 255), &Rgba([180, 180, 180, 255]));
+
+        // The style's colour for 100, darkened
+        let buffer = process_cog(path, extent, layer, (256, 256), render("hypsometric"))
+            .await
+            .expect("process_cog should succeed");
+        let img = image::load_from_memory(&buffer).unwrap().to_rgba8();
+        assert_eq!(img.get_pixel(128, 128), &Rgba([179, 123, 69, 255]));
+
+        drop(tmp);
+    }
+
+    #[tokio::test]
+    async fn test_process_cog_slope() {
+        // Rising 1 per pixel to the east, 1 m pixels in Web Mercator at the
+        // equator, so 45° and facing west
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("ramp.tif");
+        {
+            let driver = DriverManager::get_driver_by_name("GTIFF").unwrap();
+            let mut ds = driver
+                .create_with_band_type::<f32, _>(path.to_str().unwrap(), 256, 256, 1)
+                .unwrap();
+            ds.set_projection(&SpatialRef::from_epsg(3857).unwrap().to_wkt().unwrap())
+                .unwrap();
+            ds.set_geo_transform(&[0.0, 1.0, 0.0, 256.0, 0.0, -1.0])
+                .unwrap();
+            let values = (0..256 * 256).map(|i| (i % 256) as f32).collect();
+            let mut buffer = gdal::raster::Buffer::<f32>::new((256, 256), values);
+            ds.rasterband(1)
+                .unwrap()
+                .write((0, 0), (256, 256), &mut buffer)
+                .unwrap();
+        }
+        let render = |mode: &str| {
+            RenderOptions::from_query(&RenderQuery {
+                render: Some(mode.into()),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        // Greyscale over 0..90° and 0..360°
+        let slope = render_centre(&path, render("slope")).await;
+        assert!((126..=128).contains(&slope[0]), "{:?}", slope);
+        let aspect = render_centre(&path, render("aspect")).await;
+        assert!((190..=192).contains(&aspect[0]), "{:?}", aspect);
+
+        drop(tmp);
+    }
+
+    #[tokio::test]
+    async fn test_process_cog_elevation_encoding() {
+        let (tmp, path) = generate_constant_bands(&[1234.5]);
+        let render = |mode: &str, z_factor: Option<&str>| {
+            RenderOptions::from_query(&RenderQuery {
+                render: Some(mode.into()),
+                z_factor: z_factor.map(Into::into),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        // 112345 steps of 0.1 m above -10000 m, and 1234.5 + 32768 m
+        let px = render_centre(&path, render("terrain_rgb", None)).await;
+        assert_eq!(px, Rgba([1, 182, 217, 255]));
+        let px = render_centre(&path, render("terrarium", None)).await;
+        assert_eq!(px, Rgba([132, 210, 128, 255]));
+
+        // Feet to meters: 376.27 m
+        let px = render_centre(&path, render("terrarium", Some("0.3048"))).await;
+        assert_eq!(px, Rgba([129, 120, 71, 255]));
+
+        drop(tmp);
+    }
+
+    #[tokio::test]
+    async fn test_process_cog_raw_formats() {
+        let (tmp, path) = generate_constant_bands(&[10.0, 50.0, 100.0]);
+        let mut layer = make_layer(1.0, 100.0).await;
+        layer.path = path.clone();
+        let render = |format: &str, expression: &str| {
+            RenderOptions::from_query(&RenderQuery {
+                format: Some(format.into()),
+                expression: Some(expression.into()),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+
+        // A tile hanging off the raster's top right, so a quarter has data
+        let extent: GeometryExtent = (128.0, 128.0, 384.0, 384.0).into();
+        let npy = process_cog(
+            path.clone(),
+            extent.clone(),
+            layer.clone(),
+            (256, 256),
+            render("npy", "b3 - b1"),
+        )
+        .await
+        .expect("process_cog should succeed");
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        let header = String::from_utf8_lossy(&npy[10..10 + header_len]);
+        assert!(header.contains("'shape': (2, 256, 256)"), "{}", header);
+        let values: Vec<f32> = npy[10 + header_len..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        let (data, mask) = values.split_at(256 * 256);
+        let bottom_left = 200 * 256 + 50;
+        assert_eq!((data[bottom_left], mask[bottom_left]), (90.0, 255.0));
+        assert!(data[50].is_nan());
+        assert_eq!(mask[50], 0.0);
+
+        let tiff = process_cog(path, extent, layer, (256, 256), render("tif", "b2"))
+            .await
+            .expect("process_cog should succeed");
+        gdal::vsi::create_mem_file("/vsimem/raw_tile.tif", tiff).unwrap();
+        let ds = Dataset::open("/vsimem/raw_tile.tif").unwrap();
+        assert_eq!((ds.raster_count(), ds.raster_size()), (1, (256, 256)));
+        assert_eq!(ds.geo_transform().unwrap()[0], 128.0);
+        let band = ds.rasterband(1).unwrap();
+        assert!(band.no_data_value().unwrap().is_nan());
+        let pixels = band
+            .read_as::<f32>((0, 0), (256, 256), (256, 256), None)
+            .unwrap();
+        assert_eq!(pixels.data()[bottom_left], 50.0);
+        assert!(pixels.data()[50].is_nan());
+
+        drop(tmp);
+    }
+
+    #[tokio::test]
+    async fn test_process_cog_rejects_missing_band() {
+        let (tmp, path) = generate_constant_bands(&[10.0, 50.0, 100.0]);
+        let mut layer = make_layer(1.0, 100.0).await;
+        layer.path = path.clone();
+
+        let options = RenderOptions::from_query(&RenderQuery {
+            bands: Some("4,3,2".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let result = process_cog(
+            path.clone(),
+            (0.0, 0.0, 256.0, 256.0).into(),
+            layer,
+            (256, 256),
+            options,
+        )
+        .await;
+        assert!(result.is_err(), "band 4 does not exist");
+
+        drop(tmp);
+    }
 }
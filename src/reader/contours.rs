@@ -0,0 +1,369 @@
+use crate::models::{
+    geometry::GeometryExtent,
+    layer::{Layer, LayerGeometry},
+    render::Resampling,
+};
+use crate::reader::cog::{open_band, open_source, warp_bands};
+use crate::utils::mvt::{EXTENT, LineFeature, clip_line, encode_lines};
+use gdal::errors::GdalError;
+use std::collections::HashMap;
+use tokio::task;
+
+/// Tiles that would need more contour levels than this are refused
+pub const MAX_LEVELS: usize = 1000;
+/// Pixels warped around the tile so lines carry on across its edges
+const BUFFER: usize = 1;
+
+/// Which band is contoured, and at which values: every `interval` from
+/// `base`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContourOptions {
+    pub band: usize,
+    pub interval: f64,
+    pub base: f64,
+}
+
+/// A grid edge a contour crosses, (row, column, horizontal), from the
+/// top/left corner of the edge
+type Edge = (usize, usize, bool);
+
+/// Contour lines of a band across an extent in `crs` (a tile of `size`
+/// pixels), as a Mapbox Vector Tile with a "contours" layer. Each level is
+/// one feature with its value as the "elevation" property.
+pub async fn contour_tile(
+    layer_obj: Layer,
+    extent: GeometryExtent,
+    crs: i32,
+    size: (usize, usize),
+    options: ContourOptions,
+) -> gdal::errors::Result<Vec<u8>> {
+    task::spawn_blocking(move || {
+        let src_ds = open_source(&layer_obj.path)?;
+        open_band(&src_ds, options.band)?;
+
+        // The same warp as a tile, a pixel wider on each side
+        let res_x = (extent.maxx - extent.minx) / size.0 as f64;
+        let res_y = (extent.maxy - extent.miny) / size.1 as f64;
+        let buffered = GeometryExtent::from((
+            extent.minx - BUFFER as f64 * res_x,
+            extent.miny - BUFFER as f64 * res_y,
+            extent.maxx + BUFFER as f64 * res_x,
+            extent.maxy + BUFFER as f64 * res_y,
+        ));
+        let grid = (size.0 + 2 * BUFFER, size.1 + 2 * BUFFER);
+        let values = warp_bands(
+            &src_ds,
+            &layer_obj.path,
+            &[options.band],
+            layer_obj.render.nodata.as_deref().unwrap_or_default(),
+            &LayerGeometry {
+                crs_code: crs,
+                extent: buffered,
+            },
+            grid,
+            Resampling::Bilinear,
+        )?
+        .remove(0);
+
+        let levels = contour_levels(&values, options.interval, options.base)
+            .map_err(GdalError::BadArgument)?;
+
+        let features = tile_features(&values, grid, size, &levels);
+        Ok(encode_lines("contours", &features))
+    })
+    .await
+    .map_err(|e| GdalError::BadArgument(e.to_string()))?
+}
+
+/// One feature per level with lines, in tile coordinates and clipped to
+/// the tile. Grid positions are pixel centres, at 0.5 from the buffer's
+/// corner.
+fn tile_features(
+    values: &[f32],
+    grid: (usize, usize),
+    size: (usize, usize),
+    levels: &[f64],
+) -> Vec<LineFeature> {
+    let scale = (EXTENT as f64 / size.0 as f64, EXTENT as f64 / size.1 as f64);
+    let to_tile = |(col, row): (f64, f64)| {
+        (
+            (col + 0.5 - BUFFER as f64) * scale.0,
+            (row + 0.5 - BUFFER as f64) * scale.1,
+        )
+    };
+    let mut features = Vec::new();
+    for &level in levels {
+        let mut lines = Vec::new();
+        for line in contour_lines(values, grid, level) {
+            let line: Vec<(f64, f64)> = line.into_iter().map(to_tile).collect();
+            for piece in clip_line(&line, 0.0, EXTENT as f64) {
+                let mut points: Vec<(i32, i32)> = piece
+                    .iter()
+                    .map(|&(x, y)| (x.round() as i32, y.round() as i32))
+                    .collect();
+                points.dedup();
+                if points.len() > 1 {
+                    lines.push(points);
+                }
+            }
+        }
+        if !lines.is_empty() {
+            features.push(LineFeature {
+                lines,
+                properties: vec![("elevation".into(), level)],
+            });
+        }
+    }
+    features
+}
+
+/// A round interval giving about ten contours over a value range
+pub fn default_interval(min: f64, max: f64) -> f64 {
+    let step = (max - min) / 10.0;
+    if !(step.is_finite() && step > 0.0) {
+        return 1.0;
+    }
+    let magnitude = 10f64.powi(step.log10().floor() as i32);
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|m| m * magnitude)
+        .find(|&interval| interval >= step)
+        .unwrap_or(10.0 * magnitude)
+}
+
+/// Every `base + k * interval` between the lowest and highest value
+fn contour_levels(values: &[f32], interval: f64, base: f64) -> Result<Vec<f64>, String> {
+    let (min, max) = values
+        .iter()
+        .filter(|v| !v.is_nan())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &v| {
+            (min.min(v as f64), max.max(v as f64))
+        });
+    if min > max {
+        return Ok(Vec::new());
+    }
+    let first = ((min - base) / interval).ceil();
+    let last = ((max - base) / interval).floor();
+    let count = (last - first + 1.0).max(0.0);
+    if count > MAX_LEVELS as f64 {
+        return Err(format!(
+            "An interval of {} gives {} contour levels in this tile, at most {} are drawn",
+            interval, count, MAX_LEVELS
+        ));
+    }
+    Ok((0..count as usize)
+        .map(|k| base + (first + k as f64) * interval)
+        .collect())
+}
+
+/// Marching squares over a row-major grid. Lines are in grid coordinates
+/// (column, row) and follow the level between the values, cells touching
+/// NaN are skipped.
+fn contour_lines(
+    values: &[f32],
+    (width, height): (usize, usize),
+    level: f64,
+) -> Vec<Vec<(f64, f64)>> {
+    let value = |row: usize, col: usize| values[row * width + col] as f64;
+
+    // Where the level crosses an edge, between its two corners
+    let mut points: HashMap<Edge, (f64, f64)> = HashMap::new();
+    let mut crossing = |(row, col, horizontal): Edge| {
+        points.entry((row, col, horizontal)).or_insert_with(|| {
+            let (r1, c1) = if horizontal {
+                (row, col + 1)
+            } else {
+                (row + 1, col)
+            };
+            let (a, b) = (value(row, col), value(r1, c1));
+            let t = ((level - a) / (b - a)).clamp(0.0, 1.0);
+            (
+                col as f64 + t * (c1 - col) as f64,
+                row as f64 + t * (r1 - row) as f64,
+            )
+        });
+        (row, col, horizontal)
+    };
+
+    let mut segments: Vec<(Edge, Edge)> = Vec::new();
+    for row in 0..height.saturating_sub(1) {
+        for col in 0..width.saturating_sub(1) {
+            let corners = [
+                value(row, col),
+                value(row, col + 1),
+                value(row + 1, col + 1),
+                value(row + 1, col),
+            ];
+            if corners.iter().any(|v| v.is_nan()) {
+                continue;
+            }
+            let case = corners
+                .iter()
+                .fold(0, |case, &v| (case << 1) | (v >= level) as u8);
+            let top = (row, col, true);
+            let bottom = (row + 1, col, true);
+            let left = (row, col, false);
+            let right = (row, col + 1, false);
+            // Saddles are split by the value in the middle of the cell
+            let centre_high = corners.iter().sum::<f64>() / 4.0 >= level;
+            let pairs: &[(Edge, Edge)] = match case {
+                0b0001 | 0b1110 => &[(left, bottom)],
+                0b0010 | 0b1101 => &[(bottom, right)],
+                0b0011 | 0b1100 => &[(left, right)],
+                0b0100 | 0b1011 => &[(top, right)],
+                0b0110 | 0b1001 => &[(top, bottom)],
+                0b0111 | 0b1000 => &[(left, top)],
+                0b0101 if centre_high => &[(left, top), (bottom, right)],
+                0b0101 => &[(top, right), (left, bottom)],
+                0b1010 if centre_high => &[(top, right), (left, bottom)],
+                0b1010 => &[(left, top), (bottom, right)],
+                _ => &[],
+            };
+            for &(a, b) in pairs {
+                segments.push((crossing(a), crossing(b)));
+            }
+        }
+    }
+
+    // Chain the segments through the edges they share
+    let mut by_edge: HashMap<Edge, Vec<usize>> = HashMap::new();
+    for (i, &(a, b)) in segments.iter().enumerate() {
+        by_edge.entry(a).or_default().push(i);
+        by_edge.entry(b).or_default().push(i);
+    }
+    let mut used = vec![false; segments.len()];
+    let next = |edge: Edge, used: &mut Vec<bool>| {
+        let i = *by_edge.get(&edge)?.iter().find(|&&i| !used[i])?;
+        used[i] = true;
+        let (a, b) = segments[i];
+        Some(if a == edge { b } else { a })
+    };
+    let mut lines = Vec::new();
+    for i in 0..segments.len() {
+        if used[i] {
+            continue;
+        }
+        used[i] = true;
+        let (a, b) = segments[i];
+        let mut forward = vec![a, b];
+        while let Some(edge) = next(*forward.last().unwrap(), &mut used) {
+            forward.push(edge);
+        }
+        let mut backward = Vec::new();
+        while let Some(edge) = next(*backward.last().unwrap_or(&a), &mut used) {
+            backward.push(edge);
+        }
+        backward.reverse();
+        backward.extend(forward);
+        lines.push(backward.into_iter().map(|edge| points[&edge]).collect());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::cog::tests::test_raster;
+
+    #[test]
+    fn test_default_interval() {
+        assert_eq!(default_interval(0.0, 1000.0), 100.0);
+        assert_eq!(default_interval(0.0, 1500.0), 200.0);
+        assert_eq!(default_interval(-3.0, 0.5), 0.5);
+        assert_eq!(default_interval(5.0, 5.0), 1.0);
+    }
+
+    #[test]
+    fn test_contour_levels() {
+        let values = [12.0, f32::NAN, 47.0, 30.0];
+        assert_eq!(
+            contour_levels(&values, 10.0, 0.0),
+            Ok(vec![20.0, 30.0, 40.0])
+        );
+        assert_eq!(
+            contour_levels(&values, 10.0, 5.0),
+            Ok(vec![15.0, 25.0, 35.0, 45.0])
+        );
+        assert_eq!(contour_levels(&[f32::NAN], 10.0, 0.0), Ok(vec![]));
+        assert!(contour_levels(&values, 0.01, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_contour_lines() {
+        // A ramp rising to the right, crossed by one vertical line
+        let ramp = [0.0, 1.0, 2.0, 3.0, 0.0, 1.0, 2.0, 3.0, 0.0, 1.0, 2.0, 3.0];
+        let lines = contour_lines(&ramp, (4, 3), 1.5);
+        assert_eq!(lines.len(), 1);
+        let mut line = lines[0].clone();
+        line.sort_by(|a, b| a.1.total_cmp(&b.1));
+        assert_eq!(line, vec![(1.5, 0.0), (1.5, 1.0), (1.5, 2.0)]);
+
+        // A peak gives a closed ring around it
+        let peak = [0.0, 0.0, 0.0, 0.0, 4.0, 0.0, 0.0, 0.0, 0.0];
+        let lines = contour_lines(&peak, (3, 3), 2.0);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), 5);
+        assert_eq!(lines[0].first(), lines[0].last());
+
+        // Nothing through NaN cells
+        let gap = [0.0, f32::NAN, 2.0, 0.0, 1.0, 2.0, 0.0, 1.0, 2.0];
+        let lines = contour_lines(&gap, (3, 3), 1.5);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), 2);
+    }
+
+    #[test]
+    fn test_tile_features() {
+        // A 4×4 tile and its buffer, rising 1 per column, crossed by 2.5
+        let values: Vec<f32> = (0..36).map(|i| (i % 6) as f32).collect();
+        let features = tile_features(&values, (6, 6), (4, 4), &[2.5, 10.0]);
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0].properties, vec![("elevation".to_string(), 2.5)]);
+        let mut line = features[0].lines[0].clone();
+        line.sort();
+        let ys = [0, 512, 1536, 2560, 3584, 4096];
+        assert_eq!(line, ys.map(|y| (2048, y)).to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_contour_tile() {
+        // 64×64 pixels of 4 m in EPSG:3857 from (0, 256), rising 1 per column
+        let (_tmp, layer) = test_raster(
+            (64, 64),
+            [0.0, 4.0, 0.0, 256.0, 0.0, -4.0],
+            (0..64 * 64).map(|i| (i % 64) as f32).collect(),
+            None,
+        );
+        let options = ContourOptions {
+            band: 1,
+            interval: 10.0,
+            base: 0.0,
+        };
+
+        let tile = contour_tile(
+            layer.clone(),
+            (0.0, 0.0, 256.0, 256.0).into(),
+            3857,
+            (64, 64),
+            options.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(tile[0], 0x1a); // A layer
+        assert!(tile.windows(9).any(|w| w == b"elevation"));
+
+        // Off the raster is an empty tile, and a missing band an error
+        let outside = (1000.0, 1000.0, 1256.0, 1256.0).into();
+        let empty = contour_tile(layer.clone(), outside, 3857, (64, 64), options.clone())
+            .await
+            .unwrap();
+        assert!(empty.is_empty());
+        let bad_band = ContourOptions { band: 2, ..options };
+        let tile = (0.0, 0.0, 256.0, 256.0).into();
+        assert!(
+            contour_tile(layer, tile, 3857, (64, 64), bad_band)
+                .await
+                .is_err()
+        );
+    }
+}
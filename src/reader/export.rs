@@ -0,0 +1,370 @@
+use crate::models::{
+    geometry::GeometryExtent,
+    layer::{Layer, LayerGeometry},
+    render::Resampling,
+    style::Interpolation,
+};
+use crate::reader::cog::{default_resampling, open_source};
+use gdal::{
+    Dataset, DriverManager, cpl::CslStringList, errors::GdalError, raster::RasterCreationOptions,
+    vsi,
+};
+use std::ffi::CStr;
+use std::fs::File;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::task;
+
+/// Exports bigger than this many bytes uncompressed, across every band, are
+/// refused
+pub const MAX_EXPORT_BYTES: usize = 500_000_000;
+
+static EXPORT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    GTiff,
+    Cog,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::GTiff => "image/tiff; application=geotiff",
+            ExportFormat::Cog => "image/tiff; application=geotiff; profile=cloud-optimized",
+        }
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gtiff" | "geotiff" | "tif" | "tiff" => Ok(ExportFormat::GTiff),
+            "cog" => Ok(ExportFormat::Cog),
+            _ => Err(format!("Unknown format '{}', expected gtiff or cog", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub bbox: GeometryExtent,
+    pub crs: i32,                // Of the bbox
+    pub target_crs: Option<i32>, // Reproject to, the source CRS if None
+    pub resolution: Option<f64>, // Pixel size in target CRS units
+    pub resampling: Option<Resampling>,
+    pub format: ExportFormat,
+}
+
+/// Cut a layer's source to a bbox as a GeoTIFF or COG in a temporary file.
+/// With neither a target CRS nor a resolution the source pixels are copied
+/// as they are, otherwise they are warped. Nodata, masks and the CRS carry
+/// over.
+pub async fn export_extent(layer_obj: Layer, options: ExportOptions) -> gdal::errors::Result<File> {
+    task::spawn_blocking(move || {
+        let bad = |e: anyhow::Error| GdalError::BadArgument(e.to_string());
+        let src_ds = open_source(&layer_obj.path)?;
+        let source_crs = layer_obj.source_geometry.crs_code;
+        let requested = LayerGeometry {
+            crs_code: options.crs,
+            extent: options.bbox.clone(),
+        };
+        let source_extent = requested
+            .edge_bounds(source_crs)
+            .map_err(bad)?
+            .intersection(&layer_obj.source_geometry.extent)
+            .ok_or_else(|| GdalError::BadArgument("bbox does not overlap the layer".into()))?;
+
+        // The source pixels under the bbox
+        let gt = src_ds.geo_transform()?;
+        let (width, height) = src_ds.raster_size();
+        let col = ((source_extent.minx - gt[0]) / gt[1]).floor().max(0.0) as usize;
+        let row = ((source_extent.maxy - gt[3]) / gt[5]).floor().max(0.0) as usize;
+        let win_width = (((source_extent.maxx - gt[0]) / gt[1]).ceil() as usize).min(width) - col;
+        let win_height = (((source_extent.miny - gt[3]) / gt[5]).ceil() as usize).min(height) - row;
+
+        let pixel_bytes = pixel_bytes(&src_ds)?;
+        let target_crs = options.target_crs.unwrap_or(source_crs);
+        let intermediate = if target_crs == source_crs && options.resolution.is_none() {
+            if options.resampling.is_some() {
+                return Err(GdalError::BadArgument(
+                    "resampling needs a target_crs or resolution, a crop copies the source pixels"
+                        .into(),
+                ));
+            }
+            check_size(win_width, win_height, pixel_bytes)?;
+            translate(
+                &src_ds,
+                &[
+                    "-of".into(),
+                    "VRT".into(),
+                    "-srcwin".into(),
+                    col.to_string(),
+                    row.to_string(),
+                    win_width.to_string(),
+                    win_height.to_string(),
+                ],
+            )?
+        } else {
+            let extent = requested
+                .edge_bounds(target_crs)
+                .map_err(bad)?
+                .intersection(
+                    &layer_obj
+                        .source_geometry
+                        .edge_bounds(target_crs)
+                        .map_err(bad)?,
+                )
+                .ok_or_else(|| GdalError::BadArgument("bbox does not overlap the layer".into()))?;
+            let (span_x, span_y) = (extent.maxx - extent.minx, extent.maxy - extent.miny);
+            // About as many pixels as the source has under the bbox
+            let resolution = options.resolution.unwrap_or_else(|| {
+                (span_x * span_y / (win_width.max(1) * win_height.max(1)) as f64).sqrt()
+            });
+            check_size(
+                (span_x / resolution).ceil() as usize,
+                (span_y / resolution).ceil() as usize,
+                pixel_bytes,
+            )?;
+            let resampling = match options.resampling {
+                Some(resampling) => resampling,
+                None if layer_obj.interpolation == Interpolation::Exact => Resampling::Nearest,
+                None => default_resampling(&src_ds.rasterband(1)?),
+            };
+            warp(
+                &src_ds,
+                &[
+                    "-of".into(),
+                    "VRT".into(),
+                    "-t_srs".into(),
+                    format!("EPSG:{}", target_crs),
+                    "-te".into(),
+                    extent.minx.to_string(),
+                    extent.miny.to_string(),
+                    extent.maxx.to_string(),
+                    extent.maxy.to_string(),
+                    "-tr".into(),
+                    resolution.to_string(),
+                    resolution.to_string(),
+                    "-r".into(),
+                    resampling.as_str().into(),
+                ],
+            )?
+        };
+
+        tiff_file(&intermediate, options.format)
+    })
+    .await
+    .map_err(|e| GdalError::BadArgument(e.to_string()))?
+}
+
+/// A dataset written out as compressed GeoTIFF or COG bytes
+pub(crate) fn tiff_bytes(ds: &Dataset, format: ExportFormat) -> gdal::errors::Result<Vec<u8>> {
+    let path = format!(
+        "/vsimem/tileyolo_export_{}.tif",
+        EXPORT_COUNT.fetch_add(1, Ordering::Relaxed)
+    );
+    let result = write_tiff(ds, format, &path);
+    let bytes = vsi::get_vsi_mem_file_bytes_owned(&path);
+    result?;
+    bytes
+}
+
+/// A dataset written out as a compressed GeoTIFF or COG to a temporary file,
+/// which leaves the disk once the returned handle is closed
+fn tiff_file(ds: &Dataset, format: ExportFormat) -> gdal::errors::Result<File> {
+    let io = |e: std::io::Error| GdalError::BadArgument(e.to_string());
+    let tmp = tempfile::Builder::new()
+        .prefix("tileyolo_export_")
+        .suffix(".tif")
+        .tempfile()
+        .map_err(io)?;
+    write_tiff(ds, format, &tmp.path().to_string_lossy())?;
+    tmp.reopen().map_err(io)
+}
+
+fn write_tiff(ds: &Dataset, format: ExportFormat, path: &str) -> gdal::errors::Result<()> {
+    let (driver, creation_options) = match format {
+        ExportFormat::GTiff => (
+            "GTiff",
+            ["COMPRESS=DEFLATE", "TILED=YES", "BIGTIFF=IF_SAFER"].as_slice(),
+        ),
+        ExportFormat::Cog => ("COG", ["COMPRESS=DEFLATE", "BIGTIFF=IF_SAFER"].as_slice()),
+    };
+    let creation_options: RasterCreationOptions = creation_options.iter().copied().collect();
+    let driver = DriverManager::get_driver_by_name(driver)?;
+    // The copy is closed, and so complete on disk, when it drops
+    ds.create_copy(&driver, path, &creation_options)?
+        .flush_cache()
+}
+
+/// Bytes of one pixel across every band of a dataset
+fn pixel_bytes(ds: &Dataset) -> gdal::errors::Result<usize> {
+    let mut bytes = 0;
+    for band in ds.rasterbands() {
+        bytes += band?.band_type().bytes() as usize;
+    }
+    Ok(bytes)
+}
+
+fn check_size(width: usize, height: usize, pixel_bytes: usize) -> gdal::errors::Result<()> {
+    // A tiny resolution saturates the sizes, so the product can overflow
+    if width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(pixel_bytes))
+        .is_none_or(|bytes| bytes > MAX_EXPORT_BYTES)
+    {
+        return Err(GdalError::BadArgument(format!(
+            "The export would be {} × {} pixels of {} bytes, at most {} bytes are allowed",
+            width, height, pixel_bytes, MAX_EXPORT_BYTES
+        )));
+    }
+    Ok(())
+}
+
+fn last_error(method: &'static str) -> GdalError {
+    let msg = unsafe { CStr::from_ptr(gdal_sys::CPLGetLastErrorMsg()) };
+    GdalError::NullPointer {
+        method_name: method,
+        msg: msg.to_string_lossy().into_owned(),
+    }
+}
+
+/// gdal_translate with command line style arguments, to an unnamed dataset
+fn translate(src_ds: &Dataset, args: &[String]) -> gdal::errors::Result<Dataset> {
+    let args: CslStringList = args.iter().map(String::as_str).collect();
+    // The gdal crate doesn't wrap the gdal_utils programs yet
+    unsafe {
+        let options = gdal_sys::GDALTranslateOptionsNew(args.as_ptr(), null_mut());
+        if options.is_null() {
+            return Err(last_error("GDALTranslateOptionsNew"));
+        }
+        let ds = gdal_sys::GDALTranslate(c"".as_ptr(), src_ds.c_dataset(), options, null_mut());
+        gdal_sys::GDALTranslateOptionsFree(options);
+        if ds.is_null() {
+            return Err(last_error("GDALTranslate"));
+        }
+        Ok(Dataset::from_c_dataset(ds))
+    }
+}
+
+/// gdalwarp with command line style arguments, to an unnamed dataset. It
+/// reads from `src_ds` lazily when the output is a VRT, so keep it around.
+fn warp(src_ds: &Dataset, args: &[String]) -> gdal::errors::Result<Dataset> {
+    let args: CslStringList = args.iter().map(String::as_str).collect();
+    unsafe {
+        let options = gdal_sys::GDALWarpAppOptionsNew(args.as_ptr(), null_mut());
+        if options.is_null() {
+            return Err(last_error("GDALWarpAppOptionsNew"));
+        }
+        let mut sources = [src_ds.c_dataset()];
+        let ds = gdal_sys::GDALWarp(
+            c"".as_ptr(),
+            null_mut(),
+            1,
+            sources.as_mut_ptr(),
+            options,
+            null_mut(),
+        );
+        gdal_sys::GDALWarpAppOptionsFree(options);
+        if ds.is_null() {
+            return Err(last_error("GDALWarp"));
+        }
+        Ok(Dataset::from_c_dataset(ds))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::cog::tests::test_raster;
+
+    fn options(bbox: (f64, f64, f64, f64), crs: i32) -> ExportOptions {
+        ExportOptions {
+            bbox: GeometryExtent::from(bbox),
+            crs,
+            target_crs: None,
+            resolution: None,
+            resampling: None,
+            format: ExportFormat::GTiff,
+        }
+    }
+
+    /// Open an exported file through /vsimem
+    fn open_file(name: &str, mut file: File) -> Dataset {
+        let mut bytes = Vec::new();
+        std::io::Read::read_to_end(&mut file, &mut bytes).unwrap();
+        let path = format!("/vsimem/{}", name);
+        vsi::create_mem_file(&path, bytes).unwrap();
+        Dataset::open(&path).unwrap()
+    }
+
+    #[test]
+    fn test_check_size() {
+        assert!(check_size(1000, 1000, 4).is_ok());
+        assert!(check_size(MAX_EXPORT_BYTES, 1, 1).is_ok());
+        assert!(check_size(MAX_EXPORT_BYTES, 1, 2).is_err());
+        assert!(check_size(MAX_EXPORT_BYTES / 12, 1, 12).is_ok());
+        assert!(check_size(MAX_EXPORT_BYTES / 12 + 1, 1, 12).is_err());
+        assert!(check_size(usize::MAX, 2, 1).is_err());
+        assert!(check_size(usize::MAX / 2, 1, 4).is_err());
+        assert!(check_size((1e-12f64 / 1e-300).ceil() as usize, usize::MAX, 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_extent() {
+        // 4×4 pixels of 10 m in EPSG:3857 from (0, 40), values 0..16, 5 is nodata
+        let (_tmp, layer) = test_raster(
+            (4, 4),
+            [0.0, 10.0, 0.0, 40.0, 0.0, -10.0],
+            (0..16).map(|v| v as f32).collect(),
+            Some(5.0),
+        );
+
+        // A plain crop snaps out to whole source pixels
+        let file = export_extent(layer.clone(), options((12.0, 15.0, 28.0, 38.0), 3857))
+            .await
+            .unwrap();
+        let ds = open_file("crop.tif", file);
+        assert_eq!(ds.raster_size(), (2, 3));
+        assert_eq!(
+            ds.geo_transform().unwrap(),
+            [10.0, 10.0, 0.0, 40.0, 0.0, -10.0]
+        );
+        let band = ds.rasterband(1).unwrap();
+        assert_eq!(band.no_data_value(), Some(5.0));
+        let values = band.read_as::<f32>((0, 0), (2, 3), (2, 3), None).unwrap();
+        assert_eq!(values.data(), &[1.0, 2.0, 5.0, 6.0, 9.0, 10.0]);
+        assert_eq!(ds.spatial_ref().unwrap().auth_code().unwrap(), 3857);
+
+        // Reprojected to lon/lat as a COG
+        let mut reprojected = options((0.0, 0.0, 40.0, 40.0), 3857);
+        reprojected.target_crs = Some(4326);
+        reprojected.format = ExportFormat::Cog;
+        let file = export_extent(layer.clone(), reprojected).await.unwrap();
+        let ds = open_file("warped.tif", file);
+        assert_eq!(ds.spatial_ref().unwrap().auth_code().unwrap(), 4326);
+        assert_eq!(ds.rasterband(1).unwrap().no_data_value(), Some(5.0));
+        let (width, height) = ds.raster_size();
+        assert!((3..=5).contains(&width) && (3..=5).contains(&height));
+
+        // Resampling only means something when warping
+        let mut resampled = options((12.0, 15.0, 28.0, 38.0), 3857);
+        resampled.resampling = Some(Resampling::Cubic);
+        assert!(
+            export_extent(layer.clone(), resampled.clone())
+                .await
+                .is_err()
+        );
+        resampled.resolution = Some(5.0);
+        assert!(export_extent(layer.clone(), resampled).await.is_ok());
+
+        assert!(
+            export_extent(layer, options((100.0, 100.0, 200.0, 200.0), 3857))
+                .await
+                .is_err()
+        );
+    }
+}
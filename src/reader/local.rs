@@ -1,19 +1,19 @@
-use crate::config::Config;
 use crate::{
     models::{
-        geometry::GeometryExtent,
-        layer::{Layer, LayerGeometry},
-        responses::TileResponse,
+        layer::Layer, render::RenderOptions, responses::TileResponse, style::ColourMap,
+        tms::TileMatrixSet,
     },
     reader::{
-        cog::process_cog,
-        metadata::{LayerMetadata, MetadataCache, key_for, load_cache, save_cache},
+        cog::render_extent,
+        metadata::{
+            LayerMetadata, MetadataCache, is_geotiff, key_for, load_cache, load_sidecar,
+            read_layer_metadata, save_cache, style_for,
+        },
     },
     traits::TileReader,
     utils::{status::print_layer_summary, style::is_builtin_palette},
 };
 use async_trait::async_trait;
-use gdal::{Dataset, Metadata};
 use indicatif::{ProgressBar, ProgressStyle};
 use std::{
     collections::HashMap,
@@ -37,18 +37,7 @@ impl LocalTileReader {
             .min_depth(2)
             .into_iter()
             .filter_map(Result::ok)
-            .filter(|e| {
-                e.path()
-                    .extension()
-                    .and_then(|s| s.to_str())
-                    .map(|ext| {
-                        ext.eq_ignore_ascii_case("tif")
-                            || ext.eq_ignore_ascii_case("tiff")
-                            || ext.eq_ignore_ascii_case("geotiff")
-                            || ext.eq_ignore_ascii_case("geotif")
-                    })
-                    .unwrap_or(false)
-            })
+            .filter(|e| is_geotiff(e.path()))
             .collect();
 
         // If no files found, return empty
@@ -110,14 +99,15 @@ impl LocalTileReader {
                 .as_secs();
 
             // If unchanged (size + mtime), reuse metadata; style re‐derived from path
-            if let Some(meta) = old_cache.get(&rel_key) {
-                if meta.size_bytes == file_bytes && meta.last_modified == last_modified_secs {
-                    let layer = meta.to_layer(&path).await;
-                    layers.push(layer.clone());
-                    new_cache.insert(rel_key.clone(), meta.clone());
-                    pb.inc(1);
-                    continue;
-                }
+            if let Some(meta) = old_cache
+                .get(&rel_key)
+                .filter(|m| m.size_bytes == file_bytes && m.last_modified == last_modified_secs)
+            {
+                let layer = meta.to_layer(&path).await;
+                layers.push(layer.clone());
+                new_cache.insert(rel_key.clone(), meta.clone());
+                pb.inc(1);
+                continue;
             }
 
             // Otherwise read fresh via GDAL
@@ -157,83 +147,23 @@ impl LocalTileReader {
     }
 
     async fn get_tiff_metadata(entry: DirEntry) -> anyhow::Result<Layer> {
-        // (unchanged)
         let path = entry.path().to_path_buf();
-        let ds = Dataset::open(&path)?;
-
-        // Compute the native geotransform → extent
-        let gt = ds.geo_transform()?;
-        let origin_x = gt[0];
-        let pixel_width = gt[1];
-        let origin_y = gt[3];
-        let pixel_height = gt[5];
-        let (width, height) = ds.raster_size();
-        let extent = GeometryExtent {
-            minx: origin_x,
-            maxx: origin_x + pixel_width * (width as f64),
-            miny: origin_y + pixel_height * (height as f64),
-            maxy: origin_y,
-        };
-
-        let file_stem = path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("<unknown>")
-            .to_string();
         let file_bytes = entry.metadata().map(|m| m.len()).unwrap_or(0);
-        let style_name = path
-            .parent()
-            .and_then(|p| p.file_name())
-            .and_then(|s| s.to_str())
-            .unwrap_or("default");
-        let colour_stops = if is_builtin_palette(style_name) {
-            Vec::new()
+        let colour_map = if is_builtin_palette(style_for(&path)) {
+            ColourMap::default()
         } else {
             let style_path = entry.path().parent().unwrap().join("style.txt");
             crate::utils::style::parse_style_file(&style_path).unwrap_or_default()
         };
-        let layout_opt = ds.metadata_item("LAYOUT", "IMAGE_STRUCTURE");
-        let is_cog = layout_opt
-            .as_deref()
-            .map(|v| v.eq_ignore_ascii_case("COG"))
-            .unwrap_or(false);
-        let sref = ds
-            .spatial_ref()
-            .unwrap_or_else(|e| panic!("❌ CRS missing for '{}': {}", file_stem, e));
-        let auth_code = sref.auth_code().unwrap_or(0);
-        let band = ds
-            .rasterband(Config::default().default_raster_band)
-            .unwrap_or_else(|e| panic!("❌ Failed to get raster band for '{}': {}", file_stem, e));
-        let (min_value, max_value) = band
-            .compute_raster_min_max(false)
-            .map(|stats| (stats.min as f32, stats.max as f32))
-            .unwrap_or_else(|e| panic!("❌ Failed to get min/max for '{}': {}", file_stem, e));
         let last_modified = entry
             .metadata()
             .ok()
             .and_then(|m| m.modified().ok())
             .unwrap_or(SystemTime::now());
 
-        // Create the Layer object
-        let source_geometry = LayerGeometry {
-            crs_code: auth_code,
-            extent,
-        };
-        let cached_geometry = source_geometry.generate_cached_geometry_sync()?;
-
-        Ok(Layer {
-            layer: file_stem.clone(),
-            style: style_name.to_string(),
-            path: path.clone(),
-            size_bytes: file_bytes,
-            source_geometry,
-            cached_geometry,
-            colour_stops,
-            min_value,
-            max_value,
-            is_cog,
-            last_modified,
-        })
+        let mut layer = read_layer_metadata(&path, file_bytes, last_modified, colour_map)?;
+        layer.render = load_sidecar(&path);
+        Ok(layer)
     }
 }
 
@@ -249,49 +179,51 @@ impl TileReader for LocalTileReader {
         all_layers
     }
 
+    async fn get_layer(&self, layer: &str) -> Option<Layer> {
+        self.layers
+            .get(layer)
+            .and_then(|styles| styles.first())
+            .cloned()
+    }
+
     async fn get_tile(
         &self,
         layer: &str,
+        tms: &TileMatrixSet,
         z: u8,
         x: u32,
         y: u32,
         _style: Option<&str>,
+        options: &RenderOptions,
     ) -> anyhow::Result<TileResponse, String> {
-        let tile_size = (256, 256);
-
         let layer_obj = self
             .layers
             .get(layer)
             .and_then(|styles| styles.first())
             .ok_or_else(|| format!("Layer not found: '{}'", layer))?;
 
-        let extent: GeometryExtent = tile_bounds_to_3857(z, x, y);
+        let (Some(matrix), Some(extent)) = (tms.matrix(z), tms.tile_bounds(z, x, y)) else {
+            return Err(format!("Tile {}/{}/{} is outside {}", z, x, y, tms.id));
+        };
+        let tile_size = (matrix.tile_width as usize, matrix.tile_height as usize);
 
-        // always hand off to process_cog; it will do the extent-check itself
-        let png_data = process_cog(layer_obj.path.clone(), extent, layer_obj.clone(), tile_size)
-            .await
-            .map_err(|e| e.to_string())?;
+        // always hand off to render_extent; it will do the extent-check itself
+        let options = options.merged_over(&layer_obj.render)?;
+        let content_type = options.format.unwrap_or_default().content_type();
+        let tile_data = render_extent(
+            layer_obj.path.clone(),
+            extent,
+            tms.crs_code,
+            layer_obj.clone(),
+            tile_size,
+            options,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
 
         Ok(TileResponse {
-            content_type: "image/png".into(),
-            bytes: png_data,
+            content_type: content_type.into(),
+            bytes: tile_data,
         })
     }
 }
-
-fn tile_bounds_to_3857(z: u8, x: u32, y: u32) -> GeometryExtent {
-    let tile_size = 256.0;
-    let initial_resolution = 2.0 * 20037508.342789244 / tile_size;
-    let res = initial_resolution / (2f64.powi(z as i32));
-    let minx = x as f64 * tile_size * res - 20037508.342789244;
-    let maxx = (x as f64 + 1.0) * tile_size * res - 20037508.342789244;
-    let maxy = 20037508.342789244 - y as f64 * tile_size * res;
-    let miny = 20037508.342789244 - (y as f64 + 1.0) * tile_size * res;
-
-    GeometryExtent {
-        minx,
-        miny,
-        maxx,
-        maxy,
-    }
-}
@@ -1,19 +1,23 @@
 // src/reader/metadata.rs
 
 use crate::{
+    Config,
     models::{
         geometry::GeometryExtent,
         layer::{Layer, LayerGeometry},
-        style::ColourStop,
+        render::RenderOptions,
+        style::ColourMap,
     },
     utils::style::{is_builtin_palette, parse_style_file},
 };
+use anyhow::Context;
 use csv::{ReaderBuilder, WriterBuilder};
+use gdal::{Dataset, Metadata};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     path::Path,
-    time::{Duration, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 /// The minimal fields we need to cache on disk.
@@ -26,6 +30,8 @@ pub struct LayerMetadata {
     pub min_value: f32,
     pub max_value: f32,
     pub is_cog: bool,
+    pub width: usize,
+    pub height: usize,
 
     // split extent tuple into four CSV columns
     pub extent_minx: f64,
@@ -51,6 +57,8 @@ impl LayerMetadata {
             min_value: layer.min_value,
             max_value: layer.max_value,
             is_cog: layer.is_cog,
+            width: layer.raster_size.0,
+            height: layer.raster_size.1,
             extent_minx: layer.source_geometry.extent.minx,
             extent_miny: layer.source_geometry.extent.miny,
             extent_maxx: layer.source_geometry.extent.maxx,
@@ -61,14 +69,10 @@ impl LayerMetadata {
     /// Reconstruct a Layer (including style/colour_stops) from metadata + actual file path
     pub async fn to_layer(&self, path: &Path) -> Layer {
         // Style is determined at runtime from the immediate parent folder
-        let style_name = path
-            .parent()
-            .and_then(|p| p.file_name())
-            .and_then(|s| s.to_str())
-            .unwrap_or("default");
-
-        let colour_stops: Vec<ColourStop> = if is_builtin_palette(style_name) {
-            Vec::new()
+        let style_name = style_for(path);
+
+        let colour_map = if is_builtin_palette(style_name) {
+            ColourMap::default()
         } else {
             let style_path = path.parent().unwrap().join("style.txt");
             parse_style_file(&style_path).unwrap_or_default()
@@ -94,12 +98,16 @@ impl LayerMetadata {
             path: path.to_path_buf(),
             size_bytes: self.size_bytes,
             source_geometry,
+            raster_size: (self.width, self.height),
             cached_geometry,
-            colour_stops,
+            colour_stops: colour_map.stops,
+            interpolation: colour_map.interpolation,
             min_value: self.min_value,
             max_value: self.max_value,
             is_cog: self.is_cog,
             last_modified,
+            render: load_sidecar(path),
+            cache: Default::default(),
         }
     }
 }
@@ -134,3 +142,110 @@ pub fn key_for(path: &Path, _root: &Path) -> String {
         .unwrap_or_default()
         .to_string()
 }
+
+/// True if the path has one of the GeoTIFF extensions we serve
+pub fn is_geotiff(path: &Path) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .map(|ext| {
+            ext.eq_ignore_ascii_case("tif")
+                || ext.eq_ignore_ascii_case("tiff")
+                || ext.eq_ignore_ascii_case("geotiff")
+                || ext.eq_ignore_ascii_case("geotif")
+        })
+        .unwrap_or(false)
+}
+
+/// The style of a layer is the name of the folder it sits in
+pub fn style_for(path: &Path) -> &str {
+    path.parent()
+        .and_then(|p| p.file_name())
+        .and_then(|s| s.to_str())
+        .unwrap_or("default")
+}
+
+/// Open the raster with GDAL and build a full Layer from it. The path may be
+/// anything GDAL can open (a local file or a /vsis3/ object), so the caller
+/// supplies the file size, mtime and colour map it resolved itself, and
+/// fills in the sidecar render options.
+pub fn read_layer_metadata(
+    path: &Path,
+    size_bytes: u64,
+    last_modified: SystemTime,
+    colour_map: ColourMap,
+) -> anyhow::Result<Layer> {
+    let ds = Dataset::open(path)?;
+
+    // Compute the native geotransform → extent
+    let gt = ds.geo_transform()?;
+    let origin_x = gt[0];
+    let pixel_width = gt[1];
+    let origin_y = gt[3];
+    let pixel_height = gt[5];
+    let (width, height) = ds.raster_size();
+    let extent = GeometryExtent {
+        minx: origin_x,
+        maxx: origin_x + pixel_width * (width as f64),
+        miny: origin_y + pixel_height * (height as f64),
+        maxy: origin_y,
+    };
+
+    let file_stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("<unknown>")
+        .to_string();
+    let layout_opt = ds.metadata_item("LAYOUT", "IMAGE_STRUCTURE");
+    let is_cog = layout_opt
+        .as_deref()
+        .map(|v| v.eq_ignore_ascii_case("COG"))
+        .unwrap_or(false);
+    let sref = ds
+        .spatial_ref()
+        .with_context(|| format!("CRS missing for '{}'", file_stem))?;
+    let auth_code = sref.auth_code().unwrap_or(0);
+    let band = ds
+        .rasterband(Config::default().default_raster_band)
+        .with_context(|| format!("Failed to get raster band for '{}'", file_stem))?;
+    let (min_value, max_value) = band
+        .compute_raster_min_max(false)
+        .map(|stats| (stats.min as f32, stats.max as f32))
+        .with_context(|| format!("Failed to get min/max for '{}'", file_stem))?;
+
+    // Create the Layer object
+    let source_geometry = LayerGeometry {
+        crs_code: auth_code,
+        extent,
+    };
+    let cached_geometry = source_geometry.generate_cached_geometry_sync()?;
+
+    Ok(Layer {
+        layer: file_stem,
+        style: style_for(path).to_string(),
+        path: path.to_path_buf(),
+        size_bytes,
+        source_geometry,
+        raster_size: (width, height),
+        cached_geometry,
+        colour_stops: colour_map.stops,
+        interpolation: colour_map.interpolation,
+        min_value,
+        max_value,
+        is_cog,
+        last_modified,
+        render: RenderOptions::default(),
+        cache: Default::default(),
+    })
+}
+
+/// Render options from the optional `{layer}.json` next to a local raster
+pub fn load_sidecar(path: &Path) -> RenderOptions {
+    let sidecar = path.with_extension("json");
+    let Ok(content) = std::fs::read_to_string(&sidecar) else {
+        return RenderOptions::default();
+    };
+    RenderOptions::from_json(&content).unwrap_or_else(|e| {
+        eprintln!("⚠️ Ignoring {}: {}", sidecar.display(), e);
+        RenderOptions::default()
+    })
+}
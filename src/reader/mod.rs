@@ -1,4 +1,10 @@
 pub mod cog;
+pub mod contours;
+pub mod export;
 pub mod local;
 pub mod metadata;
+pub mod point;
+pub mod profile;
 pub mod s3;
+pub mod terrain;
+pub mod zonal;
@@ -0,0 +1,176 @@
+use crate::Config;
+use crate::geometry::projection::Projector;
+use crate::models::{
+    layer::Layer,
+    render::{NodataValue, Terrain},
+    responses::{BandValue, PointSample, TerrainSample},
+};
+use crate::reader::{
+    cog::open_source,
+    terrain::{source_gradients, terrain_value},
+};
+use gdal::errors::GdalError;
+use tokio::task;
+
+/// Every band's value under each point given in `crs` (x/y in lon/lat
+/// order), and the slope and aspect of the first band if `terrain` is set.
+/// `None` for points outside the raster.
+pub async fn sample_points(
+    layer_obj: Layer,
+    points: Vec<(f64, f64)>,
+    crs: i32,
+    terrain: bool,
+) -> gdal::errors::Result<Vec<Option<PointSample>>> {
+    task::spawn_blocking(move || {
+        let to_source = source_projector(&layer_obj, crs)?;
+
+        let ds = open_source(&layer_obj.path)?;
+        let gt = ds.geo_transform()?;
+        let (width, height) = ds.raster_size();
+        let nodata: &[NodataValue] = layer_obj.render.nodata.as_deref().unwrap_or_default();
+
+        points
+            .into_iter()
+            .map(|(x, y)| {
+                let Ok((sx, sy)) = to_source.convert((x, y)) else {
+                    // No image in the source CRS, so not on the raster either
+                    return Ok(None);
+                };
+
+                // Invert the (north up) geotransform to get the pixel under the point
+                let col = ((sx - gt[0]) / gt[1]).floor();
+                let row = ((sy - gt[3]) / gt[5]).floor();
+                if !(col >= 0.0 && row >= 0.0 && col < width as f64 && row < height as f64) {
+                    return Ok(None);
+                }
+                let (col, row) = (col as isize, row as isize);
+
+                let mut values = Vec::with_capacity(ds.raster_count());
+                for b in 1..=ds.raster_count() {
+                    let band = ds.rasterband(b)?;
+                    let value = band
+                        .read_as::<f64>((col, row), (1, 1), (1, 1), None)?
+                        .data()[0];
+
+                    let flags = band.mask_flags()?;
+                    let masked = if flags.is_all_valid() {
+                        false
+                    } else {
+                        band.open_mask_band()?
+                            .read_as::<u8>((col, row), (1, 1), (1, 1), None)?
+                            .data()[0]
+                            == 0
+                    };
+                    values.push(BandValue {
+                        band: b,
+                        value: (!value.is_nan()).then_some(value),
+                        nodata: masked || is_nodata_value(value, band.no_data_value(), nodata),
+                    });
+                }
+
+                let terrain = if terrain {
+                    let band = Config::default().default_raster_band;
+                    let gradient = source_gradients(
+                        &ds,
+                        layer_obj.source_geometry.crs_code,
+                        band,
+                        nodata,
+                        (col as usize, row as usize),
+                        (1, 1),
+                    )?[0];
+                    let z_factor = layer_obj.render.z_factor.unwrap_or(1.0);
+                    let value =
+                        |product| gradient.and_then(|g| terrain_value(g, product, z_factor));
+                    Some(TerrainSample {
+                        band,
+                        slope: value(Terrain::Slope),
+                        slope_percent: value(Terrain::SlopePercent),
+                        aspect: value(Terrain::Aspect),
+                    })
+                } else {
+                    None
+                };
+
+                Ok(Some(PointSample {
+                    pixel: col as usize,
+                    line: row as usize,
+                    values,
+                    terrain,
+                }))
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| GdalError::BadArgument(e.to_string()))?
+}
+
+/// Converts from `crs` to the layer's source CRS
+pub(crate) fn source_projector(layer_obj: &Layer, crs: i32) -> gdal::errors::Result<Projector> {
+    Projector::new(crs, layer_obj.source_geometry.crs_code)
+        .map_err(|e| GdalError::BadArgument(e.to_string()))
+}
+
+/// Whether a value read from a band is nodata, leaving its mask aside
+pub(crate) fn is_nodata_value(
+    value: f64,
+    band_nodata: Option<f64>,
+    nodata: &[NodataValue],
+) -> bool {
+    value.is_nan() || band_nodata == Some(value) || nodata.iter().any(|n| n.contains(value as f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::cog::tests::test_raster;
+
+    #[tokio::test]
+    async fn test_sample_points() {
+        // 4×2 pixels of 10 m in EPSG:3857 from (0, 20), values 0..8, 7 is nodata
+        let (_tmp, layer) = test_raster(
+            (4, 2),
+            [0.0, 10.0, 0.0, 20.0, 0.0, -10.0],
+            (0..8).map(|v| v as f32).collect(),
+            Some(7.0),
+        );
+
+        let samples = sample_points(
+            layer.clone(),
+            vec![(15.0, 15.0), (35.0, 5.0), (50.0, 5.0)],
+            3857,
+            false,
+        )
+        .await
+        .unwrap();
+        let first = samples[0].as_ref().unwrap();
+        assert_eq!((first.pixel, first.line), (1, 0));
+        assert_eq!(first.values[0].value, Some(1.0));
+        assert!(!first.values[0].nodata);
+        let nodata = samples[1].as_ref().unwrap();
+        assert_eq!((nodata.pixel, nodata.line), (3, 1));
+        assert!(nodata.values[0].nodata);
+        assert_eq!(nodata.values[0].data(), None);
+        assert!(samples[2].is_none());
+        assert!(first.terrain.is_none());
+
+        // Rising 1 per 10 m column eastwards and 4 per row southwards, so
+        // facing north-northwest. Pixel 1,0 has no row above, which counts
+        // as itself: dz/dx 0.075 and dz/dy -0.2.
+        let samples = sample_points(layer.clone(), vec![(15.0, 15.0)], 3857, true)
+            .await
+            .unwrap();
+        let terrain = samples[0].as_ref().unwrap().terrain.clone().unwrap();
+        assert_eq!(terrain.band, 1);
+        let slope = terrain.slope_percent.unwrap();
+        assert!((slope - 21.36).abs() < 0.1, "{}", slope);
+        let aspect = terrain.aspect.unwrap();
+        assert!((aspect - 339.44).abs() < 0.1, "{}", aspect);
+
+        // The same first pixel asked for in lon/lat
+        let (lon, lat) = crate::geometry::projection::mercator_to_lon_lat(15.0, 15.0);
+        let samples = sample_points(layer, vec![(lon, lat)], 4326, false)
+            .await
+            .unwrap();
+        assert_eq!(samples[0].as_ref().unwrap().values[0].value, Some(1.0));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::geometry::projection::{Projector, haversine_distance};
+use crate::models::{
+    layer::Layer,
+    render::NodataValue,
+    responses::{LineProfile, ProfileSample},
+};
+use crate::reader::{
+    cog::{open_band, open_source},
+    point::{is_nodata_value, source_projector},
+};
+use gdal::errors::GdalError;
+use tokio::task;
+
+/// Lines that would need more samples than this are refused
+pub const MAX_PROFILE_SAMPLES: usize = 10_000;
+/// Samples along the line when no spacing is given
+const DEFAULT_SAMPLES: usize = 256;
+
+/// Distance along a line, x and y
+type Station = (f64, f64, f64);
+
+/// Values of a band every `spacing` meters along a line (x/y in `crs`,
+/// lon/lat order) and at its end, interpolated between pixel centres
+pub async fn line_profile(
+    layer_obj: Layer,
+    line: Vec<(f64, f64)>,
+    crs: i32,
+    band: usize,
+    spacing: Option<f64>,
+) -> gdal::errors::Result<LineProfile> {
+    task::spawn_blocking(move || {
+        let (length, spacing, positions) = sample_positions(&line, crs, spacing)
+            .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+
+        let to_source = source_projector(&layer_obj, crs)?;
+        let ds = open_source(&layer_obj.path)?;
+        let raster_band = open_band(&ds, band)?;
+        let gt = ds.geo_transform()?;
+        let (width, height) = ds.raster_size();
+        let band_nodata = raster_band.no_data_value();
+        let mask = if raster_band.mask_flags()?.is_all_valid() {
+            None
+        } else {
+            Some(raster_band.open_mask_band()?)
+        };
+        let nodata: &[NodataValue] = layer_obj.render.nodata.as_deref().unwrap_or_default();
+
+        let mut samples = Vec::with_capacity(positions.len());
+        for (distance, x, y) in positions {
+            let mut sample = ProfileSample {
+                distance,
+                x,
+                y,
+                value: None,
+            };
+            let Ok((sx, sy)) = to_source.convert((x, y)) else {
+                samples.push(sample);
+                continue;
+            };
+
+            // Pixel coordinates relative to pixel centres, off the raster if
+            // the pixel under the point is
+            let fx = (sx - gt[0]) / gt[1] - 0.5;
+            let fy = (sy - gt[3]) / gt[5] - 0.5;
+            let (col, row) = ((fx + 0.5).floor(), (fy + 0.5).floor());
+            if !(col >= 0.0 && row >= 0.0 && col < width as f64 && row < height as f64) {
+                samples.push(sample);
+                continue;
+            }
+
+            // The 2×2 pixel centres around the point, fewer along the edges
+            let x0 = fx.floor().clamp(0.0, (width - 1) as f64) as usize;
+            let y0 = fy.floor().clamp(0.0, (height - 1) as f64) as usize;
+            let size = (
+                (x0 + 1).min(width - 1) - x0 + 1,
+                (y0 + 1).min(height - 1) - y0 + 1,
+            );
+            let offset = (x0 as isize, y0 as isize);
+            let values = raster_band.read_as::<f64>(offset, size, size, None)?;
+            let valid = match &mask {
+                Some(mask) => Some(mask.read_as::<u8>(offset, size, size, None)?),
+                None => None,
+            };
+            let mut corners = [[None; 2]; 2];
+            for (r, corner_row) in corners.iter_mut().enumerate() {
+                for (c, corner) in corner_row.iter_mut().enumerate() {
+                    let i = r.min(size.1 - 1) * size.0 + c.min(size.0 - 1);
+                    let value = values.data()[i];
+                    let masked = valid.as_ref().is_some_and(|m| m.data()[i] == 0);
+                    if !masked && !is_nodata_value(value, band_nodata, nodata) {
+                        *corner = Some(value);
+                    }
+                }
+            }
+            let t = (
+                (fx - x0 as f64).clamp(0.0, 1.0),
+                (fy - y0 as f64).clamp(0.0, 1.0),
+            );
+            sample.value = interpolate(corners, t);
+            samples.push(sample);
+        }
+
+        Ok(LineProfile {
+            band,
+            length,
+            spacing,
+            samples,
+        })
+    })
+    .await
+    .map_err(|e| GdalError::BadArgument(e.to_string()))?
+}
+
+/// Total length, the spacing used, and (distance, x, y) every `spacing`
+/// meters along a line and at its end. Segments run straight in `crs`,
+/// distances are great circle ones.
+fn sample_positions(
+    line: &[(f64, f64)],
+    crs: i32,
+    spacing: Option<f64>,
+) -> anyhow::Result<(f64, f64, Vec<Station>)> {
+    let to_lon_lat = Projector::new(crs, 4326)?;
+    let lon_lat = line
+        .iter()
+        .map(|&point| to_lon_lat.convert(point))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let lengths: Vec<f64> = lon_lat
+        .windows(2)
+        .map(|pair| haversine_distance(pair[0], pair[1]))
+        .collect();
+    let length: f64 = lengths.iter().sum();
+    let spacing = spacing.unwrap_or(length / (DEFAULT_SAMPLES - 1) as f64);
+    if length == 0.0 || spacing <= 0.0 {
+        return Ok((length, spacing, vec![(0.0, line[0].0, line[0].1)]));
+    }
+    let count = (length / spacing).floor() as usize + 2;
+    if count > MAX_PROFILE_SAMPLES {
+        anyhow::bail!(
+            "A {:.0} m line every {} m needs {} samples, at most {} are allowed",
+            length,
+            spacing,
+            count,
+            MAX_PROFILE_SAMPLES
+        );
+    }
+
+    let mut positions = Vec::with_capacity(count);
+    let mut start = 0.0; // Distance to the current segment's first vertex
+    let mut k = 0;
+    for (i, &segment) in lengths.iter().enumerate() {
+        let ((x0, y0), (x1, y1)) = (line[i], line[i + 1]);
+        while k as f64 * spacing <= start + segment {
+            let distance = k as f64 * spacing;
+            let t = if segment > 0.0 {
+                (distance - start) / segment
+            } else {
+                0.0
+            };
+            positions.push((distance, x0 + t * (x1 - x0), y0 + t * (y1 - y0)));
+            k += 1;
+        }
+        start += segment;
+    }
+    // The end, unless a sample already landed on it
+    let last = line[line.len() - 1];
+    if positions
+        .last()
+        .is_none_or(|&(distance, _, _)| length - distance > spacing * 1e-6)
+    {
+        positions.push((length, last.0, last.1));
+    }
+    Ok((length, spacing, positions))
+}
+
+/// Bilinear between four pixel values ([row][column]) at `t` from the first,
+/// the nearest one if any of them is nodata
+fn interpolate(corners: [[Option<f64>; 2]; 2], (tx, ty): (f64, f64)) -> Option<f64> {
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+    match corners {
+        [[Some(a), Some(b)], [Some(c), Some(d)]] => Some(lerp(lerp(a, b, tx), lerp(c, d, tx), ty)),
+        _ => corners[(ty >= 0.5) as usize][(tx >= 0.5) as usize],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::cog::tests::test_raster;
+
+    #[test]
+    fn test_sample_positions() {
+        // Two degrees along the equator, then back one, every ~half a degree
+        let degree = haversine_distance((0.0, 0.0), (1.0, 0.0));
+        let line = [(0.0, 0.0), (2.0, 0.0), (1.0, 0.0)];
+        let (length, spacing, positions) =
+            sample_positions(&line, 4326, Some(degree / 2.0)).unwrap();
+        assert!((length - 3.0 * degree).abs() < 1e-6);
+        assert_eq!(spacing, degree / 2.0);
+        let xs: Vec<f64> = positions
+            .iter()
+            .map(|p| (p.1 * 1e6).round() / 1e6)
+            .collect();
+        assert_eq!(xs, vec![0.0, 0.5, 1.0, 1.5, 2.0, 1.5, 1.0]);
+        assert!(positions.iter().all(|p| p.2 == 0.0));
+
+        // The end gets its own sample when the spacing doesn't land on it
+        let (_, _, positions) = sample_positions(&line[..2], 4326, Some(degree * 0.8)).unwrap();
+        assert_eq!(positions.len(), 4);
+        assert_eq!((positions[3].1, positions[3].2), (2.0, 0.0));
+        assert!((positions[3].0 - 2.0 * degree).abs() < 1e-6);
+
+        // Default spacing, a single point, and too many samples
+        let (_, _, positions) = sample_positions(&line, 4326, None).unwrap();
+        assert_eq!(positions.len(), DEFAULT_SAMPLES);
+        let (length, _, positions) =
+            sample_positions(&[(1.0, 1.0), (1.0, 1.0)], 4326, None).unwrap();
+        assert_eq!((length, positions.len()), (0.0, 1));
+        assert!(sample_positions(&line, 4326, Some(1.0)).is_err());
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let full = [[Some(0.0), Some(1.0)], [Some(2.0), Some(3.0)]];
+        assert_eq!(interpolate(full, (0.0, 0.0)), Some(0.0));
+        assert_eq!(interpolate(full, (0.5, 0.5)), Some(1.5));
+        assert_eq!(interpolate(full, (1.0, 0.25)), Some(1.5));
+
+        let gap = [[Some(0.0), None], [Some(2.0), Some(3.0)]];
+        assert_eq!(interpolate(gap, (0.2, 0.2)), Some(0.0));
+        assert_eq!(interpolate(gap, (0.8, 0.2)), None);
+        assert_eq!(interpolate(gap, (0.8, 0.8)), Some(3.0));
+    }
+
+    #[tokio::test]
+    async fn test_line_profile() {
+        // 4×2 pixels of 10 m in EPSG:3857 from (0, 20), values 0..8, 7 is nodata
+        let (_tmp, layer) = test_raster(
+            (4, 2),
+            [0.0, 10.0, 0.0, 20.0, 0.0, -10.0],
+            (0..8).map(|v| v as f32).collect(),
+            Some(7.0),
+        );
+
+        // Along the top row's centres and on past the raster
+        let profile = line_profile(
+            layer.clone(),
+            vec![(5.0, 15.0), (50.0, 15.0)],
+            3857,
+            1,
+            Some(5.0),
+        )
+        .await
+        .unwrap();
+        let values: Vec<Option<f64>> = profile.samples.iter().map(|s| s.value).collect();
+        assert_eq!(values[0], Some(0.0));
+        assert!((values[1].unwrap() - 0.5).abs() < 0.01);
+        assert_eq!(values.last().unwrap(), &None);
+
+        // Between the rows at the bottom right, next to the nodata pixel
+        let profile = line_profile(
+            layer.clone(),
+            vec![(33.0, 12.0), (33.0, 8.0)],
+            3857,
+            1,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(profile.samples.first().unwrap().value, Some(3.0));
+        assert_eq!(profile.samples.last().unwrap().value, None);
+
+        assert!(
+            line_profile(layer, vec![(5.0, 15.0), (50.0, 15.0)], 3857, 2, None)
+                .await
+                .is_err()
+        );
+    }
+}
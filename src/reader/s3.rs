@@ -0,0 +1,424 @@
+use crate::{
+    config::S3Options,
+    models::{
+        layer::Layer, render::RenderOptions, responses::TileResponse, style::ColourMap,
+        tms::TileMatrixSet,
+    },
+    reader::{
+        cog::render_extent,
+        metadata::{is_geotiff, read_layer_metadata, style_for},
+    },
+    traits::TileReader,
+    utils::{
+        status::print_layer_summary,
+        style::{is_builtin_palette, parse_style_str},
+    },
+};
+use async_trait::async_trait;
+use gdal::config::set_config_option;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{CStr, CString},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub struct S3TileReader {
+    layers: HashMap<String, Vec<Layer>>,
+}
+
+impl S3TileReader {
+    pub async fn new(bucket: &str, prefix: &str, options: &S3Options) -> anyhow::Result<Self> {
+        configure_gdal(options)?;
+
+        // List every object under the prefix. GDAL hands back keys relative to
+        // the root, the same layout as the local data folder: {style}/{layer}.tif
+        let root = vsis3_root(bucket, prefix);
+        let keys: Vec<PathBuf> = gdal::vsi::read_dir(&root, true)
+            .map_err(|e| anyhow::anyhow!("Failed to list objects under {}: {}", root, e))?;
+        // Every raster looks for its sidecar, so don't scan the listing each time
+        let key_set: HashSet<&Path> = keys.iter().map(PathBuf::as_path).collect();
+
+        let style_files: HashSet<&Path> = keys
+            .iter()
+            .filter(|k| k.file_name().and_then(|s| s.to_str()) == Some("style.txt"))
+            .filter_map(|k| k.parent())
+            .collect();
+        let rasters: Vec<&PathBuf> = keys
+            .iter()
+            .filter(|k| k.components().count() >= 2 && is_geotiff(k))
+            .collect();
+
+        // If no files found, return empty
+        if rasters.is_empty() {
+            return Ok(Self {
+                layers: HashMap::new(),
+            });
+        }
+
+        let pb = ProgressBar::new(rasters.len() as u64);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{msg}\n[{bar:40.cyan/blue}] {pos}/{len} {percent}%")
+                .unwrap()
+                .progress_chars("█▇▆▅▄▃▂▁  "),
+        );
+
+        // style.txt is shared by every layer in a folder, only fetch it once
+        let mut style_cache: HashMap<PathBuf, ColourMap> = HashMap::new();
+        let mut total_bytes = 0u64;
+        let mut layers: Vec<Layer> = Vec::new();
+
+        for key in rasters {
+            let path = PathBuf::from(&root).join(key);
+            pb.set_message(format!("Reading s3://{}/{}", bucket, key.display()));
+
+            let style_dir = key.parent().unwrap_or(Path::new("")).to_path_buf();
+            let colour_map = if is_builtin_palette(style_for(&path))
+                || !style_files.contains(style_dir.as_path())
+            {
+                ColourMap::default()
+            } else {
+                style_cache
+                    .entry(style_dir)
+                    .or_insert_with_key(|dir| {
+                        let style_path = PathBuf::from(&root).join(dir).join("style.txt");
+                        read_vsi_to_string(&style_path)
+                            .and_then(|content| parse_style_str(&content).ok())
+                            .unwrap_or_default()
+                    })
+                    .clone()
+            };
+
+            let (size_bytes, last_modified) = object_info(&path);
+            total_bytes += size_bytes;
+
+            match read_layer_metadata(&path, size_bytes, last_modified, colour_map) {
+                Ok(mut layer) => {
+                    let sidecar = key.with_extension("json");
+                    if key_set.contains(sidecar.as_path()) {
+                        let sidecar_path = PathBuf::from(&root).join(&sidecar);
+                        layer.render = read_vsi_to_string(&sidecar_path)
+                            .map(|content| RenderOptions::from_json(&content))
+                            .unwrap_or(Ok(RenderOptions::default()))
+                            .unwrap_or_else(|e| {
+                                pb.println(format!("⚠️ Ignoring {}: {}", sidecar.display(), e));
+                                RenderOptions::default()
+                            });
+                    }
+                    layers.push(layer);
+                }
+                Err(e) => pb.println(format!("❌ Failed to read object: {}", e)),
+            }
+            pb.inc(1);
+        }
+
+        pb.finish_with_message("✅ All objects loaded!");
+
+        println!(
+            "\n📦 Total bytes: {:.2} MiB",
+            total_bytes as f64 / 1024.0 / 1024.0
+        );
+        println!("📦 Total layers: {}", layers.len());
+
+        print_layer_summary(&layers);
+
+        let mut layers_map: HashMap<String, Vec<Layer>> = HashMap::new();
+        for layer in layers {
+            let layer_name = layer.layer.clone();
+            layers_map.entry(layer_name).or_default().push(layer);
+        }
+
+        Ok(Self { layers: layers_map })
+    }
+}
+
+#[async_trait]
+impl TileReader for S3TileReader {
+    async fn list_layers(&self) -> Vec<Layer> {
+        let mut all_layers: Vec<Layer> = self
+            .layers
+            .values()
+            .flat_map(|layers| layers.clone())
+            .collect();
+        all_layers.sort_by(|a, b| a.layer.cmp(&b.layer));
+        all_layers
+    }
+
+    async fn get_layer(&self, layer: &str) -> Option<Layer> {
+        self.layers
+            .get(layer)
+            .and_then(|styles| styles.first())
+            .cloned()
+    }
+
+    async fn get_tile(
+        &self,
+        layer: &str,
+        tms: &TileMatrixSet,
+        z: u8,
+        x: u32,
+        y: u32,
+        _style: Option<&str>,
+        options: &RenderOptions,
+    ) -> anyhow::Result<TileResponse, String> {
+        let layer_obj = self
+            .layers
+            .get(layer)
+            .and_then(|styles| styles.first())
+            .ok_or_else(|| format!("Layer not found: '{}'", layer))?;
+
+        let (Some(matrix), Some(extent)) = (tms.matrix(z), tms.tile_bounds(z, x, y)) else {
+            return Err(format!("Tile {}/{}/{} is outside {}", z, x, y, tms.id));
+        };
+        let tile_size = (matrix.tile_width as usize, matrix.tile_height as usize);
+
+        // The layer path is a /vsis3/ path, GDAL fetches the ranges it needs
+        let options = options.merged_over(&layer_obj.render)?;
+        let content_type = options.format.unwrap_or_default().content_type();
+        let tile_data = render_extent(
+            layer_obj.path.clone(),
+            extent,
+            tms.crs_code,
+            layer_obj.clone(),
+            tile_size,
+            options,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(TileResponse {
+            content_type: content_type.into(),
+            bytes: tile_data,
+        })
+    }
+}
+
+/// Build the GDAL virtual path for a bucket and prefix, without trailing slash
+fn vsis3_root(bucket: &str, prefix: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        format!("/vsis3/{}", bucket)
+    } else {
+        format!("/vsis3/{}/{}", bucket, prefix)
+    }
+}
+
+/// Push the S3 options into GDAL's global config so every /vsis3/ access
+/// (including those from the blocking tile workers) uses them
+fn configure_gdal(options: &S3Options) -> anyhow::Result<()> {
+    if let Some(endpoint) = &options.endpoint {
+        set_config_option("AWS_S3_ENDPOINT", endpoint)?;
+    }
+    if let Some(region) = &options.region {
+        set_config_option("AWS_REGION", region)?;
+    }
+    if let Some(key) = &options.access_key_id {
+        set_config_option("AWS_ACCESS_KEY_ID", key)?;
+    }
+    if let Some(secret) = &options.secret_access_key {
+        set_config_option("AWS_SECRET_ACCESS_KEY", secret)?;
+    }
+    set_config_option("AWS_HTTPS", if options.use_https { "YES" } else { "NO" })?;
+    set_config_option(
+        "AWS_VIRTUAL_HOSTING",
+        if options.virtual_hosting {
+            "TRUE"
+        } else {
+            "FALSE"
+        },
+    )?;
+    if options.no_sign_request {
+        set_config_option("AWS_NO_SIGN_REQUEST", "YES")?;
+    }
+    // Don't let GDAL probe for sidecar files (.aux.xml, .ovr, ...) with extra requests
+    set_config_option("GDAL_DISABLE_READDIR_ON_OPEN", "EMPTY_DIR")?;
+    Ok(())
+}
+
+/// Size and modification time of an object, from the HTTP headers GDAL sees
+/// on a HEAD request. A missing Last-Modified falls back to the epoch rather
+/// than now, so tile caches and ETags, which also key on the size, survive
+/// restarts.
+fn object_info(path: &Path) -> (u64, SystemTime) {
+    let mut size_bytes = 0u64;
+    let mut last_modified = None;
+
+    let (Some(c_path), Ok(c_domain)) = (
+        path.to_str().and_then(|p| CString::new(p).ok()),
+        CString::new("HEADERS"),
+    ) else {
+        return (size_bytes, UNIX_EPOCH);
+    };
+
+    unsafe {
+        let headers =
+            gdal_sys::VSIGetFileMetadata(c_path.as_ptr(), c_domain.as_ptr(), std::ptr::null_mut());
+        if headers.is_null() {
+            eprintln!("⚠️ No HTTP headers for {}", path.display());
+            return (size_bytes, UNIX_EPOCH);
+        }
+        for i in 0..gdal_sys::CSLCount(headers) {
+            let entry = gdal_sys::CSLGetField(headers, i);
+            let entry = CStr::from_ptr(entry).to_string_lossy();
+            let Some((name, value)) = entry.split_once('=') else {
+                continue;
+            };
+            if name.eq_ignore_ascii_case("Content-Length") {
+                size_bytes = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("Last-Modified") {
+                last_modified = httpdate::parse_http_date(value.trim()).ok();
+            }
+        }
+        gdal_sys::CSLDestroy(headers);
+    }
+
+    let last_modified = last_modified.unwrap_or_else(|| {
+        eprintln!(
+            "⚠️ No Last-Modified for {}, changes will only be noticed by size",
+            path.display()
+        );
+        UNIX_EPOCH
+    });
+    (size_bytes, last_modified)
+}
+
+/// Read a whole (small) object through GDAL's virtual file system
+fn read_vsi_to_string(path: &Path) -> Option<String> {
+    let c_path = CString::new(path.to_str()?).ok()?;
+    let mut data: *mut gdal_sys::GByte = std::ptr::null_mut();
+    let mut size: gdal_sys::vsi_l_offset = 0;
+
+    unsafe {
+        let ok = gdal_sys::VSIIngestFile(
+            std::ptr::null_mut(),
+            c_path.as_ptr(),
+            &mut data,
+            &mut size,
+            -1,
+        );
+        if ok == 0 || data.is_null() {
+            return None;
+        }
+        let bytes = std::slice::from_raw_parts(data, size as usize).to_vec();
+        gdal_sys::VSIFree(data as *mut std::ffi::c_void);
+        String::from_utf8(bytes).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use gdal::DriverManager;
+    use gdal::spatial_ref::SpatialRef;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_vsis3_root_trims_prefix() {
+        assert_eq!(vsis3_root("bucket", ""), "/vsis3/bucket");
+        assert_eq!(vsis3_root("bucket", "/"), "/vsis3/bucket");
+        assert_eq!(vsis3_root("bucket", "rasters/"), "/vsis3/bucket/rasters");
+        assert_eq!(vsis3_root("bucket", "/a/b/"), "/vsis3/bucket/a/b");
+    }
+
+    /// Copy a local file into the bucket through GDAL's virtual file system
+    fn upload(local: &Path, remote: &str) {
+        let bytes = std::fs::read(local).unwrap();
+        let c_remote = CString::new(remote).unwrap();
+        let c_mode = CString::new("wb").unwrap();
+        unsafe {
+            let fp = gdal_sys::VSIFOpenL(c_remote.as_ptr(), c_mode.as_ptr());
+            assert!(!fp.is_null(), "failed to open {} for writing", remote);
+            gdal_sys::VSIFWriteL(
+                bytes.as_ptr() as *const std::ffi::c_void,
+                1,
+                bytes.len(),
+                fp,
+            );
+            assert_eq!(gdal_sys::VSIFCloseL(fp), 0, "failed to upload {}", remote);
+        }
+    }
+
+    /// Runs against a local S3-compatible server, e.g.:
+    ///
+    ///   docker run -p 9000:9000 minio/minio server /data
+    ///   mc alias set local http://localhost:9000 minioadmin minioadmin
+    ///   mc mb local/tileyolo-test
+    ///   cargo test -- --ignored
+    ///
+    /// Override the endpoint/credentials with TILEYOLO_TEST_S3_ENDPOINT,
+    /// TILEYOLO_TEST_S3_ACCESS_KEY and TILEYOLO_TEST_S3_SECRET_KEY.
+    #[tokio::test]
+    #[ignore]
+    async fn test_s3_reader_against_local_s3() {
+        let options = S3Options {
+            endpoint: Some(
+                std::env::var("TILEYOLO_TEST_S3_ENDPOINT")
+                    .unwrap_or_else(|_| "localhost:9000".to_string()),
+            ),
+            region: Some("us-east-1".to_string()),
+            access_key_id: Some(
+                std::env::var("TILEYOLO_TEST_S3_ACCESS_KEY")
+                    .unwrap_or_else(|_| "minioadmin".to_string()),
+            ),
+            secret_access_key: Some(
+                std::env::var("TILEYOLO_TEST_S3_SECRET_KEY")
+                    .unwrap_or_else(|_| "minioadmin".to_string()),
+            ),
+            use_https: false,
+            virtual_hosting: false,
+            no_sign_request: false,
+        };
+        configure_gdal(&options).unwrap();
+
+        // Small 3857 raster with a simple ramp
+        let tmp = TempDir::new().unwrap();
+        let local = tmp.path().join("ramp.tif");
+        let driver = DriverManager::get_driver_by_name("GTIFF").unwrap();
+        let mut ds = driver
+            .create_with_band_type::<f32, _>(local.to_str().unwrap(), 64, 64, 1)
+            .unwrap();
+        let sref = SpatialRef::from_epsg(3857).unwrap();
+        ds.set_projection(&sref.to_wkt().unwrap()).unwrap();
+        ds.set_geo_transform(&[0.0, 1000.0, 0.0, 64000.0, 0.0, -1000.0])
+            .unwrap();
+        let data: Vec<f32> = (0..64 * 64).map(|i| (i % 64) as f32 + 1.0).collect();
+        let mut buffer = gdal::raster::Buffer::<f32>::new((64, 64), data);
+        ds.rasterband(Config::default().default_raster_band)
+            .unwrap()
+            .write((0, 0), (64, 64), &mut buffer)
+            .unwrap();
+        ds.flush_cache().unwrap();
+        drop(ds);
+
+        let root = vsis3_root("tileyolo-test", "reader-test");
+        upload(&local, &format!("{}/viridis/ramp.tif", root));
+
+        let reader = S3TileReader::new("tileyolo-test", "reader-test", &options)
+            .await
+            .expect("reader should list the bucket");
+
+        let layers = reader.list_layers().await;
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].layer, "ramp");
+        assert_eq!(layers[0].style, "viridis");
+        assert_eq!(layers[0].source_geometry.crs_code, 3857);
+
+        let tile = reader
+            .get_tile(
+                "ramp",
+                &TileMatrixSet::web_mercator_quad(),
+                0,
+                0,
+                0,
+                None,
+                &RenderOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(tile.content_type, "image/png");
+        assert!(!tile.bytes.is_empty());
+    }
+}
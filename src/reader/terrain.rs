@@ -0,0 +1,375 @@
+use crate::geometry::projection::{Projector, haversine_distance};
+use crate::models::{
+    geometry::GeometryExtent,
+    render::{NodataValue, RenderMode, RenderOptions, Terrain},
+};
+use crate::reader::cog::read_window;
+use gdal::Dataset;
+
+/// How relief is lit, with gdaldem's defaults
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lighting {
+    pub azimuth: f64,
+    pub altitude: f64,
+    pub z_factor: f64,
+    pub multidirectional: bool,
+}
+
+impl Lighting {
+    pub fn from_options(options: &RenderOptions) -> Self {
+        Lighting {
+            azimuth: options.azimuth.unwrap_or(315.0),
+            altitude: options.altitude.unwrap_or(45.0),
+            z_factor: options.z_factor.unwrap_or(1.0),
+            multidirectional: options.multidirectional.unwrap_or(false),
+        }
+    }
+}
+
+/// Ground size in meters of a pixel (east-west, north-south) along each row
+/// of a grid covering `extent` in `crs`, measured at the middle column. Falls
+/// back to the CRS units when the CRS can't be brought to lon/lat.
+pub fn ground_resolution(
+    crs: i32,
+    extent: &GeometryExtent,
+    (width, height): (usize, usize),
+) -> Vec<(f64, f64)> {
+    let res_x = (extent.maxx - extent.minx) / width as f64;
+    let res_y = (extent.maxy - extent.miny) / height as f64;
+    let to_lon_lat = Projector::new(crs, 4326).ok();
+    let x = (extent.minx + extent.maxx) / 2.0;
+    (0..height)
+        .map(|row| {
+            let y = extent.maxy - (row as f64 + 0.5) * res_y;
+            let measured = to_lon_lat.as_ref().and_then(|p| {
+                let centre = p.convert((x, y)).ok()?;
+                let east = p.convert((x + res_x, y)).ok()?;
+                let north = p.convert((x, y + res_y)).ok()?;
+                Some((
+                    haversine_distance(centre, east),
+                    haversine_distance(centre, north),
+                ))
+            });
+            measured
+                .filter(|&(ew, ns)| ew > 0.0 && ns > 0.0)
+                .unwrap_or((res_x, res_y))
+        })
+        .collect()
+}
+
+/// Horn's gradient (dz/dx east, dz/dy north) of every pixel inside a
+/// `halo` pixel border. Missing neighbours count as the centre, NaN
+/// centres give None.
+pub(crate) fn gradients(
+    values: &[f32],
+    (width, height): (usize, usize),
+    halo: usize,
+    resolution: &[(f64, f64)],
+) -> Vec<Option<(f64, f64)>> {
+    let mut out = Vec::with_capacity((width - 2 * halo) * (height - 2 * halo));
+    for row in halo..height - halo {
+        let (ew, ns) = resolution[row];
+        for col in halo..width - halo {
+            let centre = values[row * width + col] as f64;
+            if centre.is_nan() {
+                out.push(None);
+                continue;
+            }
+            // a b c / d e f / g h i, top row north
+            let at = |dr: isize, dc: isize| {
+                let (r, c) = (row as isize + dr, col as isize + dc);
+                if r < 0 || c < 0 || r >= height as isize || c >= width as isize {
+                    return centre;
+                }
+                let v = values[r as usize * width + c as usize] as f64;
+                if v.is_nan() { centre } else { v }
+            };
+            let (a, b, c) = (at(-1, -1), at(-1, 0), at(-1, 1));
+            let (d, f) = (at(0, -1), at(0, 1));
+            let (g, h, i) = (at(1, -1), at(1, 0), at(1, 1));
+            let dzdx = ((c + 2.0 * f + i) - (a + 2.0 * d + g)) / (8.0 * ew);
+            let dzdy = ((a + 2.0 * b + c) - (g + 2.0 * h + i)) / (8.0 * ns);
+            out.push(Some((dzdx, dzdy)));
+        }
+    }
+    out
+}
+
+/// Shaded relief from 0 (dark) to 1 of the pixels inside a `halo` pixel
+/// border, NaN where there is no data. `resolution` is the ground pixel
+/// size of each row.
+pub fn hillshade(
+    values: &[f32],
+    size: (usize, usize),
+    halo: usize,
+    resolution: &[(f64, f64)],
+    lighting: &Lighting,
+) -> Vec<f32> {
+    let altitude = lighting.altitude.to_radians();
+    let z = lighting.z_factor;
+    // Brightness of a surface with this gradient lit from `azimuth`
+    let lit = |(dzdx, dzdy): (f64, f64), azimuth: f64| {
+        let azimuth = azimuth.to_radians();
+        let facing = azimuth.sin() * dzdx + azimuth.cos() * dzdy;
+        (altitude.sin() - altitude.cos() * z * facing)
+            / (1.0 + z * z * (dzdx * dzdx + dzdy * dzdy)).sqrt()
+    };
+
+    gradients(values, size, halo, resolution)
+        .into_iter()
+        .map(|gradient| {
+            let Some((dzdx, dzdy)) = gradient else {
+                return f32::NAN;
+            };
+            let shade = if lighting.multidirectional {
+                // Each light weighted by how squarely the slope faces it, the
+                // weights of four lights 45° apart add up to 2
+                let steepness = dzdx * dzdx + dzdy * dzdy;
+                if steepness == 0.0 {
+                    altitude.sin()
+                } else {
+                    [-90.0, -45.0, 0.0, 45.0]
+                        .into_iter()
+                        .map(|offset| {
+                            let azimuth = lighting.azimuth + offset;
+                            let (s, c) = azimuth.to_radians().sin_cos();
+                            let weight = (dzdx * s + dzdy * c).powi(2) / steepness;
+                            weight * lit((dzdx, dzdy), azimuth).max(0.0)
+                        })
+                        .sum::<f64>()
+                        / 2.0
+                }
+            } else {
+                lit((dzdx, dzdy), lighting.azimuth)
+            };
+            shade.clamp(0.0, 1.0) as f32
+        })
+        .collect()
+}
+
+/// Slope or aspect of the pixels inside a `halo` pixel border, NaN where
+/// there is no data (and for the aspect of flat ground)
+pub fn terrain(
+    values: &[f32],
+    size: (usize, usize),
+    halo: usize,
+    resolution: &[(f64, f64)],
+    terrain: Terrain,
+    z_factor: f64,
+) -> Vec<f32> {
+    gradients(values, size, halo, resolution)
+        .into_iter()
+        .map(|gradient| {
+            gradient
+                .and_then(|gradient| terrain_value(gradient, terrain, z_factor))
+                .map_or(f32::NAN, |v| v as f32)
+        })
+        .collect()
+}
+
+/// Slope or aspect from a gradient, None for the aspect of flat ground
+pub fn terrain_value((dzdx, dzdy): (f64, f64), terrain: Terrain, z_factor: f64) -> Option<f64> {
+    let (dzdx, dzdy) = (dzdx * z_factor, dzdy * z_factor);
+    let rise = (dzdx * dzdx + dzdy * dzdy).sqrt();
+    match terrain {
+        Terrain::Slope => Some(rise.atan().to_degrees()),
+        Terrain::SlopePercent => Some(rise * 100.0),
+        // Downhill is against the gradient
+        Terrain::Aspect if rise == 0.0 => None,
+        Terrain::Aspect => Some((-dzdx).atan2(-dzdy).to_degrees().rem_euclid(360.0)),
+    }
+}
+
+/// Gradients of a window (offset, size) of a source band in `crs`, taken
+/// with the pixels around it where the raster has them. Nodata is None.
+pub fn source_gradients(
+    ds: &Dataset,
+    crs: i32,
+    band: usize,
+    nodata: &[NodataValue],
+    (col, row): (usize, usize),
+    (width, height): (usize, usize),
+) -> gdal::errors::Result<Vec<Option<(f64, f64)>>> {
+    let (raster_width, raster_height) = ds.raster_size();
+    let (left, top) = (col.min(1), row.min(1));
+    let right = raster_width.saturating_sub(col + width).min(1);
+    let bottom = raster_height.saturating_sub(row + height).min(1);
+    let grid = (left + width + right, top + height + bottom);
+    let (col, row) = (col - left, row - top);
+    let values = read_window(
+        ds,
+        &[band],
+        nodata,
+        ((col as isize, row as isize), grid),
+        grid,
+        None,
+    )?
+    .remove(0);
+
+    let gt = ds.geo_transform()?;
+    let minx = gt[0] + col as f64 * gt[1];
+    let maxy = gt[3] + row as f64 * gt[5];
+    let extent = GeometryExtent::from((
+        minx,
+        maxy + grid.1 as f64 * gt[5],
+        minx + grid.0 as f64 * gt[1],
+        maxy,
+    ));
+    let all = gradients(&values, grid, 0, &ground_resolution(crs, &extent, grid));
+    Ok((top..top + height)
+        .flat_map(|r| &all[r * grid.0 + left..r * grid.0 + left + width])
+        .copied()
+        .collect())
+}
+
+/// Base and interval of an elevation encoding. Terrarium is the same 24 bit
+/// packing as Terrain-RGB, counting 1/256 m from -32768 m.
+pub fn encoding_scale(mode: RenderMode, options: &RenderOptions) -> (f64, f64) {
+    match mode {
+        RenderMode::Terrarium => (-32768.0, 1.0 / 256.0),
+        _ => (
+            options.base.unwrap_or(-10000.0),
+            options.interval.unwrap_or(0.1),
+        ),
+    }
+}
+
+/// An elevation as R, G, B: steps of `interval` above `base`, clamped to 24
+/// bits. Nodata is sea level, as clients read every pixel as a height.
+pub fn encode_elevation(value: f32, (base, interval): (f64, f64)) -> [u8; 3] {
+    let value = if value.is_nan() { 0.0 } else { value as f64 };
+    let steps = ((value - base) / interval)
+        .round()
+        .clamp(0.0, (1 << 24) as f64 - 1.0) as u32;
+    [(steps >> 16) as u8, (steps >> 8) as u8, steps as u8]
+}
+
+/// The pixels of a buffer inside a `halo` pixel border
+pub fn crop_halo(buffer: &[f32], (width, height): (usize, usize), halo: usize) -> Vec<f32> {
+    (halo..height - halo)
+        .flat_map(|row| &buffer[row * width + halo..(row + 1) * width - halo])
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIGHTING: Lighting = Lighting {
+        azimuth: 315.0,
+        altitude: 45.0,
+        z_factor: 1.0,
+        multidirectional: false,
+    };
+
+    #[test]
+    fn test_ground_resolution() {
+        // Web Mercator pixels shrink towards the poles, degrees in longitude
+        let merc = ground_resolution(3857, &(0.0, 0.0, 1000.0, 1000.0).into(), (10, 10));
+        assert_eq!(merc.len(), 10);
+        assert!((merc[9].0 - 100.0).abs() < 0.5);
+        let high = ground_resolution(3857, &(0.0, 8e6, 1000.0, 8.001e6).into(), (10, 10));
+        assert!(high[0].0 < 60.0 && (high[0].0 - high[0].1).abs() < 0.5);
+        let degrees = ground_resolution(4326, &(0.0, 59.0, 1.0, 61.0).into(), (1, 1));
+        assert!((degrees[0].0 / degrees[0].1 - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_hillshade() {
+        // Flat ground is lit at sin(altitude) everywhere
+        let flat = vec![10.0; 25];
+        let resolution = vec![(1.0, 1.0); 5];
+        let shade = hillshade(&flat, (5, 5), 1, &resolution, &LIGHTING);
+        assert_eq!(shade.len(), 9);
+        assert!(shade.iter().all(|&s| (s - 0.70710677).abs() < 1e-6));
+
+        // Rising to the east faces a north-west light, rising west faces away
+        let east: Vec<f32> = (0..25).map(|i| (i % 5) as f32).collect();
+        let west: Vec<f32> = (0..25).map(|i| (4 - i % 5) as f32).collect();
+        let towards = hillshade(&east, (5, 5), 1, &resolution, &LIGHTING);
+        let away = hillshade(&west, (5, 5), 1, &resolution, &LIGHTING);
+        assert!((towards[4] - 0.8535534).abs() < 1e-6);
+        assert!((away[4] - 0.14644662).abs() < 1e-6);
+
+        // Multidirectional lights both sides of a ridge
+        let multi = Lighting {
+            multidirectional: true,
+            ..LIGHTING
+        };
+        let towards = hillshade(&east, (5, 5), 1, &resolution, &multi);
+        let away = hillshade(&west, (5, 5), 1, &resolution, &multi);
+        assert!(away[4] > 0.0 && towards[4] > away[4]);
+
+        // No data stays no data, and the edges use what they have
+        let mut gap = flat.clone();
+        gap[12] = f32::NAN;
+        let shade = hillshade(&gap, (5, 5), 0, &resolution, &LIGHTING);
+        assert!(shade[12].is_nan());
+        assert!((shade[0] - 0.70710677).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_terrain() {
+        // Rising 1 m per meter to the east: 45°, 100%, facing west
+        let east: Vec<f32> = (0..9).map(|i| (i % 3) as f32).collect();
+        let resolution = vec![(1.0, 1.0); 3];
+        let slope = terrain(&east, (3, 3), 1, &resolution, Terrain::Slope, 1.0);
+        assert!((slope[0] - 45.0).abs() < 1e-4);
+        let percent = terrain(&east, (3, 3), 1, &resolution, Terrain::SlopePercent, 1.0);
+        assert!((percent[0] - 100.0).abs() < 1e-4);
+        let aspect = terrain(&east, (3, 3), 1, &resolution, Terrain::Aspect, 1.0);
+        assert!((aspect[0] - 270.0).abs() < 1e-4);
+
+        // Falling to the south faces south, flat has no aspect
+        assert_eq!(terrain_value((0.0, 1.0), Terrain::Aspect, 1.0), Some(180.0));
+        assert_eq!(terrain_value((0.0, -1.0), Terrain::Aspect, 1.0), Some(0.0));
+        assert_eq!(terrain_value((0.0, 0.0), Terrain::Aspect, 1.0), None);
+        assert_eq!(terrain_value((0.0, 0.0), Terrain::Slope, 1.0), Some(0.0));
+        let feet = terrain_value((1.0, 0.0), Terrain::SlopePercent, 0.5).unwrap();
+        assert!((feet - 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_crop_halo() {
+        let grid: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        assert_eq!(crop_halo(&grid, (4, 3), 1), vec![5.0, 6.0]);
+        assert_eq!(crop_halo(&grid, (4, 3), 0), grid);
+    }
+
+    #[test]
+    fn test_encode_elevation() {
+        let options = RenderOptions::default();
+        let mapbox = encoding_scale(RenderMode::TerrainRgb, &options);
+        let terrarium = encoding_scale(RenderMode::Terrarium, &options);
+        let decode_mapbox = |[r, g, b]: [u8; 3]| {
+            -10000.0 + (r as f64 * 65536.0 + g as f64 * 256.0 + b as f64) * 0.1
+        };
+        let decode_terrarium =
+            |[r, g, b]: [u8; 3]| r as f64 * 256.0 + g as f64 + b as f64 / 256.0 - 32768.0;
+
+        for value in [0.0f32, 1234.5, -420.25, 8848.86] {
+            let decoded = decode_mapbox(encode_elevation(value, mapbox));
+            assert!((decoded - value as f64).abs() <= 0.05, "{}", decoded);
+            let decoded = decode_terrarium(encode_elevation(value, terrarium));
+            assert!((decoded - value as f64).abs() <= 1.0 / 512.0, "{}", decoded);
+        }
+
+        // Known pixels, nodata as sea level and clamping at the ends
+        assert_eq!(encode_elevation(0.0, mapbox), [1, 134, 160]);
+        assert_eq!(encode_elevation(f32::NAN, mapbox), [1, 134, 160]);
+        assert_eq!(encode_elevation(0.0, terrarium), [128, 0, 0]);
+        assert_eq!(encode_elevation(-20000.0, mapbox), [0, 0, 0]);
+        assert_eq!(encode_elevation(1e9, terrarium), [255, 255, 255]);
+
+        // A custom base and interval
+        let fine = RenderOptions {
+            base: Some(-500.0),
+            interval: Some(0.01),
+            ..Default::default()
+        };
+        let scale = encoding_scale(RenderMode::TerrainRgb, &fine);
+        assert_eq!(encode_elevation(-500.0, scale), [0, 0, 0]);
+        assert_eq!(encode_elevation(-497.44, scale), [0, 1, 0]);
+    }
+}
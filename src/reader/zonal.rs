@@ -0,0 +1,330 @@
+use crate::models::{
+    geojson::Polygon,
+    layer::Layer,
+    render::{NodataValue, Terrain},
+    responses::{Histogram, Percentile, ZonalStats},
+};
+use crate::reader::{
+    cog::{open_band, open_source},
+    point::{is_nodata_value, source_projector},
+    terrain::{source_gradients, terrain_value},
+};
+use gdal::{DriverManager, errors::GdalError, raster::rasterize, vector::Geometry};
+use tokio::task;
+
+/// Zones whose bounding box covers more source pixels than this are refused
+pub const MAX_ZONE_PIXELS: usize = 10_000_000;
+
+#[derive(Debug, Clone)]
+pub struct ZonalOptions {
+    pub band: usize,
+    pub percentiles: Vec<f64>,    // 0-100
+    pub bins: usize,              // Histogram bins, 0 for none
+    pub terrain: Option<Terrain>, // Summarise this instead of the band's values
+}
+
+/// Statistics of a band, or of its slope or aspect, over the source pixels
+/// whose centres fall inside the polygons (x/y in `crs`, lon/lat order)
+pub async fn zonal_stats(
+    layer_obj: Layer,
+    polygons: Vec<Polygon>,
+    crs: i32,
+    options: ZonalOptions,
+) -> gdal::errors::Result<ZonalStats> {
+    task::spawn_blocking(move || {
+        let to_source = source_projector(&layer_obj, crs)?;
+        let ds = open_source(&layer_obj.path)?;
+        let band = open_band(&ds, options.band)?;
+
+        // The zone as WKT in the source CRS, vertices projected one by one
+        let mut wkt_polygons = Vec::with_capacity(polygons.len());
+        for polygon in &polygons {
+            let mut wkt_rings = Vec::with_capacity(polygon.len());
+            for ring in polygon {
+                let mut wkt_points = Vec::with_capacity(ring.len());
+                for &(x, y) in ring {
+                    let (sx, sy) = to_source
+                        .convert((x, y))
+                        .map_err(|e| GdalError::BadArgument(e.to_string()))?;
+                    wkt_points.push(format!("{} {}", sx, sy));
+                }
+                wkt_rings.push(format!("({})", wkt_points.join(", ")));
+            }
+            wkt_polygons.push(format!("({})", wkt_rings.join(", ")));
+        }
+        let zone = Geometry::from_wkt(&format!("MULTIPOLYGON ({})", wkt_polygons.join(", ")))?;
+
+        // Source window around the zone's envelope
+        let envelope = zone.envelope();
+        let gt = ds.geo_transform()?;
+        let (width, height) = ds.raster_size();
+        let cols = [
+            (envelope.MinX - gt[0]) / gt[1],
+            (envelope.MaxX - gt[0]) / gt[1],
+        ];
+        let rows = [
+            (envelope.MaxY - gt[3]) / gt[5],
+            (envelope.MinY - gt[3]) / gt[5],
+        ];
+        let window = |edges: [f64; 2], size: usize| {
+            let start = edges[0].min(edges[1]).floor().clamp(0.0, size as f64) as usize;
+            let end = edges[0].max(edges[1]).ceil().clamp(0.0, size as f64) as usize;
+            (start, end - start)
+        };
+        let (col, win_width) = window(cols, width);
+        let (row, win_height) = window(rows, height);
+
+        let nodata: &[NodataValue] = layer_obj.render.nodata.as_deref().unwrap_or_default();
+        let mut values = Vec::new();
+        let mut nodata_count = 0;
+        if win_width > 0 && win_height > 0 {
+            if win_width * win_height > MAX_ZONE_PIXELS {
+                return Err(GdalError::BadArgument(format!(
+                    "The zone spans {} × {} source pixels, at most {} can be summarised at once",
+                    win_width, win_height, MAX_ZONE_PIXELS
+                )));
+            }
+
+            // Burn the zone into a byte mask over the window
+            let mut mask_ds = DriverManager::get_driver_by_name("MEM")?
+                .create_with_band_type::<u8, _>("", win_width, win_height, 1)?;
+            mask_ds.set_geo_transform(&[
+                gt[0] + col as f64 * gt[1] + row as f64 * gt[2],
+                gt[1],
+                gt[2],
+                gt[3] + col as f64 * gt[4] + row as f64 * gt[5],
+                gt[4],
+                gt[5],
+            ])?;
+            rasterize(&mut mask_ds, &[1], &[zone], &[1.0], None)?;
+            let inside = mask_ds.rasterband(1)?.read_as::<u8>(
+                (0, 0),
+                (win_width, win_height),
+                (win_width, win_height),
+                None,
+            )?;
+
+            let offset = (col as isize, row as isize);
+            let size = (win_width, win_height);
+            let data = band.read_as::<f64>(offset, size, size, None)?;
+            let valid = if band.mask_flags()?.is_all_valid() {
+                None
+            } else {
+                Some(
+                    band.open_mask_band()?
+                        .read_as::<u8>(offset, size, size, None)?,
+                )
+            };
+            let band_nodata = band.no_data_value();
+            // Slopes come from the neighbours too, so the pixels around the
+            // window are read with it
+            let gradients = match options.terrain {
+                Some(_) => Some(source_gradients(
+                    &ds,
+                    layer_obj.source_geometry.crs_code,
+                    options.band,
+                    nodata,
+                    (col, row),
+                    size,
+                )?),
+                None => None,
+            };
+            let z_factor = layer_obj.render.z_factor.unwrap_or(1.0);
+
+            for (i, &value) in data.data().iter().enumerate() {
+                if inside.data()[i] == 0 {
+                    continue;
+                }
+                let masked = valid.as_ref().is_some_and(|m| m.data()[i] == 0);
+                if masked || is_nodata_value(value, band_nodata, nodata) {
+                    nodata_count += 1;
+                    continue;
+                }
+                let value = match (&gradients, options.terrain) {
+                    (Some(gradients), Some(terrain)) => {
+                        gradients[i].and_then(|g| terrain_value(g, terrain, z_factor))
+                    }
+                    _ => Some(value),
+                };
+                match value {
+                    Some(value) => values.push(value),
+                    None => nodata_count += 1, // Flat ground has no aspect
+                }
+            }
+        }
+
+        Ok(summarise(options.band, values, nodata_count, &options))
+    })
+    .await
+    .map_err(|e| GdalError::BadArgument(e.to_string()))?
+}
+
+fn summarise(
+    band: usize,
+    mut values: Vec<f64>,
+    nodata_count: usize,
+    options: &ZonalOptions,
+) -> ZonalStats {
+    let mut stats = ZonalStats {
+        band,
+        terrain: options.terrain,
+        count: values.len(),
+        nodata_count,
+        min: None,
+        max: None,
+        mean: None,
+        stddev: None,
+        sum: None,
+        percentiles: Vec::new(),
+        histogram: None,
+    };
+    if values.is_empty() {
+        return stats;
+    }
+
+    values.sort_by(f64::total_cmp);
+    let n = values.len() as f64;
+    let (min, max) = (values[0], values[values.len() - 1]);
+    let sum: f64 = values.iter().sum();
+    let mean = sum / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+
+    // Linear interpolation between the closest ranks, like numpy's default
+    stats.percentiles = options
+        .percentiles
+        .iter()
+        .map(|&percentile| {
+            let rank = percentile / 100.0 * (n - 1.0);
+            let (lower, upper) = (values[rank.floor() as usize], values[rank.ceil() as usize]);
+            Percentile {
+                percentile,
+                value: lower + (upper - lower) * rank.fract(),
+            }
+        })
+        .collect();
+
+    if options.bins > 0 {
+        let bins = options.bins;
+        let width = (max - min) / bins as f64;
+        let mut edges: Vec<f64> = (0..bins).map(|i| min + width * i as f64).collect();
+        edges.push(max);
+        let mut counts = vec![0; bins];
+        for v in &values {
+            // The last bin includes max
+            let bin = if width > 0.0 {
+                (((v - min) / width) as usize).min(bins - 1)
+            } else {
+                0
+            };
+            counts[bin] += 1;
+        }
+        stats.histogram = Some(Histogram { edges, counts });
+    }
+
+    stats.min = Some(min);
+    stats.max = Some(max);
+    stats.mean = Some(mean);
+    stats.stddev = Some(variance.sqrt());
+    stats.sum = Some(sum);
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::cog::tests::test_raster;
+
+    fn options(bins: usize) -> ZonalOptions {
+        ZonalOptions {
+            band: 1,
+            percentiles: vec![0.0, 25.0, 50.0, 100.0],
+            bins,
+            terrain: None,
+        }
+    }
+
+    #[test]
+    fn test_summarise() {
+        let stats = summarise(1, vec![4.0, 1.0, 3.0, 2.0], 2, &options(3));
+        assert_eq!((stats.count, stats.nodata_count), (4, 2));
+        assert_eq!((stats.min, stats.max), (Some(1.0), Some(4.0)));
+        assert_eq!((stats.sum, stats.mean), (Some(10.0), Some(2.5)));
+        assert!((stats.stddev.unwrap() - 1.25f64.sqrt()).abs() < 1e-12);
+        let percentiles: Vec<f64> = stats.percentiles.iter().map(|p| p.value).collect();
+        assert_eq!(percentiles, vec![1.0, 1.75, 2.5, 4.0]);
+        let histogram = stats.histogram.unwrap();
+        assert_eq!(histogram.edges, vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(histogram.counts, vec![1, 1, 2]);
+
+        let flat = summarise(1, vec![5.0; 3], 0, &options(2));
+        assert_eq!(flat.histogram.unwrap().counts, vec![3, 0]);
+
+        let empty = summarise(1, vec![], 9, &options(2));
+        assert_eq!((empty.count, empty.nodata_count), (0, 9));
+        assert!(empty.mean.is_none() && empty.histogram.is_none());
+        assert!(empty.percentiles.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_zonal_stats() {
+        // 4×4 pixels of 10 m in EPSG:3857 from (0, 40), values 0..16, 5 is nodata
+        let (_tmp, layer) = test_raster(
+            (4, 4),
+            [0.0, 10.0, 0.0, 40.0, 0.0, -10.0],
+            (0..16).map(|v| v as f32).collect(),
+            Some(5.0),
+        );
+
+        // The top left 2×2 pixels (0, 1, 4, 5) plus a sliver of the row below
+        // that misses its pixel centres
+        let square = vec![vec![vec![
+            (0.0, 40.0),
+            (20.0, 40.0),
+            (20.0, 18.0),
+            (0.0, 18.0),
+            (0.0, 40.0),
+        ]]];
+        let stats = zonal_stats(layer.clone(), square, 3857, options(0))
+            .await
+            .unwrap();
+        assert_eq!((stats.count, stats.nodata_count), (3, 1));
+        assert_eq!(stats.sum, Some(5.0));
+        assert!(stats.histogram.is_none());
+
+        // Entirely off the raster
+        let away = vec![vec![vec![
+            (100.0, 100.0),
+            (110.0, 100.0),
+            (110.0, 110.0),
+            (100.0, 100.0),
+        ]]];
+        let stats = zonal_stats(layer.clone(), away, 3857, options(0))
+            .await
+            .unwrap();
+        assert_eq!((stats.count, stats.nodata_count), (0, 0));
+
+        // Slope of the top right 2×2, from 13.8% in the corner, which takes
+        // itself for the missing neighbours, to 40.7% at the bottom left
+        let top_right = vec![vec![vec![
+            (20.0, 40.0),
+            (40.0, 40.0),
+            (40.0, 20.0),
+            (20.0, 20.0),
+            (20.0, 40.0),
+        ]]];
+        let mut slope = options(0);
+        slope.terrain = Some(Terrain::SlopePercent);
+        let stats = zonal_stats(layer.clone(), top_right, 3857, slope)
+            .await
+            .unwrap();
+        assert_eq!(stats.terrain, Some(Terrain::SlopePercent));
+        assert_eq!((stats.count, stats.nodata_count), (4, 0));
+        let (min, max) = (stats.min.unwrap(), stats.max.unwrap());
+        assert!((min - 13.81).abs() < 0.1, "{}", min);
+        assert!((max - 40.70).abs() < 0.1, "{}", max);
+
+        let mut bad_band = options(0);
+        bad_band.band = 2;
+        assert!(zonal_stats(layer, vec![], 3857, bad_band).await.is_err());
+    }
+}
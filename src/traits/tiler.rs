@@ -1,15 +1,212 @@
-use crate::models::{layer::Layer, responses::TileResponse};
+use crate::models::{
+    geojson::Polygon,
+    geometry::GeometryExtent,
+    layer::Layer,
+    render::RenderOptions,
+    responses::{FileResponse, LineProfile, PointSample, TileResponse, ZonalStats},
+    tms::TileMatrixSet,
+};
+use crate::reader::{
+    cog::{render_extent, render_preview},
+    contours::{ContourOptions, contour_tile},
+    export::{ExportOptions, export_extent},
+    point::sample_points,
+    profile::line_profile,
+    zonal::{ZonalOptions, zonal_stats},
+};
 use async_trait::async_trait;
 
 #[async_trait]
 pub trait TileReader: Send + Sync {
     async fn list_layers(&self) -> Vec<Layer>;
+    async fn get_layer(&self, layer: &str) -> Option<Layer> {
+        self.list_layers()
+            .await
+            .into_iter()
+            .find(|l| l.layer == layer)
+    }
+    /// Render tile z/x/y (matrix, column, row) of a tile matrix set
+    #[allow(clippy::too_many_arguments)]
     async fn get_tile(
         &self,
         layer: &str,
+        tms: &TileMatrixSet,
         z: u8,
         x: u32,
         y: u32,
         style: Option<&str>,
+        options: &RenderOptions,
     ) -> Result<TileResponse, String>;
+
+    /// Render an arbitrary extent in EPSG:3857 or EPSG:4326 to a PNG
+    async fn get_map(
+        &self,
+        layer: &str,
+        extent: GeometryExtent,
+        crs: i32,
+        size: (usize, usize),
+        options: &RenderOptions,
+    ) -> Result<TileResponse, String> {
+        let layer_obj = self
+            .get_layer(layer)
+            .await
+            .ok_or_else(|| format!("Layer not found: '{}'", layer))?;
+        let options = options.merged_over(&layer_obj.render)?;
+        let content_type = options.format.unwrap_or_default().content_type();
+        let png_data = render_extent(
+            layer_obj.path.clone(),
+            extent,
+            crs,
+            layer_obj,
+            size,
+            options,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(TileResponse {
+            content_type: content_type.into(),
+            bytes: png_data,
+        })
+    }
+
+    /// Render part of a layer in its own CRS (`extent` in source
+    /// coordinates, the whole layer if None) to a PNG, from overviews
+    async fn get_preview(
+        &self,
+        layer: &str,
+        extent: Option<GeometryExtent>,
+        size: (usize, usize),
+        options: &RenderOptions,
+    ) -> Result<TileResponse, String> {
+        let layer_obj = self
+            .get_layer(layer)
+            .await
+            .ok_or_else(|| format!("Layer not found: '{}'", layer))?;
+        let options = options.merged_over(&layer_obj.render)?;
+        let content_type = options.format.unwrap_or_default().content_type();
+        let extent = extent.unwrap_or_else(|| layer_obj.source_geometry.extent.clone());
+        let png_data = render_preview(layer_obj, extent, size, options)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(TileResponse {
+            content_type: content_type.into(),
+            bytes: png_data,
+        })
+    }
+
+    /// Contour lines of tile z/x/y (matrix, column, row) as a Mapbox Vector
+    /// Tile
+    async fn get_contours(
+        &self,
+        layer: &str,
+        tms: &TileMatrixSet,
+        z: u8,
+        x: u32,
+        y: u32,
+        options: ContourOptions,
+    ) -> Result<TileResponse, String> {
+        let layer_obj = self
+            .get_layer(layer)
+            .await
+            .ok_or_else(|| format!("Layer not found: '{}'", layer))?;
+        let (Some(matrix), Some(extent)) = (tms.matrix(z), tms.tile_bounds(z, x, y)) else {
+            return Err(format!("Tile {}/{}/{} is outside {}", z, x, y, tms.id));
+        };
+        let tile_size = (matrix.tile_width as usize, matrix.tile_height as usize);
+        let mvt_data = contour_tile(layer_obj, extent, tms.crs_code, tile_size, options)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(TileResponse {
+            content_type: "application/vnd.mapbox-vector-tile".into(),
+            bytes: mvt_data,
+        })
+    }
+
+    /// The pixel under each point (x/y in `crs`), None outside the raster,
+    /// with the slope and aspect there if `terrain` is set
+    async fn get_points(
+        &self,
+        layer: &str,
+        points: Vec<(f64, f64)>,
+        crs: i32,
+        terrain: bool,
+    ) -> Result<Vec<Option<PointSample>>, String> {
+        let layer_obj = self
+            .get_layer(layer)
+            .await
+            .ok_or_else(|| format!("Layer not found: '{}'", layer))?;
+        sample_points(layer_obj, points, crs, terrain)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// The pixel under one point, None outside the raster
+    async fn get_point(
+        &self,
+        layer: &str,
+        x: f64,
+        y: f64,
+        crs: i32,
+    ) -> Result<Option<PointSample>, String> {
+        let mut samples = self.get_points(layer, vec![(x, y)], crs, false).await?;
+        Ok(samples.pop().flatten())
+    }
+
+    /// Statistics of one band inside polygons given in `crs`
+    async fn get_zonal_stats(
+        &self,
+        layer: &str,
+        polygons: Vec<Polygon>,
+        crs: i32,
+        options: ZonalOptions,
+    ) -> Result<ZonalStats, String> {
+        let layer_obj = self
+            .get_layer(layer)
+            .await
+            .ok_or_else(|| format!("Layer not found: '{}'", layer))?;
+        zonal_stats(layer_obj, polygons, crs, options)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Values of one band along a line given in `crs`, every `spacing` meters
+    async fn get_profile(
+        &self,
+        layer: &str,
+        line: Vec<(f64, f64)>,
+        crs: i32,
+        band: usize,
+        spacing: Option<f64>,
+    ) -> Result<LineProfile, String> {
+        let layer_obj = self
+            .get_layer(layer)
+            .await
+            .ok_or_else(|| format!("Layer not found: '{}'", layer))?;
+        line_profile(layer_obj, line, crs, band, spacing)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Source pixels under a bbox as a GeoTIFF or COG
+    async fn get_export(
+        &self,
+        layer: &str,
+        options: ExportOptions,
+    ) -> Result<FileResponse, String> {
+        let layer_obj = self
+            .get_layer(layer)
+            .await
+            .ok_or_else(|| format!("Layer not found: '{}'", layer))?;
+        let content_type = options.format.content_type().to_string();
+        let file = export_extent(layer_obj, options)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(FileResponse {
+            file: tokio::fs::File::from_std(file),
+            content_type,
+        })
+    }
 }
@@ -0,0 +1,472 @@
+/// A band math expression such as `(b4-b3)/(b4+b3)`, evaluated per pixel.
+///
+/// Supports numbers, band references `b1`…`bN`, `+ - * / ^`, unary minus,
+/// comparisons (`< <= > >= == !=`, giving 1 or 0), parentheses and the
+/// functions `min`, `max`, `abs`, `sqrt` and `log` (natural). Nothing else is
+/// accepted, so the expression can safely come from a query string.
+#[derive(Debug, Clone)]
+pub struct Expression {
+    root: Node,
+    bands: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Number(f32),
+    Band(usize),
+    Neg(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+    Min,
+    Max,
+    Abs,
+    Sqrt,
+    Log,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f32),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+// Parsing and evaluation recurse, so keep what a query string can ask for
+// well inside a worker thread's stack
+const MAX_LENGTH: usize = 1000;
+const MAX_DEPTH: usize = 64;
+
+// Longest first so "<=" wins over "<"
+const OPERATORS: [&str; 11] = ["<=", ">=", "==", "!=", "<", ">", "+", "-", "*", "/", "^"];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // Exponent, e.g. 1e-3
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                let mut j = i + 1;
+                if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                    j += 1;
+                }
+                if j < chars.len() && chars[j].is_ascii_digit() {
+                    i = j;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let value = text
+                .parse()
+                .map_err(|_| format!("Invalid number '{}'", text))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_ascii_alphabetic() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().collect();
+            tokens.push(Token::Ident(ident.to_ascii_lowercase()));
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c == ',' {
+            tokens.push(Token::Comma);
+            i += 1;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("Unexpected character '{}'", c))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    bands: Vec<usize>,
+    depth: usize, // Of parentheses, calls, unary minus and exponents
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Parse one level further in, refusing past MAX_DEPTH
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("Expression nests deeper than {} levels", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let node = parse(self);
+        self.depth -= 1;
+        node
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(t) if t == expected => Ok(()),
+            Some(t) => Err(format!("Expected {:?}, found {:?}", expected, t)),
+            None => Err(format!("Expected {:?}, found end of expression", expected)),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        let left = self.additive()?;
+        let op = match self.peek() {
+            Some(Token::Op("<")) => BinaryOp::Lt,
+            Some(Token::Op("<=")) => BinaryOp::Le,
+            Some(Token::Op(">")) => BinaryOp::Gt,
+            Some(Token::Op(">=")) => BinaryOp::Ge,
+            Some(Token::Op("==")) => BinaryOp::Eq,
+            Some(Token::Op("!=")) => BinaryOp::Ne,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.additive()?;
+        Ok(Node::Binary(op, Box::new(left), Box::new(right)))
+    }
+
+    fn additive(&mut self) -> Result<Node, String> {
+        let mut node = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op("+")) => BinaryOp::Add,
+                Some(Token::Op("-")) => BinaryOp::Sub,
+                _ => return Ok(node),
+            };
+            self.pos += 1;
+            node = Node::Binary(op, Box::new(node), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op("*")) => BinaryOp::Mul,
+                Some(Token::Op("/")) => BinaryOp::Div,
+                _ => return Ok(node),
+            };
+            self.pos += 1;
+            node = Node::Binary(op, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.peek() == Some(&Token::Op("-")) {
+            self.pos += 1;
+            return Ok(Node::Neg(Box::new(self.nested(Self::unary)?)));
+        }
+        self.power()
+    }
+
+    // Right associative and binds tighter than unary minus: -2^2 == -4
+    fn power(&mut self) -> Result<Node, String> {
+        let base = self.primary()?;
+        if self.peek() == Some(&Token::Op("^")) {
+            self.pos += 1;
+            let exponent = self.nested(Self::unary)?;
+            return Ok(Node::Binary(
+                BinaryOp::Pow,
+                Box::new(base),
+                Box::new(exponent),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Number(v)) => Ok(Node::Number(v)),
+            Some(Token::LParen) => {
+                let node = self.nested(Self::comparison)?;
+                self.expect(Token::RParen)?;
+                Ok(node)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek() == Some(&Token::LParen) {
+                    return self.call(&name);
+                }
+                let band = name
+                    .strip_prefix('b')
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .filter(|&b| b > 0)
+                    .ok_or_else(|| format!("Unknown name '{}', bands are b1, b2, …", name))?;
+                if !self.bands.contains(&band) {
+                    self.bands.push(band);
+                }
+                Ok(Node::Band(band))
+            }
+            Some(t) => Err(format!("Unexpected {:?}", t)),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+
+    fn call(&mut self, name: &str) -> Result<Node, String> {
+        let (function, arity) = match name {
+            "min" => (Function::Min, None),
+            "max" => (Function::Max, None),
+            "abs" => (Function::Abs, Some(1)),
+            "sqrt" => (Function::Sqrt, Some(1)),
+            "log" => (Function::Log, Some(1)),
+            _ => return Err(format!("Unknown function '{}'", name)),
+        };
+
+        self.expect(Token::LParen)?;
+        let mut args = vec![self.nested(Self::comparison)?];
+        while self.peek() == Some(&Token::Comma) {
+            self.pos += 1;
+            args.push(self.nested(Self::comparison)?);
+        }
+        self.expect(Token::RParen)?;
+
+        match arity {
+            Some(n) if args.len() != n => Err(format!(
+                "{}() takes {} argument(s), got {}",
+                name,
+                n,
+                args.len()
+            )),
+            None if args.len() < 2 => Err(format!("{}() needs at least 2 arguments", name)),
+            _ => Ok(Node::Call(function, args)),
+        }
+    }
+}
+
+impl Node {
+    fn eval(&self, band_value: &impl Fn(usize) -> f32) -> f32 {
+        match self {
+            Node::Number(v) => *v,
+            Node::Band(b) => band_value(*b),
+            Node::Neg(n) => -n.eval(band_value),
+            Node::Binary(op, l, r) => {
+                let (l, r) = (l.eval(band_value), r.eval(band_value));
+                let truth = |t: bool| if t { 1.0 } else { 0.0 };
+                match op {
+                    BinaryOp::Add => l + r,
+                    BinaryOp::Sub => l - r,
+                    BinaryOp::Mul => l * r,
+                    BinaryOp::Div => l / r,
+                    BinaryOp::Pow => l.powf(r),
+                    BinaryOp::Lt => truth(l < r),
+                    BinaryOp::Le => truth(l <= r),
+                    BinaryOp::Gt => truth(l > r),
+                    BinaryOp::Ge => truth(l >= r),
+                    BinaryOp::Eq => truth(l == r),
+                    BinaryOp::Ne => truth(l != r),
+                }
+            }
+            Node::Call(function, args) => {
+                let mut values = args.iter().map(|a| a.eval(band_value));
+                match function {
+                    Function::Min => values.fold(f32::INFINITY, f32::min),
+                    Function::Max => values.fold(f32::NEG_INFINITY, f32::max),
+                    Function::Abs => values.next().unwrap_or(f32::NAN).abs(),
+                    Function::Sqrt => values.next().unwrap_or(f32::NAN).sqrt(),
+                    Function::Log => values.next().unwrap_or(f32::NAN).ln(),
+                }
+            }
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        if input.len() > MAX_LENGTH {
+            return Err(format!(
+                "Expression is longer than {} characters",
+                MAX_LENGTH
+            ));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+            bands: Vec::new(),
+            depth: 0,
+        };
+        let root = parser.comparison()?;
+        if let Some(t) = parser.peek() {
+            return Err(format!("Unexpected {:?} after the expression", t));
+        }
+        if parser.bands.is_empty() {
+            return Err("Expression does not use any band".to_string());
+        }
+        let mut bands = parser.bands;
+        bands.sort_unstable();
+        Ok(Expression { root, bands })
+    }
+
+    /// The 1-based bands the expression reads, sorted
+    pub fn bands(&self) -> &[usize] {
+        &self.bands
+    }
+
+    /// Evaluate one pixel. A NaN (nodata) in any band used makes the result
+    /// nodata, as does a non-finite result such as log(0) or x/0.
+    pub fn eval(&self, band_value: impl Fn(usize) -> f32) -> f32 {
+        if self.bands.iter().any(|&b| band_value(b).is_nan()) {
+            return f32::NAN;
+        }
+        let value = self.root.eval(&band_value);
+        if value.is_finite() { value } else { f32::NAN }
+    }
+
+    /// Evaluate every pixel of buffers laid out as `self.bands()`
+    pub fn eval_buffers(&self, buffers: &[Vec<f32>]) -> Vec<f32> {
+        let len = buffers.first().map(|b| b.len()).unwrap_or(0);
+        (0..len)
+            .map(|i| {
+                self.eval(|band| {
+                    let idx = self.bands.binary_search(&band).unwrap();
+                    buffers[idx][i]
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expr: &str, bands: &[f32]) -> f32 {
+        Expression::parse(expr).unwrap().eval(|b| bands[b - 1])
+    }
+
+    #[test]
+    fn test_ndvi() {
+        let expr = Expression::parse("(b4-b3)/(b4+b3)").unwrap();
+        assert_eq!(expr.bands(), &[3, 4]);
+        let ndvi = eval("(b4-b3)/(b4+b3)", &[0.0, 0.0, 0.1, 0.5]);
+        assert!((ndvi - 0.6667).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_precedence_and_functions() {
+        assert_eq!(eval("b1 + 2 * 3", &[1.0]), 7.0);
+        assert_eq!(eval("(b1 + 2) * 3", &[1.0]), 9.0);
+        assert_eq!(eval("-2^2 + b1", &[0.0]), -4.0);
+        assert_eq!(eval("2^3^2 * b1", &[1.0]), 512.0);
+        assert_eq!(eval("min(b1, 3, b2)", &[5.0, 4.0]), 3.0);
+        assert_eq!(eval("max(b1, b2)", &[5.0, 4.0]), 5.0);
+        assert_eq!(eval("abs(b1) + sqrt(16)", &[-2.0]), 6.0);
+        assert!((eval("log(b1)", &[std::f32::consts::E]) - 1.0).abs() < 1e-6);
+        assert_eq!(eval("1.5e1 * B1", &[2.0]), 30.0);
+    }
+
+    #[test]
+    fn test_nesting_limits() {
+        let nested = |depth: usize| format!("{}b1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(MAX_DEPTH), &[2.0]), 2.0);
+        assert!(Expression::parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(Expression::parse(&format!("{}b1", "-".repeat(MAX_DEPTH + 1))).is_err());
+        assert!(Expression::parse(&format!("{}b1", "2^".repeat(MAX_DEPTH + 1))).is_err());
+
+        // Far too deep for the stack if it were parsed
+        assert!(Expression::parse(&nested(50_000)).is_err());
+        assert!(Expression::parse(&format!("{}b1", "-".repeat(100_000))).is_err());
+        assert!(Expression::parse(&"b1+".repeat(20_000)).is_err());
+
+        // Long sums are fine up to the length limit
+        let sum = format!("{}b1", "b1+".repeat(300));
+        assert_eq!(eval(&sum, &[1.0]), 301.0);
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(eval("b1 > 2", &[3.0]), 1.0);
+        assert_eq!(eval("b1 <= 2", &[3.0]), 0.0);
+        assert_eq!(eval("(b1 >= 3) * b2", &[3.0, 7.0]), 7.0);
+        assert_eq!(eval("b1 == b2", &[3.0, 3.0]), 1.0);
+        assert_eq!(eval("b1 != b2", &[3.0, 3.0]), 0.0);
+    }
+
+    #[test]
+    fn test_nodata_propagation() {
+        assert!(eval("b1 * 0 + b2", &[f32::NAN, 1.0]).is_nan());
+        assert!(eval("b1 > 0", &[f32::NAN]).is_nan());
+        assert!(eval("b1 / 0", &[1.0]).is_nan());
+        assert!(eval("log(b1)", &[0.0]).is_nan());
+    }
+
+    #[test]
+    fn test_eval_buffers() {
+        let expr = Expression::parse("b3 - b1").unwrap();
+        let result = expr.eval_buffers(&[vec![1.0, 2.0], vec![10.0, f32::NAN]]);
+        assert_eq!(result[0], 9.0);
+        assert!(result[1].is_nan());
+    }
+
+    #[test]
+    fn test_rejects_invalid() {
+        for bad in [
+            "",
+            "1 + 2",
+            "b0 + 1",
+            "b1 +",
+            "(b1",
+            "b1)",
+            "foo(b1)",
+            "abs(b1, b2)",
+            "min(b1)",
+            "x + b1",
+            "b1; drop",
+            "b1 % 2",
+        ] {
+            assert!(Expression::parse(bad).is_err(), "{} should not parse", bad);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+/// 64-bit FNV-1a, stable across builds and restarts unlike DefaultHasher
+pub fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for byte in part.iter() {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        // Separator so ("ab", "c") and ("a", "bc") differ
+        hash ^= 0xff;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
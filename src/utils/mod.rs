@@ -1,2 +1,7 @@
+pub mod expression;
+pub mod hash;
+pub mod mvt;
+pub mod npy;
 pub mod status;
 pub mod style;
+pub mod xml;
@@ -0,0 +1,232 @@
+//! Just enough of the Mapbox Vector Tile 2.1 protobuf encoding to write line
+//! layers, https://github.com/mapbox/vector-tile-spec
+
+/// Tile coordinates run from 0 to this along each side
+pub const EXTENT: u32 = 4096;
+
+/// A (multi) line feature in tile coordinates with numeric properties
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineFeature {
+    pub lines: Vec<Vec<(i32, i32)>>,
+    pub properties: Vec<(String, f64)>,
+}
+
+/// A tile of one layer of line features, empty if there are none
+pub fn encode_lines(layer_name: &str, features: &[LineFeature]) -> Vec<u8> {
+    if features.is_empty() {
+        return Vec::new();
+    }
+
+    // Keys and values are shared by all features, which refer to them by index
+    let mut keys: Vec<&str> = Vec::new();
+    let mut values: Vec<u64> = Vec::new(); // f64 bits, so they can be compared
+    let mut layer = Vec::new();
+    write_varint_field(&mut layer, 15, 2); // version
+    write_bytes_field(&mut layer, 1, layer_name.as_bytes());
+
+    for (id, feature) in features.iter().enumerate() {
+        let mut tags = Vec::new();
+        for (key, value) in &feature.properties {
+            tags.push(index_of(&mut keys, key.as_str()));
+            tags.push(index_of(&mut values, value.to_bits()));
+        }
+
+        let mut message = Vec::new();
+        write_varint_field(&mut message, 1, id as u64 + 1);
+        write_packed_field(&mut message, 2, &tags);
+        write_varint_field(&mut message, 3, 2); // LINESTRING
+        write_packed_field(&mut message, 4, &line_geometry(&feature.lines));
+        write_bytes_field(&mut layer, 2, &message);
+    }
+
+    for key in keys {
+        write_bytes_field(&mut layer, 3, key.as_bytes());
+    }
+    for value in values {
+        // A Value message holding only double_value (field 3, 64-bit)
+        let mut message = vec![(3 << 3) | 1];
+        message.extend_from_slice(&value.to_le_bytes());
+        write_bytes_field(&mut layer, 4, &message);
+    }
+    write_varint_field(&mut layer, 5, EXTENT as u64);
+
+    let mut tile = Vec::new();
+    write_bytes_field(&mut tile, 3, &layer);
+    tile
+}
+
+/// Split a line into the pieces inside the square `min..=max` on both axes
+pub fn clip_line(line: &[(f64, f64)], min: f64, max: f64) -> Vec<Vec<(f64, f64)>> {
+    let mut pieces = Vec::new();
+    let mut current: Vec<(f64, f64)> = Vec::new();
+    for pair in line.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        let (dx, dy) = (x1 - x0, y1 - y0);
+        let Some((t0, t1)) = clip_segment((x0, y0), (dx, dy), min, max) else {
+            if current.len() > 1 {
+                pieces.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            continue;
+        };
+        if t0 > 0.0 || current.is_empty() {
+            if current.len() > 1 {
+                pieces.push(std::mem::take(&mut current));
+            }
+            current = vec![(x0 + t0 * dx, y0 + t0 * dy)];
+        }
+        current.push((x0 + t1 * dx, y0 + t1 * dy));
+        if t1 < 1.0 {
+            pieces.push(std::mem::take(&mut current));
+        }
+    }
+    if current.len() > 1 {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Liang–Barsky: the part of a segment (start, direction) inside the
+/// square, as a range of 0..=1 along it
+fn clip_segment(
+    (x, y): (f64, f64),
+    (dx, dy): (f64, f64),
+    min: f64,
+    max: f64,
+) -> Option<(f64, f64)> {
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    for (p, q) in [(-dx, x - min), (dx, max - x), (-dy, y - min), (dy, max - y)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    (t0 <= t1).then_some((t0, t1))
+}
+
+/// MoveTo/LineTo commands with zigzag deltas from the previous position
+fn line_geometry(lines: &[Vec<(i32, i32)>]) -> Vec<u64> {
+    let command = |id: u64, count: usize| id | ((count as u64) << 3);
+    let zigzag = |n: i32| ((n << 1) ^ (n >> 31)) as u32 as u64;
+    let mut geometry = Vec::new();
+    let mut cursor = (0, 0);
+    for line in lines.iter().filter(|line| line.len() > 1) {
+        for (i, &(x, y)) in line.iter().enumerate() {
+            match i {
+                0 => geometry.push(command(1, 1)),
+                1 => geometry.push(command(2, line.len() - 1)),
+                _ => {}
+            }
+            geometry.push(zigzag(x - cursor.0));
+            geometry.push(zigzag(y - cursor.1));
+            cursor = (x, y);
+        }
+    }
+    geometry
+}
+
+fn index_of<T: PartialEq>(items: &mut Vec<T>, item: T) -> u64 {
+    let index = items.iter().position(|i| *i == item).unwrap_or_else(|| {
+        items.push(item);
+        items.len() - 1
+    });
+    index as u64
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(buf, field << 3);
+    write_varint(buf, value);
+}
+
+fn write_bytes_field(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(buf, (field << 3) | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_packed_field(buf: &mut Vec<u8>, field: u64, values: &[u64]) {
+    let mut packed = Vec::new();
+    for &value in values {
+        write_varint(&mut packed, value);
+    }
+    write_bytes_field(buf, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_geometry() {
+        // The example from the spec: a line then a second one
+        let lines = vec![vec![(2, 2), (2, 10), (10, 10)], vec![(1, 1), (3, 5)]];
+        assert_eq!(
+            line_geometry(&lines),
+            vec![9, 4, 4, 18, 0, 16, 16, 0, 9, 17, 17, 10, 4, 8]
+        );
+    }
+
+    #[test]
+    fn test_encode_lines() {
+        assert!(encode_lines("contours", &[]).is_empty());
+
+        let feature = LineFeature {
+            lines: vec![vec![(0, 0), (1, 1)]],
+            properties: vec![("elevation".into(), 100.0)],
+        };
+        let tile = encode_lines("contours", &[feature.clone(), feature]);
+        let feature = |id| {
+            [
+                0x12, 16, 0x08, id, 0x12, 2, 0, 0, 0x18, 2, 0x22, 6, 9, 0, 0, 10, 2, 2,
+            ]
+        };
+        let mut layer = vec![0x78, 2, 0x0a, 8];
+        layer.extend_from_slice(b"contours");
+        // Both features use key 0 and value 0
+        layer.extend_from_slice(&feature(1));
+        layer.extend_from_slice(&feature(2));
+        layer.extend_from_slice(b"\x1a\x09elevation");
+        layer.extend_from_slice(&[0x22, 9, 0x19, 0, 0, 0, 0, 0, 0, 0x59, 0x40]);
+        layer.extend_from_slice(&[0x28, 0x80, 0x20]);
+        let mut expected = vec![0x1a, layer.len() as u8];
+        expected.extend(layer);
+        assert_eq!(tile, expected);
+    }
+
+    #[test]
+    fn test_clip_line() {
+        // Crossing the square, leaving it and coming back
+        let line = [
+            (-5.0, 5.0),
+            (5.0, 5.0),
+            (5.0, 15.0),
+            (8.0, 15.0),
+            (8.0, 5.0),
+        ];
+        assert_eq!(
+            clip_line(&line, 0.0, 10.0),
+            vec![
+                vec![(0.0, 5.0), (5.0, 5.0), (5.0, 10.0)],
+                vec![(8.0, 10.0), (8.0, 5.0)],
+            ]
+        );
+
+        // Inside stays as is, outside is dropped
+        let inside = [(1.0, 1.0), (2.0, 2.0), (3.0, 1.0)];
+        assert_eq!(clip_line(&inside, 0.0, 10.0), vec![inside.to_vec()]);
+        assert!(clip_line(&[(-1.0, -1.0), (-1.0, 20.0)], 0.0, 10.0).is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+//! Writes NumPy .npy files, https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html
+
+/// A little-endian float32 array of `shape` in C order. `values` must hold
+/// the product of the shape.
+pub fn encode_f32(shape: &[usize], values: impl IntoIterator<Item = f32>) -> Vec<u8> {
+    let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+    // A one-dimensional shape is a tuple of one, which needs the comma
+    let dims = match dims.len() {
+        1 => format!("{},", dims[0]),
+        _ => dims.join(", "),
+    };
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}), }}",
+        dims
+    );
+    // Magic, version and length take 10 bytes, the data starts 64 aligned
+    let padding = 63 - (10 + header.len()) % 64;
+    header.extend(std::iter::repeat_n(' ', padding));
+    header.push('\n');
+
+    let count: usize = shape.iter().product();
+    let mut npy = Vec::with_capacity(10 + header.len() + count * 4);
+    npy.extend_from_slice(b"\x93NUMPY\x01\x00");
+    npy.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    npy.extend_from_slice(header.as_bytes());
+    for value in values {
+        npy.extend_from_slice(&value.to_le_bytes());
+    }
+    npy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_f32() {
+        let npy = encode_f32(&[2, 1, 2], [1.0, 2.0, f32::NAN, 0.5]);
+        assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&npy[10..10 + header_len]).unwrap();
+        assert!(
+            header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2, 1, 2), }")
+        );
+        assert!(header.ends_with(" \n"));
+
+        let values: Vec<f32> = npy[10 + header_len..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(values.len(), 4);
+        assert_eq!((values[0], values[1], values[3]), (1.0, 2.0, 0.5));
+        assert!(values[2].is_nan());
+
+        let npy = encode_f32(&[3], [0.0; 3]);
+        assert!(String::from_utf8_lossy(&npy).contains("'shape': (3,)"));
+    }
+}
@@ -1,25 +1,51 @@
 use crate::{
-    models::{layer::Layer, style::ColourStop},
+    models::{
+        layer::Layer,
+        render::StopValues,
+        style::{ColourStop, Interpolation},
+    },
     utils::style::{get_builtin_gradient, is_builtin_palette},
 };
 use comfy_table::{Attribute, Cell, CellAlignment, Table};
 use std::collections::HashMap;
 
+struct StyleSummary {
+    count: usize,
+    stops: Vec<ColourStop>,
+    interpolation: Interpolation,
+    min_value: f32,
+    max_value: f32,
+    // Range of the layers whose stops are data values, stretched ones always fit
+    absolute_range: Option<(f32, f32)>,
+    num_cogs: usize,
+}
+
 pub fn print_layer_summary(layers: &Vec<Layer>) {
-    let mut style_info: HashMap<String, (usize, Vec<ColourStop>, f32, f32, usize)> = HashMap::new();
+    let mut style_info: HashMap<String, StyleSummary> = HashMap::new();
     for layer in layers {
-        let entry = style_info.entry(layer.style.clone()).or_insert((
-            0,
-            layer.colour_stops.clone(),
-            layer.min_value,
-            layer.max_value,
-            0,
-        ));
-        entry.0 += 1;
-        entry.1 = layer.colour_stops.clone();
-        entry.2 = entry.2.min(layer.min_value);
-        entry.3 = entry.3.max(layer.max_value);
-        entry.4 += layer.is_cog as usize;
+        let entry = style_info
+            .entry(layer.style.clone())
+            .or_insert(StyleSummary {
+                count: 0,
+                stops: layer.colour_stops.clone(),
+                interpolation: layer.interpolation,
+                min_value: layer.min_value,
+                max_value: layer.max_value,
+                absolute_range: None,
+                num_cogs: 0,
+            });
+        entry.count += 1;
+        entry.stops = layer.colour_stops.clone();
+        entry.interpolation = layer.interpolation;
+        entry.min_value = entry.min_value.min(layer.min_value);
+        entry.max_value = entry.max_value.max(layer.max_value);
+        if layer.render.stops.unwrap_or_default() == StopValues::Absolute {
+            let (min, max) = entry
+                .absolute_range
+                .unwrap_or((layer.min_value, layer.max_value));
+            entry.absolute_range = Some((min.min(layer.min_value), max.max(layer.max_value)));
+        }
+        entry.num_cogs += layer.is_cog as usize;
     }
 
     let mut table = Table::new();
@@ -49,7 +75,16 @@ pub fn print_layer_summary(layers: &Vec<Layer>) {
 
     let mut warnings = Vec::new();
     let mut cog_error_count: usize = 0;
-    for (style, (count, stops, min_v, max_v, num_cogs)) in style_info {
+    for (style, summary) in style_info {
+        let StyleSummary {
+            count,
+            stops,
+            interpolation,
+            min_value: min_v,
+            max_value: max_v,
+            absolute_range,
+            num_cogs,
+        } = summary;
         let breaks_str = if is_builtin_palette(&style) || stops.is_empty() {
             "auto".to_string()
         } else {
@@ -99,13 +134,23 @@ pub fn print_layer_summary(layers: &Vec<Layer>) {
             Cell::new(bar),
         ];
 
-        if !stops.is_empty() {
+        // Only stops holding data values can miss part of the data. Exact
+        // styles match classes, so a range says nothing about them.
+        if let Some((abs_min, abs_max)) =
+            absolute_range.filter(|_| !stops.is_empty() && interpolation != Interpolation::Exact)
+        {
             let style_min = stops.first().unwrap().value;
             let style_max = stops.last().unwrap().value;
-            if min_v < style_min || max_v > style_max {
+            let outside = match interpolation {
+                Interpolation::Discrete => "are transparent",
+                _ => "get the end colours",
+            };
+            // Discrete stops colour everything below the first one
+            let below = interpolation != Interpolation::Discrete && abs_min < style_min;
+            if below || abs_max > style_max {
                 warnings.push(format!(
-                    "  ⚠️{}: Colour stops [{:.2}…{:.2}] do NOT cover data range [{:.2}…{:.2}]",
-                    style_str, style_min, style_max, min_v, max_v
+                    "  ⚠️{}: Colour stops [{:.2}…{:.2}] do NOT cover data range [{:.2}…{:.2}], values outside {}",
+                    style_str, style_min, style_max, abs_min, abs_max, outside
                 ));
                 style_row[0] = Cell::new("⚠️");
             }
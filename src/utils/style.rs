@@ -1,15 +1,30 @@
-use crate::models::style::ColourStop;
+use crate::models::style::{ColourMap, ColourStop, Interpolation};
 use colorgrad::{Gradient, preset};
 use std::fs;
 use std::path::Path;
 
-pub fn parse_style_file<P: AsRef<Path>>(path: P) -> Result<Vec<ColourStop>, String> {
+pub fn parse_style_file<P: AsRef<Path>>(path: P) -> Result<ColourMap, String> {
     let content =
         fs::read_to_string(path).map_err(|e| format!("Failed to read style.txt: {}", e))?;
+    parse_style_str(&content)
+}
+
+/// Parse the contents of a QGIS colour map export
+pub fn parse_style_str(content: &str) -> Result<ColourMap, String> {
     let mut stops = Vec::new();
+    let mut interpolation = Interpolation::default();
 
     for line in content.lines() {
-        if line.starts_with('#') || line.starts_with("INTERPOLATION") || line.trim().is_empty() {
+        if let Some(mode) = line.strip_prefix("INTERPOLATION:") {
+            interpolation = match mode.trim() {
+                "INTERPOLATED" => Interpolation::Interpolated,
+                "DISCRETE" => Interpolation::Discrete,
+                "EXACT" => Interpolation::Exact,
+                other => return Err(format!("Unknown interpolation: {}", other)),
+            };
+            continue;
+        }
+        if line.starts_with('#') || line.trim().is_empty() {
             continue;
         }
         let parts: Vec<&str> = line.split(',').collect();
@@ -32,6 +47,8 @@ pub fn parse_style_file<P: AsRef<Path>>(path: P) -> Result<Vec<ColourStop>, Stri
         let alpha = parts[4]
             .parse()
             .map_err(|e| format!("Invalid alpha: {}", e))?;
+        // Labels are free text and may contain commas themselves
+        let label = Some(parts[5..].join(",").trim().to_string()).filter(|l| !l.is_empty());
 
         stops.push(ColourStop {
             value,
@@ -39,10 +56,52 @@ pub fn parse_style_file<P: AsRef<Path>>(path: P) -> Result<Vec<ColourStop>, Stri
             green,
             blue,
             alpha,
+            label,
         });
     }
 
-    Ok(stops)
+    // The renderer walks the stops in order
+    stops.sort_by(|a, b| a.value.total_cmp(&b.value));
+
+    Ok(ColourMap {
+        interpolation,
+        stops,
+    })
+}
+
+/// The colour for a value given stops sorted by value, or None if the mode
+/// leaves it uncoloured
+pub fn colour_at(
+    stops: &[ColourStop],
+    interpolation: Interpolation,
+    value: f32,
+) -> Option<[u8; 4]> {
+    let rgba = |s: &ColourStop| [s.red, s.green, s.blue, s.alpha];
+    match interpolation {
+        Interpolation::Interpolated => stops.windows(2).find_map(|w| {
+            let (a, b) = (&w[0], &w[1]);
+            if value < a.value || value > b.value {
+                return None;
+            }
+            let t = if b.value > a.value {
+                (value - a.value) / (b.value - a.value)
+            } else {
+                0.0
+            };
+            let mix = |a: u8, b: u8| ((1.0 - t) * a as f32 + t * b as f32) as u8;
+            Some([
+                mix(a.red, b.red),
+                mix(a.green, b.green),
+                mix(a.blue, b.blue),
+                mix(a.alpha, b.alpha),
+            ])
+        }),
+        Interpolation::Discrete => stops.iter().find(|s| value <= s.value).map(rgba),
+        Interpolation::Exact => stops
+            .iter()
+            .find(|s| (value - s.value).abs() <= f32::EPSILON * value.abs().max(1.0))
+            .map(rgba),
+    }
 }
 
 pub fn is_builtin_palette(name: &str) -> bool {
@@ -63,3 +122,52 @@ pub fn get_builtin_gradient(name: &str) -> Option<Box<dyn Gradient>> {
         _ => return None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STYLE: &str = "# QGIS Generated Colour Map Export File
+INTERPOLATION:DISCRETE
+10,255,0,0,255,Water
+20,0,255,0,255,Forest, mixed
+30,0,0,255,255
+";
+
+    #[test]
+    fn test_parse_style_str() {
+        let map = parse_style_str(STYLE).unwrap();
+        assert_eq!(map.interpolation, Interpolation::Discrete);
+        assert_eq!(map.stops.len(), 3);
+        assert_eq!(map.stops[0].label.as_deref(), Some("Water"));
+        assert_eq!(map.stops[1].label.as_deref(), Some("Forest, mixed"));
+        assert_eq!(map.stops[2].label, None);
+
+        let exact = STYLE.replace("DISCRETE", "EXACT");
+        assert_eq!(
+            parse_style_str(&exact).unwrap().interpolation,
+            Interpolation::Exact
+        );
+        assert!(parse_style_str(&STYLE.replace("DISCRETE", "SMOOTH")).is_err());
+    }
+
+    #[test]
+    fn test_colour_at() {
+        let stops = parse_style_str(STYLE).unwrap().stops;
+
+        let interpolated = |v| colour_at(&stops, Interpolation::Interpolated, v);
+        assert_eq!(interpolated(10.0), Some([255, 0, 0, 255]));
+        assert_eq!(interpolated(15.0), Some([127, 127, 0, 255]));
+        assert_eq!(interpolated(5.0), None);
+
+        let discrete = |v| colour_at(&stops, Interpolation::Discrete, v);
+        assert_eq!(discrete(5.0), Some([255, 0, 0, 255]));
+        assert_eq!(discrete(10.0), Some([255, 0, 0, 255]));
+        assert_eq!(discrete(10.5), Some([0, 255, 0, 255]));
+        assert_eq!(discrete(31.0), None);
+
+        let exact = |v| colour_at(&stops, Interpolation::Exact, v);
+        assert_eq!(exact(20.0), Some([0, 255, 0, 255]));
+        assert_eq!(exact(20.5), None);
+    }
+}